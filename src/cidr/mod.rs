@@ -0,0 +1,131 @@
+//! IP Address Ranges
+//!
+//! CIDR notation (RFC 4632, RFC 4291 2.3) for IPv4 and IPv6 networks, e.g. `10.0.0.0/8` or
+//! `fd00::/8`. A bare address is a network of that single address. IPv4-mapped IPv6 addresses
+//! (`::ffff:10.0.0.1`), as seen on dual-stack sockets, match IPv4 networks.
+
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use super::*;
+
+/// An IPv4 or IPv6 network
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Whether the address is within this network
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.network, addr.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                mask_v4(u32::from(addr), self.prefix_len) == u32::from(network)
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                mask_v6(u128::from(addr), self.prefix_len) == u128::from(network)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self> {
+        let invalid = || format!("invalid network: '{value}' (expected e.g. '10.0.0.0/8')");
+        let (addr, prefix_len) = match value.trim().split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (value.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().map_err(|_| invalid())?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(invalid().into());
+        }
+        // Host bits are ignored, so that e.g. '10.1.2.3/8' means '10.0.0.0/8'
+        let network = match addr {
+            IpAddr::V4(addr) => IpAddr::V4(mask_v4(u32::from(addr), prefix_len).into()),
+            IpAddr::V6(addr) => IpAddr::V6(mask_v6(u128::from(addr), prefix_len).into()),
+        };
+        Ok(Cidr {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+// Helper function that clears all but the leading `prefix_len` bits of an IPv4 address
+fn mask_v4(bits: u32, prefix_len: u8) -> u32 {
+    bits & u32::MAX
+        .checked_shl(32 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+// Helper function that clears all but the leading `prefix_len` bits of an IPv6 address
+fn mask_v6(bits: u128, prefix_len: u8) -> u128 {
+    bits & u128::MAX
+        .checked_shl(128 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(value: &str) -> Cidr {
+        value.parse().unwrap()
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn ipv4() {
+        let private = cidr("10.0.0.0/8");
+        assert!(private.contains(ip("10.1.2.3")));
+        assert!(!private.contains(ip("11.0.0.1")));
+        assert!(private.contains(ip("::ffff:10.0.0.1")));
+        assert!(!private.contains(ip("::1")));
+        assert!(cidr("0.0.0.0/0").contains(ip("192.0.2.1")));
+        assert!(cidr("192.0.2.7").contains(ip("192.0.2.7")));
+        assert!(!cidr("192.0.2.7").contains(ip("192.0.2.8")));
+        assert_eq!("10.0.0.0/8", cidr("10.1.2.3/8").to_string());
+    }
+
+    #[test]
+    fn ipv6() {
+        let unique_local = cidr("fd00::/8");
+        assert!(unique_local.contains(ip("fd12:3456::1")));
+        assert!(!unique_local.contains(ip("fe80::1")));
+        assert!(!unique_local.contains(ip("10.0.0.1")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+        assert!(cidr("2001:db8::1/128").contains(ip("2001:db8::1")));
+        assert_eq!("2001:db8::/32", cidr("2001:db8:1::/32").to_string());
+    }
+
+    #[test]
+    fn invalid() {
+        for value in [
+            "",
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/x",
+            "example.com/8",
+            "10.0.0/8",
+        ] {
+            assert!(value.parse::<Cidr>().is_err(), "{value}");
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Access List Settings
+//!
+//! Access lists are set per route group in `[access.<group>]` tables, each applying to the requests
+//! whose path starts with the group's prefix. A list's rules allow or deny a network (in CIDR
+//! notation, see [`Cidr`]) or `all` addresses, and are evaluated in order:
+//!
+//! ```toml
+//! [access.admin]
+//! prefix = "/admin/"
+//! rules = ["allow 10.0.0.0/8", "allow ::1", "deny all"]
+//! ```
+
+use super::*;
+
+/// Whether a rule lets requests through
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccessAction {
+    Allow,
+    Deny,
+}
+
+/// An access rule: an action for the requests from a network
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AccessRule {
+    pub action: AccessAction,
+    /// The network the rule applies to; `None` for all addresses, including unknown ones
+    pub network: Option<Cidr>,
+}
+
+impl FromStr for AccessRule {
+    type Err = String;
+    fn from_str(value: &str) -> result::Result<Self, String> {
+        let invalid = || {
+            format!(
+                "invalid access rule: '{value}' (expected e.g. 'allow 10.0.0.0/8' or 'deny all')"
+            )
+        };
+        let (action, network) = value.trim().split_once(' ').ok_or_else(invalid)?;
+        let action = match action.to_ascii_lowercase().as_str() {
+            "allow" => AccessAction::Allow,
+            "deny" => AccessAction::Deny,
+            _ => return Err(invalid()),
+        };
+        let network = match network.trim() {
+            "all" => None,
+            network => Some(network.parse().map_err(|e: crate::Error| e.to_string())?),
+        };
+        Ok(AccessRule { action, network })
+    }
+}
+
+/// The access rules for the requests whose path starts with a prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessList {
+    /// The name of the list's table
+    pub name: String,
+    /// The path prefix the list applies to
+    pub prefix: String,
+    pub rules: Vec<AccessRule>,
+}
+
+/// Access control settings
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AccessLists {
+    /// The route groups' lists, of which the one with the longest matching prefix applies to a
+    /// request
+    pub lists: Vec<AccessList>,
+}
+
+impl AccessLists {
+    // Applies a setting from the `access` table, given without its `access.` prefix
+    pub(super) fn apply(&mut self, setting: &str, value: Value) -> result::Result<(), String> {
+        let Some((name, field)) = setting.split_once('.') else {
+            return Err("unknown setting".to_string());
+        };
+        let list = match self.lists.iter().position(|list| list.name == name) {
+            Some(index) => &mut self.lists[index],
+            None => {
+                self.lists.push(AccessList {
+                    name: name.to_string(),
+                    prefix: String::new(),
+                    rules: Vec::new(),
+                });
+                self.lists.last_mut().unwrap()
+            }
+        };
+        match field {
+            "prefix" => list.prefix = string(value)?,
+            "rules" => {
+                list.rules = match value {
+                    Value::Array(rules) => rules
+                        .into_iter()
+                        .map(parsed)
+                        .collect::<result::Result<_, _>>()?,
+                    value => vec![parsed(value)?],
+                }
+            }
+            _ => return Err("unknown setting".to_string()),
+        }
+        Ok(())
+    }
+
+    // Checks that the settings are usable
+    pub(super) fn validate(&self) -> Result<()> {
+        for list in &self.lists {
+            let setting = |field: &str| format!("access.{}.{field}", list.name);
+            if !list.prefix.starts_with('/') {
+                return Err(Error::invalid(
+                    setting("prefix"),
+                    "a path prefix starting with '/' is required",
+                ));
+            }
+            if list.rules.is_empty() {
+                return Err(Error::invalid(
+                    setting("rules"),
+                    "at least one rule is required",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules() {
+        assert_eq!(
+            Ok(AccessRule {
+                action: AccessAction::Allow,
+                network: Some("10.0.0.0/8".parse().unwrap()),
+            }),
+            "allow 10.0.0.0/8".parse()
+        );
+        assert_eq!(
+            Ok(AccessRule {
+                action: AccessAction::Deny,
+                network: None,
+            }),
+            "Deny all".parse()
+        );
+        for bad in ["allow", "permit ::1", "deny 10.0.0.0/33"] {
+            assert!(bad.parse::<AccessRule>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn lists() {
+        let mut access = AccessLists::default();
+        access
+            .apply("admin.prefix", Value::String("/admin/".to_string()))
+            .unwrap();
+        assert!(access.validate().is_err(), "no rules");
+        let rules = ["allow ::1", "deny all"]
+            .iter()
+            .map(|rule| Value::String(rule.to_string()))
+            .collect();
+        access.apply("admin.rules", Value::Array(rules)).unwrap();
+        assert!(access.validate().is_ok());
+        assert_eq!(2, access.lists[0].rules.len());
+        assert!(access.apply("admin", Value::Integer(1)).is_err());
+        assert!(access
+            .apply("admin.rules", Value::String("allow nowhere".to_string()))
+            .is_err());
+    }
+}
@@ -0,0 +1,50 @@
+//! Config module custom errors
+
+use super::*;
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// `--help` was requested
+    Help,
+    /// The command line could not be understood
+    Usage(String),
+    /// The configuration file could not be read
+    Io { path: PathBuf, err: std::io::Error },
+    /// The configuration file is malformed
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// A setting has an unacceptable value
+    Invalid { setting: String, message: String },
+}
+
+impl Error {
+    pub(super) fn invalid(setting: impl Into<String>, message: impl fmt::Display) -> Error {
+        Error::Invalid {
+            setting: setting.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Help => write!(f, "{USAGE}"),
+            Error::Usage(s) => write!(f, "{s}\n\n{USAGE}"),
+            Error::Io { path, err } => write!(f, "{}: {err}", path.display()),
+            Error::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{line}: {message}", path.display()),
+            Error::Invalid { setting, message } => write!(f, "invalid {setting}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
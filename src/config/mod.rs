@@ -0,0 +1,1078 @@
+//! Server configuration
+//!
+//! Settings are layered, with later sources overriding earlier ones:
+//!
+//! 1. built-in defaults
+//! 2. a configuration file in a TOML subset (see [`toml`]), given by `--config` or `PTODD_CONFIG`
+//! 3. environment variables (`PTODD_BIND`, `PTODD_ADMIN_BIND`, `PTODD_SOCKET_MODE`, `PTODD_WORKERS`,
+//!    `PTODD_MIN_WORKERS`, `PTODD_MAX_WORKERS`, `PTODD_WORKER_IDLE_TIMEOUT`, `PTODD_QUEUE_DEPTH`,
+//!    `PTODD_JOB_TIMEOUT`, `PTODD_SOCKET_TIMEOUT`, `PTODD_MAX_REQUEST_LINE`, `PTODD_MAX_HEADER_SIZE`,
+//!    `PTODD_MAX_HEADERS`, `PTODD_HEADER_TIMEOUT`, `PTODD_MAX_BODY_SIZE`, `PTODD_MAX_CONNECTIONS`,
+//!    `PTODD_MAX_CONNECTIONS_PER_IP`, `PTODD_IO_ENGINE`, `PTODD_ROOT`, `PTODD_ERROR_PAGES`,
+//!    `PTODD_ADMIN_TOKEN`, `PTODD_TRACE`,
+//!    `PTODD_SHUTDOWN_TIMEOUT`, `PTODD_PROXY_PROTOCOL`, `PTODD_FORWARDED_HEADERS`,
+//!    `PTODD_TRUSTED_PROXIES`, `PTODD_RATE_LIMIT_KEY`, `PTODD_RATE_LIMIT_MAX_CLIENTS`, `RUST_LOG`,
+//!    `LOG_FORMAT`, `LOG_MODE`, `LOG_QUEUE_CAPACITY` and `LOG_OVERFLOW`)
+//! 4. command-line flags
+//!
+//! Values are validated once all sources have been applied.
+
+use std::{
+    env, fmt, fs,
+    net::ToSocketAddrs,
+    path::{Path, PathBuf},
+    result,
+    str::FromStr,
+    time::Duration,
+};
+
+use log::LevelFilter;
+
+#[cfg(test)]
+pub use access::AccessList;
+pub use access::{AccessAction, AccessLists};
+pub use error::{Error, Result};
+#[cfg(test)]
+pub use rate_limit::RateGroup;
+pub use rate_limit::{RateKey, RateLimits};
+use toml::Value;
+
+use crate::{
+    cidr::Cidr,
+    logger::{Levels, LoggerConfig},
+};
+
+mod access;
+mod error;
+mod rate_limit;
+mod toml;
+
+/// Default address to bind the server to
+const DEFAULT_BIND: &str = "localhost:6502";
+
+/// Default number of worker threads kept running when idle
+const DEFAULT_MIN_WORKERS: usize = 4;
+
+/// Default number of worker threads the pool may grow to under load
+const DEFAULT_MAX_WORKERS: usize = 32;
+
+/// Default time a worker thread above the minimum may sit idle before it exits
+const DEFAULT_WORKER_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Default number of connections that may wait for a worker thread
+const DEFAULT_QUEUE_DEPTH: usize = 128;
+
+/// Default time a request may take before it is cancelled
+const DEFAULT_JOB_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default time a read from or write to a client may block
+const DEFAULT_SOCKET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default longest request line accepted, in bytes
+const DEFAULT_MAX_REQUEST_LINE: usize = 8192;
+
+/// Default largest total size of a request's header fields, in bytes
+const DEFAULT_MAX_HEADER_SIZE: usize = 32768;
+
+/// Default most header fields accepted in a request
+const DEFAULT_MAX_HEADERS: usize = 100;
+
+/// Default time a client may take to send a request's head
+const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default largest request body accepted, in bytes
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Default most connections open at once
+const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// Default most connections open at once from one address
+const DEFAULT_MAX_CONNECTIONS_PER_IP: usize = 64;
+
+/// Default time in-flight requests are given to finish when shutting down
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest number of worker threads accepted
+const MAX_WORKERS: usize = 1024;
+
+/// Prefix marking a bind address as the path of a Unix domain socket (`unix:/run/ptodd.sock`)
+pub const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// Configuration file environment variable name
+const CONFIG_ENV_VAR_NAME: &str = "PTODD_CONFIG";
+
+/// Bind addresses (comma separated) environment variable name
+const BIND_ENV_VAR_NAME: &str = "PTODD_BIND";
+
+/// Admin bind address environment variable name
+const ADMIN_BIND_ENV_VAR_NAME: &str = "PTODD_ADMIN_BIND";
+
+/// Unix domain socket permissions (octal) environment variable name
+const SOCKET_MODE_ENV_VAR_NAME: &str = "PTODD_SOCKET_MODE";
+
+/// Fixed worker count (both minimum and maximum) environment variable name
+const WORKERS_ENV_VAR_NAME: &str = "PTODD_WORKERS";
+
+/// Minimum worker count environment variable name
+const MIN_WORKERS_ENV_VAR_NAME: &str = "PTODD_MIN_WORKERS";
+
+/// Maximum worker count environment variable name
+const MAX_WORKERS_ENV_VAR_NAME: &str = "PTODD_MAX_WORKERS";
+
+/// Worker idle timeout (in seconds) environment variable name
+const WORKER_IDLE_TIMEOUT_ENV_VAR_NAME: &str = "PTODD_WORKER_IDLE_TIMEOUT";
+
+/// Job queue depth environment variable name
+const QUEUE_DEPTH_ENV_VAR_NAME: &str = "PTODD_QUEUE_DEPTH";
+
+/// Job timeout (in seconds) environment variable name
+const JOB_TIMEOUT_ENV_VAR_NAME: &str = "PTODD_JOB_TIMEOUT";
+
+/// Socket timeout (in seconds) environment variable name
+const SOCKET_TIMEOUT_ENV_VAR_NAME: &str = "PTODD_SOCKET_TIMEOUT";
+
+/// Request line limit (in bytes) environment variable name
+const MAX_REQUEST_LINE_ENV_VAR_NAME: &str = "PTODD_MAX_REQUEST_LINE";
+
+/// Header size limit (in bytes) environment variable name
+const MAX_HEADER_SIZE_ENV_VAR_NAME: &str = "PTODD_MAX_HEADER_SIZE";
+
+/// Header count limit environment variable name
+const MAX_HEADERS_ENV_VAR_NAME: &str = "PTODD_MAX_HEADERS";
+
+/// Header timeout (in seconds) environment variable name
+const HEADER_TIMEOUT_ENV_VAR_NAME: &str = "PTODD_HEADER_TIMEOUT";
+
+/// Body size limit (in bytes) environment variable name
+const MAX_BODY_SIZE_ENV_VAR_NAME: &str = "PTODD_MAX_BODY_SIZE";
+
+/// Connection cap environment variable name
+const MAX_CONNECTIONS_ENV_VAR_NAME: &str = "PTODD_MAX_CONNECTIONS";
+
+/// Per-address connection cap environment variable name
+const MAX_CONNECTIONS_PER_IP_ENV_VAR_NAME: &str = "PTODD_MAX_CONNECTIONS_PER_IP";
+
+/// I/O engine environment variable name
+const IO_ENGINE_ENV_VAR_NAME: &str = "PTODD_IO_ENGINE";
+
+/// Document root environment variable name
+const ROOT_ENV_VAR_NAME: &str = "PTODD_ROOT";
+
+/// Error page template directory environment variable name
+const ERROR_PAGES_ENV_VAR_NAME: &str = "PTODD_ERROR_PAGES";
+
+/// Admin bearer token environment variable name (admin routes are disabled when unset)
+const ADMIN_TOKEN_ENV_VAR_NAME: &str = "PTODD_ADMIN_TOKEN";
+
+/// TRACE switch environment variable name
+const TRACE_ENV_VAR_NAME: &str = "PTODD_TRACE";
+
+/// Shutdown timeout (in seconds) environment variable name
+const SHUTDOWN_TIMEOUT_ENV_VAR_NAME: &str = "PTODD_SHUTDOWN_TIMEOUT";
+
+/// PROXY protocol switch environment variable name
+const PROXY_PROTOCOL_ENV_VAR_NAME: &str = "PTODD_PROXY_PROTOCOL";
+
+/// Forwarded headers switch environment variable name
+const FORWARDED_HEADERS_ENV_VAR_NAME: &str = "PTODD_FORWARDED_HEADERS";
+
+/// Trusted proxy networks (comma separated) environment variable name
+const TRUSTED_PROXIES_ENV_VAR_NAME: &str = "PTODD_TRUSTED_PROXIES";
+
+/// Rate limit key environment variable name
+const RATE_LIMIT_KEY_ENV_VAR_NAME: &str = "PTODD_RATE_LIMIT_KEY";
+
+/// Rate limit client count environment variable name
+const RATE_LIMIT_MAX_CLIENTS_ENV_VAR_NAME: &str = "PTODD_RATE_LIMIT_MAX_CLIENTS";
+
+/// Logging level environment variable name
+const LOG_ENV_VAR_NAME: &str = "RUST_LOG";
+
+/// Log output format environment variable name
+const LOG_FORMAT_ENV_VAR_NAME: &str = "LOG_FORMAT";
+
+/// Log write mode (`sync` or `async`) environment variable name
+const LOG_MODE_ENV_VAR_NAME: &str = "LOG_MODE";
+
+/// Asynchronous log queue capacity environment variable name
+const LOG_QUEUE_CAPACITY_ENV_VAR_NAME: &str = "LOG_QUEUE_CAPACITY";
+
+/// Asynchronous log queue overflow policy environment variable name
+const LOG_OVERFLOW_ENV_VAR_NAME: &str = "LOG_OVERFLOW";
+
+/// Command-line usage
+pub const USAGE: &str = "\
+Usage: ptodd [OPTIONS]
+
+Options:
+  -c, --config <FILE>   Read settings from a configuration file [env: PTODD_CONFIG]
+  -b, --bind <ADDR>     Address to listen on; repeat to listen on several [env: PTODD_BIND]
+                        [default: localhost:6502]
+      --admin-bind <ADDR>
+                        Serve the admin routes on their own address instead [env: PTODD_ADMIN_BIND]
+                        Addresses are 'host:port', or 'unix:<PATH>' for a Unix domain socket
+      --socket-mode <MODE>
+                        Permissions for Unix domain sockets, in octal [env: PTODD_SOCKET_MODE]
+  -w, --workers <N>     Fixed number of worker threads, setting both of the following [env: PTODD_WORKERS]
+      --min-workers <N> Worker threads kept when idle [env: PTODD_MIN_WORKERS] [default: 4]
+      --max-workers <N> Worker threads the pool may grow to [env: PTODD_MAX_WORKERS] [default: 32]
+      --io-engine <ENGINE>
+                        How connections are handled: 'threaded' or 'epoll' (Linux only)
+                        [env: PTODD_IO_ENGINE] [default: threaded]
+  -r, --root <DIR>      Directory containing the site's pages [env: PTODD_ROOT] [default: .]
+  -h, --help            Print this help";
+
+/// How the server waits for requests on the connections it accepts
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum IoEngine {
+    /// Each connection is handed to a worker thread as soon as it is accepted
+    #[default]
+    Threaded,
+    /// Connections wait in an `epoll` set, costing no thread, until their request arrives (Linux)
+    Epoll,
+}
+
+impl FromStr for IoEngine {
+    type Err = String;
+    fn from_str(value: &str) -> result::Result<Self, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "threaded" => Ok(IoEngine::Threaded),
+            "epoll" if cfg!(target_os = "linux") => Ok(IoEngine::Epoll),
+            "epoll" => Err("the 'epoll' engine is only available on Linux".to_string()),
+            _ => Err(format!(
+                "invalid I/O engine: '{value}' (expected 'threaded' or 'epoll')"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for IoEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IoEngine::Threaded => write!(f, "threaded"),
+            IoEngine::Epoll => write!(f, "epoll"),
+        }
+    }
+}
+
+/// Server configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// The addresses to bind the server to
+    pub bind: Vec<String>,
+    /// A separate address for the admin routes, which are otherwise served alongside the site
+    pub admin_bind: Option<String>,
+    /// Permissions given to Unix domain sockets, which otherwise follow the process' umask
+    pub socket_mode: Option<u32>,
+    /// The number of worker threads kept running when idle
+    pub min_workers: usize,
+    /// The number of worker threads the pool may grow to when requests queue up
+    pub max_workers: usize,
+    /// How long a worker thread above the minimum may sit idle before it exits
+    pub worker_idle_timeout: Duration,
+    /// How many connections may wait for a worker thread before more are turned away with a 503
+    pub queue_depth: usize,
+    /// How long a request may take before it is cancelled and reported as overdue
+    pub job_timeout: Duration,
+    /// How long a read from or write to a client may block before the connection is dropped
+    pub socket_timeout: Duration,
+    /// The longest request line accepted, in bytes; longer ones get a 414
+    pub max_request_line: usize,
+    /// The largest total size of a request's header fields, in bytes; larger ones get a 431
+    pub max_header_size: usize,
+    /// The most header fields accepted in a request; more get a 431
+    pub max_headers: usize,
+    /// How long a client may take to send a request's head before getting a 408
+    pub header_timeout: Duration,
+    /// The largest request body accepted, in bytes; larger ones get a 413
+    pub max_body_size: usize,
+    /// The most connections open at once; more are turned away with a 503
+    pub max_connections: usize,
+    /// The most connections open at once from one address, other than a trusted proxy's; more are
+    /// turned away with a 503
+    pub max_connections_per_ip: usize,
+    /// How connections wait for their requests
+    pub io_engine: IoEngine,
+    /// The directory containing the site's pages
+    pub root: PathBuf,
+    /// The directory containing the error page templates, if not the root
+    pub error_pages: Option<PathBuf>,
+    /// The bearer token protecting the admin routes, which are disabled without one
+    pub admin_token: Option<String>,
+    /// Whether TRACE requests are echoed back; otherwise they get a 405
+    pub trace: bool,
+    /// How long in-flight requests are given to finish when shutting down
+    pub shutdown_timeout: Duration,
+    /// Whether connections from trusted proxies start with a PROXY protocol header
+    pub proxy_protocol: bool,
+    /// Whether trusted proxies' `Forwarded` and `X-Forwarded-*` headers are believed
+    pub forwarded_headers: bool,
+    /// The networks of the proxies trusted to report the client's address
+    pub trusted_proxies: Vec<Cidr>,
+    /// Per-client rate limits for groups of routes
+    pub rate_limit: RateLimits,
+    /// Which client addresses may use groups of routes
+    pub access: AccessLists,
+    /// Logger settings
+    pub log: LoggerConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: vec![DEFAULT_BIND.to_string()],
+            admin_bind: None,
+            socket_mode: None,
+            min_workers: DEFAULT_MIN_WORKERS,
+            max_workers: DEFAULT_MAX_WORKERS,
+            worker_idle_timeout: DEFAULT_WORKER_IDLE_TIMEOUT,
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+            job_timeout: DEFAULT_JOB_TIMEOUT,
+            socket_timeout: DEFAULT_SOCKET_TIMEOUT,
+            max_request_line: DEFAULT_MAX_REQUEST_LINE,
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_headers: DEFAULT_MAX_HEADERS,
+            header_timeout: DEFAULT_HEADER_TIMEOUT,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            max_connections_per_ip: DEFAULT_MAX_CONNECTIONS_PER_IP,
+            io_engine: IoEngine::default(),
+            root: PathBuf::from("."),
+            error_pages: None,
+            admin_token: None,
+            trace: false,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            proxy_protocol: false,
+            forwarded_headers: false,
+            trusted_proxies: Vec::new(),
+            rate_limit: RateLimits::default(),
+            access: AccessLists::default(),
+            log: LoggerConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from the process' command line, environment and configuration file
+    pub fn load() -> Result<Config> {
+        Config::from_sources(env::args().skip(1), &|name| env::var(name).ok())
+    }
+
+    // Builds a configuration from command-line arguments and an environment variable lookup
+    fn from_sources(
+        args: impl IntoIterator<Item = String>,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Config> {
+        let flags = parse_args(args)?;
+        let mut config = Config::default();
+        let config_file = flags
+            .iter()
+            .rev()
+            .find(|(flag, _)| flag == "config")
+            .map(|(_, value)| value.clone())
+            .or_else(|| env(CONFIG_ENV_VAR_NAME));
+        if let Some(path) = config_file {
+            config.apply_file(Path::new(&path))?;
+        }
+        for (name, setting) in [
+            (BIND_ENV_VAR_NAME, "bind"),
+            (ADMIN_BIND_ENV_VAR_NAME, "admin_bind"),
+            (SOCKET_MODE_ENV_VAR_NAME, "socket_mode"),
+            (WORKERS_ENV_VAR_NAME, "workers"),
+            (MIN_WORKERS_ENV_VAR_NAME, "min_workers"),
+            (MAX_WORKERS_ENV_VAR_NAME, "max_workers"),
+            (WORKER_IDLE_TIMEOUT_ENV_VAR_NAME, "worker_idle_timeout"),
+            (QUEUE_DEPTH_ENV_VAR_NAME, "queue_depth"),
+            (JOB_TIMEOUT_ENV_VAR_NAME, "job_timeout"),
+            (SOCKET_TIMEOUT_ENV_VAR_NAME, "socket_timeout"),
+            (MAX_REQUEST_LINE_ENV_VAR_NAME, "max_request_line"),
+            (MAX_HEADER_SIZE_ENV_VAR_NAME, "max_header_size"),
+            (MAX_HEADERS_ENV_VAR_NAME, "max_headers"),
+            (HEADER_TIMEOUT_ENV_VAR_NAME, "header_timeout"),
+            (MAX_BODY_SIZE_ENV_VAR_NAME, "max_body_size"),
+            (MAX_CONNECTIONS_ENV_VAR_NAME, "max_connections"),
+            (
+                MAX_CONNECTIONS_PER_IP_ENV_VAR_NAME,
+                "max_connections_per_ip",
+            ),
+            (IO_ENGINE_ENV_VAR_NAME, "io_engine"),
+            (ROOT_ENV_VAR_NAME, "root"),
+            (ERROR_PAGES_ENV_VAR_NAME, "error_pages"),
+            (ADMIN_TOKEN_ENV_VAR_NAME, "admin_token"),
+            (TRACE_ENV_VAR_NAME, "trace"),
+            (SHUTDOWN_TIMEOUT_ENV_VAR_NAME, "shutdown_timeout"),
+            (PROXY_PROTOCOL_ENV_VAR_NAME, "proxy_protocol"),
+            (FORWARDED_HEADERS_ENV_VAR_NAME, "forwarded_headers"),
+            (TRUSTED_PROXIES_ENV_VAR_NAME, "trusted_proxies"),
+            (RATE_LIMIT_KEY_ENV_VAR_NAME, "rate_limit.key"),
+            (
+                RATE_LIMIT_MAX_CLIENTS_ENV_VAR_NAME,
+                "rate_limit.max_clients",
+            ),
+            (LOG_ENV_VAR_NAME, "log.level"),
+            (LOG_FORMAT_ENV_VAR_NAME, "log.format"),
+            (LOG_MODE_ENV_VAR_NAME, "log.mode"),
+            (LOG_QUEUE_CAPACITY_ENV_VAR_NAME, "log.queue_capacity"),
+            (LOG_OVERFLOW_ENV_VAR_NAME, "log.overflow"),
+        ] {
+            if let Some(value) = env(name) {
+                config.apply(setting, Value::String(value), name)?;
+            }
+        }
+        let binds: Vec<_> = flags
+            .iter()
+            .filter(|(flag, _)| flag == "bind")
+            .map(|(_, value)| Value::String(value.clone()))
+            .collect();
+        if !binds.is_empty() {
+            config.apply("bind", Value::Array(binds), "--bind")?;
+        }
+        for (flag, value) in flags {
+            if flag != "config" && flag != "bind" {
+                let source = format!("--{flag}");
+                config.apply(&flag, Value::String(value), &source)?;
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Applies the settings found in a configuration file
+    fn apply_file(&mut self, path: &Path) -> Result<()> {
+        let text = fs::read_to_string(path).map_err(|err| Error::Io {
+            path: path.to_path_buf(),
+            err,
+        })?;
+        let entries = toml::parse(&text).map_err(|(line, message)| Error::Parse {
+            path: path.to_path_buf(),
+            line,
+            message,
+        })?;
+        for entry in entries {
+            let source = format!("{}:{}", path.display(), entry.line);
+            self.apply(&entry.key, entry.value, &source)?;
+        }
+        Ok(())
+    }
+
+    // Applies a single setting. String values are accepted for every setting so that environment
+    // variables and flags can share this code; `source` identifies where the value came from.
+    fn apply(&mut self, setting: &str, value: Value, source: &str) -> Result<()> {
+        let invalid = |message: String| Error::invalid(format!("{setting} ({source})"), message);
+        match setting {
+            "bind" => self.bind = strings(value).map_err(invalid)?,
+            "admin_bind" => self.admin_bind = Some(string(value).map_err(invalid)?),
+            "socket_mode" => self.socket_mode = Some(mode(value).map_err(invalid)?),
+            "workers" => {
+                self.min_workers = number(value).map_err(invalid)?;
+                self.max_workers = self.min_workers;
+            }
+            "min_workers" => self.min_workers = number(value).map_err(invalid)?,
+            "max_workers" => self.max_workers = number(value).map_err(invalid)?,
+            "worker_idle_timeout" => {
+                self.worker_idle_timeout =
+                    Duration::from_secs(number(value).map_err(invalid)? as u64)
+            }
+            "queue_depth" => self.queue_depth = number(value).map_err(invalid)?,
+            "job_timeout" => {
+                self.job_timeout = Duration::from_secs(number(value).map_err(invalid)? as u64)
+            }
+            "socket_timeout" => {
+                self.socket_timeout = Duration::from_secs(number(value).map_err(invalid)? as u64)
+            }
+            "max_request_line" => self.max_request_line = number(value).map_err(invalid)?,
+            "max_header_size" => self.max_header_size = number(value).map_err(invalid)?,
+            "max_headers" => self.max_headers = number(value).map_err(invalid)?,
+            "max_body_size" => self.max_body_size = number(value).map_err(invalid)?,
+            "header_timeout" => {
+                self.header_timeout = Duration::from_secs(number(value).map_err(invalid)? as u64)
+            }
+            "max_connections" => self.max_connections = number(value).map_err(invalid)?,
+            "max_connections_per_ip" => {
+                self.max_connections_per_ip = number(value).map_err(invalid)?
+            }
+            "io_engine" => self.io_engine = parsed(value).map_err(invalid)?,
+            "root" => self.root = PathBuf::from(string(value).map_err(invalid)?),
+            "error_pages" => {
+                self.error_pages = Some(PathBuf::from(string(value).map_err(invalid)?))
+            }
+            "admin_token" => self.admin_token = Some(string(value).map_err(invalid)?),
+            "trace" => self.trace = boolean(value).map_err(invalid)?,
+            "shutdown_timeout" => {
+                self.shutdown_timeout = Duration::from_secs(number(value).map_err(invalid)? as u64)
+            }
+            "proxy_protocol" => self.proxy_protocol = boolean(value).map_err(invalid)?,
+            "forwarded_headers" => self.forwarded_headers = boolean(value).map_err(invalid)?,
+            "trusted_proxies" => {
+                self.trusted_proxies = strings(value)
+                    .and_then(|networks| {
+                        networks
+                            .iter()
+                            .map(|network| network.parse().map_err(|e: crate::Error| e.to_string()))
+                            .collect()
+                    })
+                    .map_err(invalid)?
+            }
+            _ if setting.starts_with("rate_limit.") => self
+                .rate_limit
+                .apply(&setting["rate_limit.".len()..], value)
+                .map_err(invalid)?,
+            _ if setting.starts_with("access.") => self
+                .access
+                .apply(&setting["access.".len()..], value)
+                .map_err(invalid)?,
+            "log.level" => self.log.level = directives(value).map_err(invalid)?,
+            "log.format" => self.log.format = parsed(value).map_err(invalid)?,
+            "log.mode" => self.log.mode = parsed(value).map_err(invalid)?,
+            "log.queue_capacity" => self.log.queue_capacity = number(value).map_err(invalid)?,
+            "log.overflow" => self.log.overflow = parsed(value).map_err(invalid)?,
+            _ => return Err(invalid("unknown setting".to_string())),
+        }
+        Ok(())
+    }
+
+    // Checks that the combined settings are usable
+    fn validate(&self) -> Result<()> {
+        if self.bind.is_empty() {
+            return Err(Error::invalid("bind", "at least one address is required"));
+        }
+        let binds = self.bind.iter().map(|addr| ("bind", addr));
+        let mut seen = Vec::new();
+        for (setting, addr) in binds.chain(self.admin_bind.iter().map(|addr| ("admin_bind", addr)))
+        {
+            validate_addr(setting, addr)?;
+            if seen.contains(&addr) {
+                return Err(Error::invalid(
+                    setting,
+                    format!("'{addr}' is listed more than once"),
+                ));
+            }
+            seen.push(addr);
+        }
+        let tcp = self
+            .bind
+            .iter()
+            .any(|addr| !addr.starts_with(UNIX_SOCKET_PREFIX));
+        for (setting, enabled) in [
+            ("proxy_protocol", self.proxy_protocol),
+            ("forwarded_headers", self.forwarded_headers),
+        ] {
+            if enabled && tcp && self.trusted_proxies.is_empty() {
+                return Err(Error::invalid(
+                    setting,
+                    "requires trusted_proxies, the networks of the proxies to believe",
+                ));
+            }
+        }
+        if !(1..=MAX_WORKERS).contains(&self.max_workers) {
+            return Err(Error::invalid(
+                "max_workers",
+                format!("{} is not between 1 and {MAX_WORKERS}", self.max_workers),
+            ));
+        }
+        if !(1..=self.max_workers).contains(&self.min_workers) {
+            return Err(Error::invalid(
+                "min_workers",
+                format!(
+                    "{} is not between 1 and max_workers ({})",
+                    self.min_workers, self.max_workers
+                ),
+            ));
+        }
+        if self.queue_depth == 0 {
+            return Err(Error::invalid("queue_depth", "must be greater than zero"));
+        }
+        for (setting, timeout) in [
+            ("job_timeout", self.job_timeout),
+            ("socket_timeout", self.socket_timeout),
+            ("header_timeout", self.header_timeout),
+        ] {
+            if timeout.is_zero() {
+                return Err(Error::invalid(setting, "must be greater than zero"));
+            }
+        }
+        for (setting, limit) in [
+            ("max_request_line", self.max_request_line),
+            ("max_header_size", self.max_header_size),
+            ("max_headers", self.max_headers),
+            ("max_body_size", self.max_body_size),
+            ("max_connections", self.max_connections),
+            ("max_connections_per_ip", self.max_connections_per_ip),
+        ] {
+            if limit == 0 {
+                return Err(Error::invalid(setting, "must be greater than zero"));
+            }
+        }
+        if !self.root.is_dir() {
+            return Err(Error::invalid(
+                "root",
+                format!("'{}' is not a directory", self.root.display()),
+            ));
+        }
+        if let Some(dir) = self.error_pages.as_ref().filter(|dir| !dir.is_dir()) {
+            return Err(Error::invalid(
+                "error_pages",
+                format!("'{}' is not a directory", dir.display()),
+            ));
+        }
+        self.rate_limit.validate()?;
+        self.access.validate()?;
+        if self.log.queue_capacity == 0 {
+            return Err(Error::invalid(
+                "log.queue_capacity",
+                "must be greater than zero",
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Helper function that splits command-line arguments into `(flag, value)` pairs using long flag names
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Vec<(String, String)>> {
+    let mut flags = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => {
+                (name.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let flag = match name.as_str() {
+            "-h" | "--help" => return Err(Error::Help),
+            "-c" | "--config" => "config",
+            "-b" | "--bind" => "bind",
+            "--admin-bind" => "admin_bind",
+            "--socket-mode" => "socket_mode",
+            "-w" | "--workers" => "workers",
+            "--min-workers" => "min_workers",
+            "--max-workers" => "max_workers",
+            "--io-engine" => "io_engine",
+            "-r" | "--root" => "root",
+            _ => return Err(Error::Usage(format!("unexpected argument '{arg}'"))),
+        };
+        let value = match inline_value {
+            Some(value) => value,
+            None => args
+                .next()
+                .ok_or_else(|| Error::Usage(format!("'{name}' requires a value")))?,
+        };
+        flags.push((flag.to_string(), value));
+    }
+    Ok(flags)
+}
+
+// Helper function that checks that an address is a resolvable 'host:port' or a Unix socket path
+fn validate_addr(setting: &str, addr: &str) -> Result<()> {
+    if let Some(path) = addr.strip_prefix(UNIX_SOCKET_PREFIX) {
+        return match Path::new(path).parent() {
+            _ if path.is_empty() => Err(Error::invalid(setting, "missing Unix socket path")),
+            Some(dir) if !dir.as_os_str().is_empty() && !dir.is_dir() => Err(Error::invalid(
+                setting,
+                format!("'{}' is not a directory", dir.display()),
+            )),
+            _ => Ok(()),
+        };
+    }
+    match addr.to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(Error::invalid(setting, format!("'{addr}' did not resolve"))),
+        Err(e) => Err(Error::invalid(
+            setting,
+            format!("'{addr}' is not a valid 'host:port' address: {e}"),
+        )),
+    }
+}
+
+// Helper function that extracts a list of strings, given either as an array or comma separated
+fn strings(value: Value) -> result::Result<Vec<String>, String> {
+    match value {
+        Value::String(list) => Ok(list
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect()),
+        Value::Array(items) => items.into_iter().map(string).collect(),
+        value => Err(format!(
+            "expected a string or array, found {}",
+            value.type_name()
+        )),
+    }
+}
+
+// Helper function that extracts a string setting
+fn string(value: Value) -> result::Result<String, String> {
+    match value {
+        Value::String(value) => Ok(value),
+        value => Err(format!("expected a string, found {}", value.type_name())),
+    }
+}
+
+// Helper function that extracts log level directives, checking that they parse
+fn directives(value: Value) -> result::Result<String, String> {
+    let directives = string(value)?;
+    Levels::parse(&directives, LevelFilter::Info).map_err(|e| e.to_string())?;
+    Ok(directives)
+}
+
+// Helper function that extracts a boolean setting, which may also be given as a string
+fn boolean(value: Value) -> result::Result<bool, String> {
+    match value {
+        Value::Boolean(value) => Ok(value),
+        Value::String(text) => match text.trim().to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Ok(true),
+            "false" | "no" | "off" | "0" => Ok(false),
+            _ => Err(format!("'{text}' is not a boolean")),
+        },
+        value => Err(format!("expected a boolean, found {}", value.type_name())),
+    }
+}
+
+// Helper function that extracts a non-negative integer setting, which may also be given as a string
+fn number(value: Value) -> result::Result<usize, String> {
+    match value {
+        Value::Integer(number) => {
+            usize::try_from(number).map_err(|_| format!("{number} is not a non-negative integer"))
+        }
+        Value::String(text) => text
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{text}' is not a non-negative integer")),
+        value => Err(format!("expected an integer, found {}", value.type_name())),
+    }
+}
+
+// Helper function that extracts file permissions given as an octal string such as "660"
+fn mode(value: Value) -> result::Result<u32, String> {
+    let text = string(value)?;
+    let digits = text.trim().trim_start_matches("0o");
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("'{text}' is not an octal permission mode")),
+    }
+}
+
+// Helper function that extracts a string setting and parses it
+fn parsed<T>(value: Value) -> result::Result<T, String>
+where
+    T: std::str::FromStr,
+    T::Err: fmt::Display,
+{
+    string(value)?.parse().map_err(|e: T::Err| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logger::{LogFormat, LogMode};
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(
+            Config::default(),
+            Config::from_sources(Vec::new(), &no_env).unwrap()
+        );
+    }
+
+    #[test]
+    fn layering() {
+        let path = env::temp_dir().join(format!("ptodd-config-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "bind = \"127.0.0.1:8000\"\nworkers = 8\n[log]\nformat = \"json\"\n",
+        )
+        .unwrap();
+        let env = |name: &str| match name {
+            WORKERS_ENV_VAR_NAME => Some("16".to_string()),
+            ADMIN_TOKEN_ENV_VAR_NAME => Some("secret".to_string()),
+            LOG_MODE_ENV_VAR_NAME => Some("async".to_string()),
+            _ => None,
+        };
+        let config = Config::from_sources(
+            args(&["--config", path.to_str().unwrap(), "-w", "2", "--root=."]),
+            &env,
+        )
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(vec!["127.0.0.1:8000".to_string()], config.bind);
+        assert_eq!((2, 2), (config.min_workers, config.max_workers));
+        assert_eq!(Some("secret".to_string()), config.admin_token);
+        assert_eq!(LogFormat::Json, config.log.format);
+        assert_eq!(LogMode::Async, config.log.mode);
+        let invalid = Config::from_sources(Vec::new(), &|name| {
+            (name == LOG_QUEUE_CAPACITY_ENV_VAR_NAME).then(|| "0".to_string())
+        })
+        .unwrap_err();
+        assert_eq!(
+            "invalid log.queue_capacity: must be greater than zero",
+            invalid.to_string()
+        );
+        let invalid = Config::from_sources(Vec::new(), &|name| {
+            (name == LOG_ENV_VAR_NAME).then(|| "info,ptodd=bogus".to_string())
+        })
+        .unwrap_err();
+        assert_eq!(
+            "invalid log.level (RUST_LOG): invalid log level: 'bogus'",
+            invalid.to_string()
+        );
+    }
+
+    #[test]
+    fn binds() {
+        let config = Config::from_sources(
+            args(&[
+                "-b",
+                "127.0.0.1:8000",
+                "--bind=[::1]:8000",
+                "--admin-bind",
+                "127.0.0.1:8001",
+            ]),
+            &|name| (name == BIND_ENV_VAR_NAME).then(|| "127.0.0.1:9000".to_string()),
+        )
+        .unwrap();
+        assert_eq!(vec!["127.0.0.1:8000", "[::1]:8000"], config.bind);
+        assert_eq!(Some("127.0.0.1:8001".to_string()), config.admin_bind);
+        let config = Config::from_sources(Vec::new(), &|name| {
+            (name == BIND_ENV_VAR_NAME).then(|| "127.0.0.1:9000, 127.0.0.1:9001".to_string())
+        })
+        .unwrap();
+        assert_eq!(vec!["127.0.0.1:9000", "127.0.0.1:9001"], config.bind);
+        assert!(Config::from_sources(
+            args(&["-b", "127.0.0.1:8000", "--admin-bind", "127.0.0.1:8000"]),
+            &no_env
+        )
+        .is_err());
+        let mut config = Config::default();
+        let list = Value::Array(vec![Value::String("0.0.0.0:80".to_string())]);
+        config.apply("bind", list, "test").unwrap();
+        assert_eq!(vec!["0.0.0.0:80"], config.bind);
+        assert!(config
+            .apply("bind", Value::Array(vec![Value::Integer(80)]), "test")
+            .is_err());
+    }
+
+    #[test]
+    fn unix_sockets() {
+        let path = env::temp_dir().join("ptodd.sock");
+        let bind = format!("{UNIX_SOCKET_PREFIX}{}", path.display());
+        let config =
+            Config::from_sources(args(&["-b", &bind, "--socket-mode", "0660"]), &no_env).unwrap();
+        assert_eq!(vec![bind], config.bind);
+        assert_eq!(Some(0o660), config.socket_mode);
+        for bad in [
+            ["-b", "unix:"],
+            ["-b", "unix:/no/such/dir/ptodd.sock"],
+            ["--socket-mode", "999"],
+        ] {
+            assert!(matches!(
+                Config::from_sources(args(&bad), &no_env),
+                Err(Error::Invalid { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn proxies() {
+        let env = |name: &str| match name {
+            PROXY_PROTOCOL_ENV_VAR_NAME => Some("yes".to_string()),
+            TRUSTED_PROXIES_ENV_VAR_NAME => Some("10.0.0.0/8, fd00::/8".to_string()),
+            _ => None,
+        };
+        let config = Config::from_sources(Vec::new(), &env).unwrap();
+        assert!(config.proxy_protocol);
+        assert_eq!(
+            vec![
+                "10.0.0.0/8".parse().unwrap(),
+                "fd00::/8".parse::<Cidr>().unwrap()
+            ],
+            config.trusted_proxies
+        );
+        let untrusted =
+            |name: &str| (name == PROXY_PROTOCOL_ENV_VAR_NAME).then(|| "true".to_string());
+        assert!(Config::from_sources(Vec::new(), &untrusted).is_err());
+        let mut config = Config::default();
+        let bad = Value::String("10.0.0.0/99".to_string());
+        assert!(config.apply("trusted_proxies", bad, "test").is_err());
+        assert!(config
+            .apply("proxy_protocol", Value::Integer(1), "test")
+            .is_err());
+    }
+
+    #[test]
+    fn workers() {
+        let env = |name: &str| (name == WORKERS_ENV_VAR_NAME).then(|| "8".to_string());
+        let config = Config::from_sources(args(&["--max-workers", "64"]), &env).unwrap();
+        assert_eq!((8, 64), (config.min_workers, config.max_workers));
+        let mut config = Config::default();
+        config
+            .apply("queue_depth", Value::Integer(0), "test")
+            .unwrap();
+        assert!(config.validate().is_err());
+        let env = |name: &str| match name {
+            JOB_TIMEOUT_ENV_VAR_NAME => Some("5".to_string()),
+            SOCKET_TIMEOUT_ENV_VAR_NAME => Some("2".to_string()),
+            _ => None,
+        };
+        let config = Config::from_sources(Vec::new(), &env).unwrap();
+        assert_eq!(
+            (Duration::from_secs(5), Duration::from_secs(2)),
+            (config.job_timeout, config.socket_timeout)
+        );
+        let env = |name: &str| (name == SOCKET_TIMEOUT_ENV_VAR_NAME).then(|| "0".to_string());
+        assert!(Config::from_sources(Vec::new(), &env).is_err());
+        let config = Config::from_sources(args(&["--io-engine", "Threaded"]), &no_env).unwrap();
+        assert_eq!(IoEngine::Threaded, config.io_engine);
+        assert!(Config::from_sources(args(&["--io-engine", "kqueue"]), &no_env).is_err());
+        let env = |name: &str| match name {
+            MAX_REQUEST_LINE_ENV_VAR_NAME => Some("1024".to_string()),
+            MAX_HEADERS_ENV_VAR_NAME => Some("20".to_string()),
+            HEADER_TIMEOUT_ENV_VAR_NAME => Some("3".to_string()),
+            _ => None,
+        };
+        let config = Config::from_sources(Vec::new(), &env).unwrap();
+        assert_eq!(
+            (1024, DEFAULT_MAX_HEADER_SIZE, 20, Duration::from_secs(3)),
+            (
+                config.max_request_line,
+                config.max_header_size,
+                config.max_headers,
+                config.header_timeout
+            )
+        );
+        let env = |name: &str| (name == MAX_HEADER_SIZE_ENV_VAR_NAME).then(|| "0".to_string());
+        assert!(Config::from_sources(Vec::new(), &env).is_err());
+        let env = |name: &str| (name == MAX_BODY_SIZE_ENV_VAR_NAME).then(|| "4096".to_string());
+        let config = Config::from_sources(Vec::new(), &env).unwrap();
+        assert_eq!(4096, config.max_body_size);
+        let env = |name: &str| match name {
+            MAX_CONNECTIONS_ENV_VAR_NAME => Some("500".to_string()),
+            MAX_CONNECTIONS_PER_IP_ENV_VAR_NAME => Some("10".to_string()),
+            _ => None,
+        };
+        let config = Config::from_sources(Vec::new(), &env).unwrap();
+        assert_eq!(
+            (500, 10),
+            (config.max_connections, config.max_connections_per_ip)
+        );
+        let env =
+            |name: &str| (name == MAX_CONNECTIONS_PER_IP_ENV_VAR_NAME).then(|| "0".to_string());
+        assert!(Config::from_sources(Vec::new(), &env).is_err());
+        #[cfg(target_os = "linux")]
+        {
+            let env = |name: &str| (name == IO_ENGINE_ENV_VAR_NAME).then(|| "epoll".to_string());
+            let config = Config::from_sources(Vec::new(), &env).unwrap();
+            assert_eq!(IoEngine::Epoll, config.io_engine);
+        }
+        for bad in [
+            &["--min-workers", "0"][..],
+            &["--min-workers", "64"],
+            &["--max-workers", "2048"],
+        ] {
+            assert!(matches!(
+                Config::from_sources(args(bad), &no_env),
+                Err(Error::Invalid { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn route_groups() {
+        let path = env::temp_dir().join(format!("ptodd-route-groups-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "[rate_limit]\nkey = \"ip\"\n\n[rate_limit.api]\nprefix = \"/api/\"\nrate = \"600/m\"\n\n\
+             [access.admin]\nprefix = \"/admin/\"\nrules = [\"allow ::1\", \"deny all\"]\n",
+        )
+        .unwrap();
+        let env = |name: &str| match name {
+            CONFIG_ENV_VAR_NAME => Some(path.display().to_string()),
+            RATE_LIMIT_KEY_ENV_VAR_NAME => Some("header:X-Api-Key".to_string()),
+            _ => None,
+        };
+        let config = Config::from_sources(Vec::new(), &env).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            RateKey::Header("X-Api-Key".to_string()),
+            config.rate_limit.key
+        );
+        assert_eq!("/api/", config.rate_limit.groups[0].prefix);
+        assert_eq!(600, config.rate_limit.groups[0].burst());
+        assert_eq!("/admin/", config.access.lists[0].prefix);
+        let env =
+            |name: &str| (name == RATE_LIMIT_MAX_CLIENTS_ENV_VAR_NAME).then(|| "0".to_string());
+        assert!(Config::from_sources(Vec::new(), &env).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            Config::from_sources(args(&["--help"]), &no_env),
+            Err(Error::Help)
+        ));
+        assert!(matches!(
+            Config::from_sources(args(&["--verbose"]), &no_env),
+            Err(Error::Usage(_))
+        ));
+        assert!(matches!(
+            Config::from_sources(args(&["--bind"]), &no_env),
+            Err(Error::Usage(_))
+        ));
+        assert!(matches!(
+            Config::from_sources(args(&["--workers", "0"]), &no_env),
+            Err(Error::Invalid { .. })
+        ));
+        assert!(matches!(
+            Config::from_sources(args(&["--workers", "many"]), &no_env),
+            Err(Error::Invalid { .. })
+        ));
+        assert!(matches!(
+            Config::from_sources(args(&["--bind", "nowhere"]), &no_env),
+            Err(Error::Invalid { .. })
+        ));
+        assert!(matches!(
+            Config::from_sources(args(&["--root", "/no/such/dir"]), &no_env),
+            Err(Error::Invalid { .. })
+        ));
+        let env =
+            |name: &str| (name == ERROR_PAGES_ENV_VAR_NAME).then(|| "/no/such/dir".to_string());
+        assert!(matches!(
+            Config::from_sources(Vec::new(), &env),
+            Err(Error::Invalid { .. })
+        ));
+        assert!(matches!(
+            Config::from_sources(args(&["--config", "/no/such/file.toml"]), &no_env),
+            Err(Error::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn settings() {
+        let mut config = Config::default();
+        assert!(config.apply("workers", Value::Integer(3), "test").is_ok());
+        assert_eq!((3, 3), (config.min_workers, config.max_workers));
+        assert!(config.apply("workers", Value::Integer(-3), "test").is_err());
+        assert!(config.apply("bind", Value::Integer(80), "test").is_err());
+        assert!(config.apply("trace", Value::Boolean(true), "test").is_ok());
+        assert!(config.trace);
+        assert!(config
+            .apply("log.format", Value::String("xml".to_string()), "test")
+            .is_err());
+        let unknown = config
+            .apply("colour", Value::Boolean(true), "test")
+            .unwrap_err();
+        assert_eq!(
+            "invalid colour (test): unknown setting",
+            unknown.to_string()
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Rate Limit Settings
+//!
+//! Rate limits are set per route group in `[rate_limit.<group>]` tables, each applying to the
+//! requests whose path starts with the group's prefix. Clients are told apart by their address or,
+//! with `key = "header:<name>"`, by a request header such as an API key:
+//!
+//! ```toml
+//! [rate_limit]
+//! key = "ip"
+//! max_clients = 10000
+//!
+//! [rate_limit.api]
+//! prefix = "/api/"
+//! rate = "10/s"
+//! burst = 20
+//! ```
+
+use super::*;
+
+/// Default number of clients tracked per route group
+const DEFAULT_MAX_CLIENTS: usize = 10_000;
+
+/// What tells clients apart for rate limiting
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum RateKey {
+    /// The client's address
+    #[default]
+    Ip,
+    /// The value of a request header, falling back to the client's address without one
+    Header(String),
+}
+
+impl FromStr for RateKey {
+    type Err = String;
+    fn from_str(value: &str) -> result::Result<Self, String> {
+        match value.trim().split_once(':') {
+            None if value.trim().eq_ignore_ascii_case("ip") => Ok(RateKey::Ip),
+            Some((kind, name))
+                if kind.eq_ignore_ascii_case("header") && !name.trim().is_empty() =>
+            {
+                Ok(RateKey::Header(name.trim().to_string()))
+            }
+            _ => Err(format!(
+                "invalid rate limit key: '{value}' (expected 'ip' or 'header:<name>')"
+            )),
+        }
+    }
+}
+
+/// A sustained request rate: `requests` every `per`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rate {
+    pub requests: u32,
+    pub per: Duration,
+}
+
+impl Rate {
+    /// The rate in requests per second
+    pub fn per_second(&self) -> f64 {
+        f64::from(self.requests) / self.per.as_secs_f64()
+    }
+}
+
+impl Default for Rate {
+    fn default() -> Self {
+        Rate {
+            requests: 0,
+            per: Duration::from_secs(1),
+        }
+    }
+}
+
+impl FromStr for Rate {
+    type Err = String;
+    fn from_str(value: &str) -> result::Result<Self, String> {
+        let invalid =
+            || format!("invalid rate: '{value}' (expected e.g. '10/s', '600/m' or '1000/h')");
+        let (requests, unit) = value.trim().split_once('/').unwrap_or((value.trim(), "s"));
+        let per = match unit.trim() {
+            "s" | "sec" | "second" => Duration::from_secs(1),
+            "m" | "min" | "minute" => Duration::from_secs(60),
+            "h" | "hour" => Duration::from_secs(3600),
+            _ => return Err(invalid()),
+        };
+        let requests = requests.trim().parse().map_err(|_| invalid())?;
+        Ok(Rate { requests, per })
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.per.as_secs() {
+            3600 => write!(f, "{}/h", self.requests),
+            60 => write!(f, "{}/m", self.requests),
+            _ => write!(f, "{}/s", self.requests),
+        }
+    }
+}
+
+/// The rate limit for the requests whose path starts with a prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateGroup {
+    /// The name of the group's table
+    pub name: String,
+    /// The path prefix the group applies to
+    pub prefix: String,
+    /// The rate each client's requests are allowed at
+    pub rate: Rate,
+    /// How many requests a client may make at once; defaults to one period's worth
+    pub burst: Option<u32>,
+}
+
+impl RateGroup {
+    /// How many requests a client may make at once
+    pub fn burst(&self) -> u32 {
+        self.burst.unwrap_or(self.rate.requests)
+    }
+}
+
+/// Rate limiting settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimits {
+    /// What tells clients apart
+    pub key: RateKey,
+    /// The most clients tracked per group; the least recently seen are forgotten beyond that
+    pub max_clients: usize,
+    /// The route groups, of which the one with the longest matching prefix applies to a request
+    pub groups: Vec<RateGroup>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            key: RateKey::default(),
+            max_clients: DEFAULT_MAX_CLIENTS,
+            groups: Vec::new(),
+        }
+    }
+}
+
+impl RateLimits {
+    // Applies a setting from the `rate_limit` table, given without its `rate_limit.` prefix
+    pub(super) fn apply(&mut self, setting: &str, value: Value) -> result::Result<(), String> {
+        match setting.split_once('.') {
+            None => match setting {
+                "key" => self.key = parsed(value)?,
+                "max_clients" => self.max_clients = number(value)?,
+                _ => return Err("unknown setting".to_string()),
+            },
+            Some((name, field)) => {
+                let group = match self.groups.iter().position(|group| group.name == name) {
+                    Some(index) => &mut self.groups[index],
+                    None => {
+                        self.groups.push(RateGroup {
+                            name: name.to_string(),
+                            prefix: String::new(),
+                            rate: Rate::default(),
+                            burst: None,
+                        });
+                        self.groups.last_mut().unwrap()
+                    }
+                };
+                match field {
+                    "prefix" => group.prefix = string(value)?,
+                    "rate" => {
+                        group.rate = match value {
+                            Value::Integer(_) => Rate {
+                                requests: u32::try_from(number(value)?)
+                                    .map_err(|e| e.to_string())?,
+                                ..Rate::default()
+                            },
+                            value => parsed(value)?,
+                        }
+                    }
+                    "burst" => {
+                        group.burst =
+                            Some(u32::try_from(number(value)?).map_err(|e| e.to_string())?)
+                    }
+                    _ => return Err("unknown setting".to_string()),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Checks that the settings are usable
+    pub(super) fn validate(&self) -> Result<()> {
+        if self.max_clients == 0 {
+            return Err(Error::invalid(
+                "rate_limit.max_clients",
+                "must be greater than zero",
+            ));
+        }
+        for group in &self.groups {
+            let setting = |field: &str| format!("rate_limit.{}.{field}", group.name);
+            if !group.prefix.starts_with('/') {
+                return Err(Error::invalid(
+                    setting("prefix"),
+                    "a path prefix starting with '/' is required",
+                ));
+            }
+            if group.rate.requests == 0 {
+                return Err(Error::invalid(
+                    setting("rate"),
+                    "a rate greater than zero is required",
+                ));
+            }
+            if group.burst() == 0 {
+                return Err(Error::invalid(
+                    setting("burst"),
+                    "must be greater than zero",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Ok(RateKey::Ip), "IP".parse());
+        assert_eq!(
+            Ok(RateKey::Header("X-Api-Key".to_string())),
+            "header: X-Api-Key".parse()
+        );
+        assert!("header:".parse::<RateKey>().is_err());
+        assert!("cookie:session".parse::<RateKey>().is_err());
+        assert_eq!(
+            Ok(Rate {
+                requests: 600,
+                per: Duration::from_secs(60)
+            }),
+            "600/m".parse()
+        );
+        assert_eq!(
+            Ok(Duration::from_secs(1)),
+            "5".parse().map(|rate: Rate| rate.per)
+        );
+        assert!("5/fortnight".parse::<Rate>().is_err());
+        assert_eq!("1000/h", "1000/hour".parse::<Rate>().unwrap().to_string());
+    }
+
+    #[test]
+    fn groups() {
+        let mut limits = RateLimits::default();
+        limits
+            .apply("api.prefix", Value::String("/api/".to_string()))
+            .unwrap();
+        limits.apply("api.rate", Value::Integer(10)).unwrap();
+        limits
+            .apply("key", Value::String("header:X-Api-Key".to_string()))
+            .unwrap();
+        assert!(limits.validate().is_ok());
+        assert_eq!(10, limits.groups[0].burst());
+        limits.apply("api.burst", Value::Integer(0)).unwrap();
+        assert!(limits.validate().is_err());
+        limits
+            .apply("site.rate", Value::String("1/s".to_string()))
+            .unwrap();
+        assert_eq!(2, limits.groups.len());
+        assert!(limits.apply("api.colour", Value::Integer(1)).is_err());
+        assert!(limits.apply("api.rate", Value::Integer(-1)).is_err());
+        limits.apply("api.burst", Value::Integer(5)).unwrap();
+        assert!(limits.validate().is_err(), "site has no prefix");
+    }
+}
@@ -0,0 +1,324 @@
+//! A TOML subset parser
+//!
+//! Supports the parts of [TOML](https://toml.io/en/v1.0.0) a server configuration file needs:
+//!
+//! * `# comments` and blank lines
+//! * `[table]` headers (keys that follow are prefixed with `table.`)
+//! * `key = value` pairs with bare keys
+//! * basic (`"..."`, with escapes) and literal (`'...'`) strings, integers, booleans and single-line
+//!   arrays of those values
+//!
+//! Multi-line strings, floats, dates, inline tables and arrays of tables are not supported.
+
+use std::{iter::Peekable, str::Chars};
+
+use super::*;
+
+/// A configuration value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// A short name for the value's type for use in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+        }
+    }
+}
+
+/// A `key = value` pair, with its fully qualified key and the line it was found on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub line: usize,
+    pub key: String,
+    pub value: Value,
+}
+
+/// Parses a document into its entries, or returns the line number and description of the first error
+pub fn parse(text: &str) -> result::Result<Vec<Entry>, (usize, String)> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut table = String::new();
+    for (index, raw_line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = strip_comment(raw_line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .ok_or((line_number, "unterminated table header".to_string()))?
+                .trim();
+            if name.starts_with('[') {
+                return Err((
+                    line_number,
+                    "arrays of tables are not supported".to_string(),
+                ));
+            }
+            if name.is_empty() || !name.split('.').all(is_bare_key) {
+                return Err((line_number, format!("invalid table name '{name}'")));
+            }
+            table = name.to_string();
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or((line_number, "expected 'key = value'".to_string()))?;
+        let key = key.trim();
+        if !is_bare_key(key) {
+            return Err((line_number, format!("invalid key '{key}'")));
+        }
+        let key = if table.is_empty() {
+            key.to_string()
+        } else {
+            format!("{table}.{key}")
+        };
+        if entries.iter().any(|entry| entry.key == key) {
+            return Err((line_number, format!("duplicate key '{key}'")));
+        }
+        let mut chars = value.trim().chars().peekable();
+        let value = parse_value(&mut chars).map_err(|message| (line_number, message))?;
+        if let Some(c) = chars.find(|c| !c.is_whitespace()) {
+            return Err((line_number, format!("unexpected '{c}' after value")));
+        }
+        entries.push(Entry {
+            line: line_number,
+            key,
+            value,
+        });
+    }
+    Ok(entries)
+}
+
+// Helper function that removes a trailing comment, ignoring '#' inside strings
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..index],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+// Helper function that determines if a key is a valid bare key (Cf. TOML 1.0 Keys)
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// Helper function that parses a single value from the front of the input
+fn parse_value(chars: &mut Peekable<Chars>) -> result::Result<Value, String> {
+    skip_whitespace(chars);
+    match chars.peek() {
+        None => Err("missing value".to_string()),
+        Some('"') => parse_basic_string(chars),
+        Some('\'') => {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    None => return Err("unterminated string".to_string()),
+                    Some('\'') => return Ok(Value::String(value)),
+                    Some(c) => value.push(c),
+                }
+            }
+        }
+        Some('[') => parse_array(chars),
+        Some(_) => {
+            let mut token = String::new();
+            while let Some(&c) = chars.peek() {
+                if c == ',' || c == ']' || c.is_whitespace() {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+            match token.as_str() {
+                "true" => Ok(Value::Boolean(true)),
+                "false" => Ok(Value::Boolean(false)),
+                _ => parse_integer(&token),
+            }
+        }
+    }
+}
+
+// Helper function that parses a basic (double-quoted) string with its escape sequences
+fn parse_basic_string(chars: &mut Peekable<Chars>) -> result::Result<Value, String> {
+    chars.next();
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            None => return Err("unterminated string".to_string()),
+            Some('"') => return Ok(Value::String(value)),
+            Some('\\') => match chars.next() {
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('r') => value.push('\r'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .filter(|_| hex.len() == 4)
+                        .and_then(char::from_u32)
+                        .ok_or(format!("invalid unicode escape '\\u{hex}'"))?;
+                    value.push(c);
+                }
+                Some(c) => return Err(format!("invalid escape '\\{c}'")),
+                None => return Err("unterminated string".to_string()),
+            },
+            Some(c) => value.push(c),
+        }
+    }
+}
+
+// Helper function that parses a single-line array
+fn parse_array(chars: &mut Peekable<Chars>) -> result::Result<Value, String> {
+    chars.next();
+    let mut values = Vec::new();
+    loop {
+        skip_whitespace(chars);
+        if chars.peek() == Some(&']') {
+            chars.next();
+            return Ok(Value::Array(values));
+        }
+        values.push(parse_value(chars)?);
+        skip_whitespace(chars);
+        match chars.next() {
+            Some(',') => {}
+            Some(']') => return Ok(Value::Array(values)),
+            Some(c) => return Err(format!("expected ',' or ']' in array, found '{c}'")),
+            None => return Err("unterminated array".to_string()),
+        }
+    }
+}
+
+// Helper function that parses a decimal integer, allowing '_' between digits
+fn parse_integer(token: &str) -> result::Result<Value, String> {
+    let digits = token.strip_prefix(['+', '-']).unwrap_or(token);
+    if digits.is_empty()
+        || digits.starts_with('_')
+        || digits.ends_with('_')
+        || digits.contains("__")
+        || !digits.chars().all(|c| c.is_ascii_digit() || c == '_')
+    {
+        return Err(format!("invalid value '{token}'"));
+    }
+    token
+        .replace('_', "")
+        .parse()
+        .map(Value::Integer)
+        .map_err(|_| format!("integer out of range '{token}'"))
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(text: &str) -> Vec<(String, Value)> {
+        parse(text)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.key, entry.value))
+            .collect()
+    }
+
+    #[test]
+    fn document() {
+        let text = r#"
+            # ptodd configuration
+            bind = "localhost:6502"  # trailing comment
+            workers = 1_024
+            enabled = true
+            token = 'C:\no\escapes # here'
+
+            [log]
+            level = "info,ptodd::server=debug"
+            quoted = "tab\t\"quote\" \u00e9"
+            list = ["a", 'b' , -3, [false]]
+            empty = []
+        "#;
+        assert_eq!(
+            vec![
+                (
+                    "bind".to_string(),
+                    Value::String("localhost:6502".to_string())
+                ),
+                ("workers".to_string(), Value::Integer(1024)),
+                ("enabled".to_string(), Value::Boolean(true)),
+                (
+                    "token".to_string(),
+                    Value::String("C:\\no\\escapes # here".to_string())
+                ),
+                (
+                    "log.level".to_string(),
+                    Value::String("info,ptodd::server=debug".to_string())
+                ),
+                (
+                    "log.quoted".to_string(),
+                    Value::String("tab\t\"quote\" é".to_string())
+                ),
+                (
+                    "log.list".to_string(),
+                    Value::Array(vec![
+                        Value::String("a".to_string()),
+                        Value::String("b".to_string()),
+                        Value::Integer(-3),
+                        Value::Array(vec![Value::Boolean(false)]),
+                    ])
+                ),
+                ("log.empty".to_string(), Value::Array(Vec::new())),
+            ],
+            values(text)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Err((2, "expected 'key = value'".to_string())),
+            parse("a = 1\nb\n")
+        );
+        assert_eq!(
+            Err((2, "duplicate key 'a'".to_string())),
+            parse("a = 1\na = 2")
+        );
+        assert!(parse("a = 'open").is_err());
+        assert!(parse("a = \"open").is_err());
+        assert!(parse("a = 1 2").is_err());
+        assert!(parse("a = 1__0").is_err());
+        assert!(parse("a = 99999999999999999999").is_err());
+        assert!(parse("a = [1, 2").is_err());
+        assert!(parse("a = \"\\q\"").is_err());
+        assert!(parse("[log").is_err());
+        assert!(parse("[[servers]]").is_err());
+        assert!(parse("bad key = 1").is_err());
+        assert!(parse("a = 1.5").is_err());
+    }
+}
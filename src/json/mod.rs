@@ -0,0 +1,378 @@
+//! JSON
+//!
+//! A small JSON (RFC 8259) parser and serializer for request and response bodies. Numbers are held
+//! as `f64`, and objects keep their members in the order given. Nesting is limited to
+//! [`MAX_DEPTH`] levels so that a hostile document cannot exhaust the stack.
+
+use std::{fmt, str::FromStr};
+
+use super::*;
+
+/// The deepest nesting of arrays and objects parsed
+pub const MAX_DEPTH: usize = 64;
+
+/// A JSON value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// An object's members, in order
+    Object(Vec<(String, Value)>),
+}
+
+impl FromStr for Value {
+    type Err = Error;
+    fn from_str(text: &str) -> Result<Self> {
+        let mut parser = Parser {
+            text: text.as_bytes(),
+            pos: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.text.len() {
+            return Err(parser.error("unexpected content after the value"));
+        }
+        Ok(value)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(value) => write!(f, "{value}"),
+            // Integers print without a fraction; JSON has no representation of NaN or infinity
+            Value::Number(value) if !value.is_finite() => write!(f, "null"),
+            Value::Number(value) => write!(f, "{value}"),
+            Value::String(value) => write_string(f, value),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (name, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// A recursive descent parser over a document's bytes
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    /// The arrays and objects open at `pos`
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Value> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.nested(Parser::object),
+            Some(b'[') => self.nested(Parser::array),
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    // Parses an array or object, within the nesting limit
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value>) -> Result<Value> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(&format!("nested deeper than {MAX_DEPTH} levels")));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Value> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.eat(b'}') {
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a member name"));
+            }
+            let name = self.string()?;
+            self.skip_whitespace();
+            if !self.eat(b':') {
+                return Err(self.error("expected ':'"));
+            }
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            if self.eat(b'}') {
+                return Ok(Value::Object(members));
+            }
+            if !self.eat(b',') {
+                return Err(self.error("expected ',' or '}'"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value> {
+        self.pos += 1;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.eat(b']') {
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            if self.eat(b']') {
+                return Ok(Value::Array(values));
+            }
+            if !self.eat(b',') {
+                return Err(self.error("expected ',' or ']'"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.next() {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let c = match self.next() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape sequence")),
+                    };
+                    let mut buffer = [0u8; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+                Some(0..=0x1f) => return Err(self.error("control character in a string")),
+                Some(byte) => bytes.push(byte),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+        // The input is a `str`, and escapes only ever split it between characters
+        Ok(String::from_utf8(bytes).expect("JSON strings are UTF-8"))
+    }
+
+    // Parses the hexadecimal digits of a `\u` escape, and the low surrogate's escape that must
+    // follow a high surrogate (Cf. RFC 8259 7)
+    fn unicode_escape(&mut self) -> Result<char> {
+        let high = self.hex4()?;
+        let code = match high {
+            0xd800..=0xdbff => {
+                if !(self.eat(b'\\') && self.eat(b'u')) {
+                    return Err(self.error("unpaired surrogate"));
+                }
+                let low = self.hex4()?;
+                if !(0xdc00..=0xdfff).contains(&low) {
+                    return Err(self.error("unpaired surrogate"));
+                }
+                0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+            }
+            code => code,
+        };
+        char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate"))
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let digits = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .filter(|digits| digits.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("expected 4 hexadecimal digits"))?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(digits, 16)?)
+    }
+
+    fn number(&mut self) -> Result<Value> {
+        let start = self.pos;
+        self.eat(b'-');
+        match self.next() {
+            Some(b'0') => {}
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(self.error("invalid number")),
+        }
+        if self.eat(b'.') {
+            self.required_digits()?;
+        }
+        if self.eat(b'e') || self.eat(b'E') {
+            if !self.eat(b'+') {
+                self.eat(b'-');
+            }
+            self.required_digits()?;
+        }
+        // Only ASCII digits and signs have been consumed
+        let number = std::str::from_utf8(&self.text[start..self.pos])?;
+        Ok(Value::Number(number.parse()?))
+    }
+
+    fn required_digits(&mut self) -> Result<()> {
+        if !self.peek().is_some_and(|byte| byte.is_ascii_digit()) {
+            return Err(self.error("invalid number"));
+        }
+        self.digits();
+        Ok(())
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|byte| byte.is_ascii_digit()) {
+            self.pos += 1;
+        }
+    }
+
+    fn literal(&mut self, literal: &str, value: Value) -> Result<Value> {
+        if !self.text[self.pos..].starts_with(literal.as_bytes()) {
+            return Err(self.error("expected a value"));
+        }
+        self.pos += literal.len();
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    // Consumes the next byte if it is `expected`
+    fn eat(&mut self, expected: u8) -> bool {
+        let found = self.peek() == Some(expected);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn error(&self, message: &str) -> Error {
+        format!("invalid JSON at byte {}: {message}", self.pos).into()
+    }
+}
+
+// Helper function that writes a string as a JSON string literal
+fn write_string(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            '\u{0}'..='\u{1f}' => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Value {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(Value::Null, parse(" null "));
+        assert_eq!(Value::Bool(false), parse("false"));
+        assert_eq!(Value::Number(-12.5e1), parse("-12.5e1"));
+        assert_eq!(Value::Number(0.0), parse("0"));
+        assert_eq!(
+            Value::String("a\"\\/\n\u{e9}\u{1f600}".to_string()),
+            parse(r#""a\"\\\/\n\u00e9\ud83d\ude00""#)
+        );
+        assert_eq!(
+            Value::Object(vec![
+                ("level".to_string(), Value::String("debug".to_string())),
+                (
+                    "tags".to_string(),
+                    Value::Array(vec![
+                        Value::Number(1.0),
+                        Value::Bool(true),
+                        Value::Object(Vec::new())
+                    ])
+                ),
+            ]),
+            parse(r#"{"level": "debug", "tags": [1, true, {}]}"#)
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        for bad in [
+            "",
+            "nul",
+            "01",
+            "1.",
+            "-",
+            "1e",
+            "[1,]",
+            "{\"a\" 1}",
+            "{a: 1}",
+            "\"open",
+            "\"\t\"",
+            "\"\\x\"",
+            "\"\\ud83d\"",
+            "[] []",
+        ] {
+            assert!(bad.parse::<Value>().is_err(), "{bad:?}");
+        }
+        let deep = "[".repeat(MAX_DEPTH) + &"]".repeat(MAX_DEPTH);
+        assert!(deep.parse::<Value>().is_ok());
+        let deeper = "[".repeat(MAX_DEPTH + 1) + &"]".repeat(MAX_DEPTH + 1);
+        assert!(deeper.parse::<Value>().is_err());
+    }
+
+    #[test]
+    fn display() {
+        let text = r#"{"a":[1,2.5,null,true],"b":"x\"y\n\u0001","c":{}}"#;
+        assert_eq!(text, parse(text).to_string());
+        assert_eq!("null", Value::Number(f64::NAN).to_string());
+    }
+}
@@ -0,0 +1,199 @@
+//! Runtime-adjustable log levels
+//!
+//! Levels are expressed with `RUST_LOG`-style directives: a comma separated list of either a bare
+//! level, which sets the global level, or `target=level`, which overrides the level for a target and
+//! its descendants (e.g. `info,ptodd::server=debug`).
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        PoisonError, RwLock,
+    },
+};
+
+use super::*;
+
+/// Global and per-target log levels that can be changed while the server is running
+#[derive(Debug)]
+pub struct Levels {
+    /// The global level, stored as a `LevelFilter` discriminant
+    global: AtomicUsize,
+    /// Per-target overrides
+    targets: RwLock<Vec<(String, LevelFilter)>>,
+}
+
+impl Levels {
+    /// Creates levels from a directive string such as `info,ptodd::server=debug`
+    pub fn parse(directives: &str, default: LevelFilter) -> Result<Levels> {
+        let mut global = default;
+        let mut targets = Vec::new();
+        for directive in directives.split(',').map(str::trim) {
+            match directive.split_once('=') {
+                None if directive.is_empty() => {}
+                None => global = parse_level(directive)?,
+                Some((target, level)) => {
+                    let target = parse_target(target)?;
+                    let level = parse_level(level)?;
+                    targets.retain(|(existing, _)| existing != target);
+                    targets.push((target.to_string(), level));
+                }
+            }
+        }
+        Ok(Levels {
+            global: AtomicUsize::new(global as usize),
+            targets: RwLock::new(targets),
+        })
+    }
+
+    /// The global level
+    pub fn global(&self) -> LevelFilter {
+        level_filter(self.global.load(Ordering::Relaxed))
+    }
+
+    /// Sets the global level
+    pub fn set_global(&self, level: LevelFilter) {
+        self.global.store(level as usize, Ordering::Relaxed);
+        self.update_max_level();
+    }
+
+    /// Overrides the level for a target and its descendants
+    pub fn set_target(&self, target: &str, level: LevelFilter) -> Result<()> {
+        let target = parse_target(target)?;
+        {
+            let mut targets = self.targets.write().unwrap_or_else(PoisonError::into_inner);
+            match targets.iter_mut().find(|(existing, _)| existing == target) {
+                Some((_, existing)) => *existing = level,
+                None => targets.push((target.to_string(), level)),
+            }
+        }
+        self.update_max_level();
+        Ok(())
+    }
+
+    /// Removes a target override, returning whether one existed
+    pub fn clear_target(&self, target: &str) -> bool {
+        let removed = {
+            let mut targets = self.targets.write().unwrap_or_else(PoisonError::into_inner);
+            let count = targets.len();
+            targets.retain(|(existing, _)| existing != target);
+            targets.len() != count
+        };
+        self.update_max_level();
+        removed
+    }
+
+    /// The level that applies to a target: its most specific override, or the global level
+    pub(super) fn level_for(&self, target: &str) -> LevelFilter {
+        let targets = self.targets.read().unwrap_or_else(PoisonError::into_inner);
+        targets
+            .iter()
+            .filter(|(prefix, _)| target_matches(target, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+            .unwrap_or_else(|| self.global())
+    }
+
+    /// The most verbose level in effect anywhere
+    pub(super) fn max(&self) -> LevelFilter {
+        let targets = self.targets.read().unwrap_or_else(PoisonError::into_inner);
+        targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.global(), Ord::max)
+    }
+
+    // Keeps the `log` facade's cheap pre-filter in step with the configured levels
+    fn update_max_level(&self) {
+        set_max_level(self.max());
+    }
+}
+
+impl fmt::Display for Levels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.global().as_str().to_ascii_lowercase())?;
+        let targets = self.targets.read().unwrap_or_else(PoisonError::into_inner);
+        for (target, level) in targets.iter() {
+            write!(f, ",{target}={}", level.as_str().to_ascii_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a level name such as `debug` or `off`
+pub fn parse_level(level: &str) -> Result<LevelFilter> {
+    LevelFilter::from_str(level.trim()).map_err(|_| format!("invalid log level: '{level}'").into())
+}
+
+// Helper function that validates a target name
+fn parse_target(target: &str) -> Result<&str> {
+    let target = target.trim();
+    if target.is_empty()
+        || target
+            .chars()
+            .any(|c| c.is_whitespace() || c == ',' || c == '=')
+    {
+        return Err(format!("invalid log target: '{target}'").into());
+    }
+    Ok(target)
+}
+
+// Helper function that determines if a target is the given prefix or one of its descendants
+fn target_matches(target: &str, prefix: &str) -> bool {
+    target
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+// Helper function that converts a stored discriminant back into a level filter
+fn level_filter(value: usize) -> LevelFilter {
+    match value {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directives() {
+        let levels =
+            Levels::parse("warn, ptodd::server=debug,ptodd=error", LevelFilter::Info).unwrap();
+        assert_eq!(LevelFilter::Warn, levels.global());
+        assert_eq!(
+            LevelFilter::Debug,
+            levels.level_for("ptodd::server::worker")
+        );
+        assert_eq!(LevelFilter::Error, levels.level_for("ptodd::logger"));
+        assert_eq!(LevelFilter::Warn, levels.level_for("ptodd_other"));
+        assert_eq!(LevelFilter::Debug, levels.max());
+        assert_eq!("warn,ptodd::server=debug,ptodd=error", levels.to_string());
+        assert_eq!(
+            LevelFilter::Info,
+            Levels::parse("", LevelFilter::Info).unwrap().global()
+        );
+        assert!(Levels::parse("loud", LevelFilter::Info).is_err());
+        assert!(Levels::parse("=debug", LevelFilter::Info).is_err());
+    }
+
+    #[test]
+    fn adjust() {
+        let levels = Levels::parse("info", LevelFilter::Info).unwrap();
+        levels.set_global(LevelFilter::Error);
+        levels
+            .set_target("ptodd::server", LevelFilter::Trace)
+            .unwrap();
+        assert_eq!(LevelFilter::Error, levels.level_for("ptodd::logger"));
+        assert_eq!(LevelFilter::Trace, levels.level_for("ptodd::server"));
+        assert!(levels.clear_target("ptodd::server"));
+        assert!(!levels.clear_target("ptodd::server"));
+        assert_eq!(LevelFilter::Error, levels.level_for("ptodd::server"));
+        assert!(levels.set_target("bad target", LevelFilter::Info).is_err());
+    }
+}
@@ -7,61 +7,386 @@
 //! loggers would provide configuration options. It is included here in keeping with the intent of this
 //! site to minimize to ideally none the usage of any third-party crates.
 //!
-//! Log entries are output to stderr
+//! Log entries are output to stderr, either as human-readable text or as JSON lines (one JSON object
+//! per line) suitable for a log collector, which carry the record's key-value pairs (e.g.
+//! `info!(peer = addr; "accepted")`) as members of their own. Entries are written synchronously by
+//! the logging thread unless the asynchronous mode is selected, in which case they are handed off to
+//! a background writer thread through a bounded queue (see [`writer`]).
+//!
+//! Log levels default to `info` and can be changed while the server is running through
+//! [`SimpleLogger::levels`] (see [`level`]).
 //!
 
 use std::{
-    env::{var, VarError},
+    fmt::Write as _,
+    result,
     str::FromStr,
+    sync::{Arc, OnceLock},
 };
 
-use log::{set_boxed_logger, set_max_level, LevelFilter, Log, Metadata, Record};
+use log::{
+    kv::{self, VisitSource},
+    set_logger, set_max_level, LevelFilter, Log, Metadata, Record,
+};
 
 use crate::time::DateTime;
+pub use level::{parse_level, Levels};
+use writer::AsyncWriter;
+pub use writer::OverflowPolicy;
 
 use super::*;
 
-/// Logging level environment variable name
-const LOG_ENV_VAR_NAME: &str = "RUST_LOG";
+mod level;
+mod writer;
+
+/// Default log level
+const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// Default asynchronous log queue capacity
+const DEFAULT_LOG_QUEUE_CAPACITY: usize = 1024;
+
+/// Log output formats
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("invalid log format: '{value}' (expected 'text' or 'json')").into()),
+        }
+    }
+}
+
+/// Log write modes
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum LogMode {
+    /// Written to stderr by the logging thread
+    #[default]
+    Sync,
+    /// Queued for a background writer thread
+    Async,
+}
+
+impl FromStr for LogMode {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "sync" => Ok(LogMode::Sync),
+            "async" => Ok(LogMode::Async),
+            _ => Err(format!("invalid log mode: '{value}' (expected 'sync' or 'async')").into()),
+        }
+    }
+}
+
+/// Logger settings
+///
+/// Settings come from the `[log]` section of the configuration file and the logging environment
+/// variables, layered and validated with the other settings (see [`crate::config`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggerConfig {
+    /// Level directives such as `info,ptodd::server=debug`
+    pub level: String,
+    pub format: LogFormat,
+    pub mode: LogMode,
+    /// Capacity of the asynchronous log queue
+    pub queue_capacity: usize,
+    /// What to do when the asynchronous log queue is full
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for LoggerConfig {
+    fn default() -> Self {
+        LoggerConfig {
+            level: DEFAULT_LOG_LEVEL.as_str().to_ascii_lowercase(),
+            format: LogFormat::default(),
+            mode: LogMode::default(),
+            queue_capacity: DEFAULT_LOG_QUEUE_CAPACITY,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+/// Where formatted log lines are sent
+enum Sink {
+    /// Written to stderr by the logging thread
+    Stderr,
+    /// Queued for the background writer thread
+    Async(Arc<AsyncWriter>),
+}
+
+/// The installed logger
+static LOGGER: OnceLock<SimpleLogger> = OnceLock::new();
 
 /// Simple logger
 pub struct SimpleLogger {
-    log_level: LevelFilter,
+    levels: Levels,
+    format: LogFormat,
+    sink: Sink,
 }
 
 impl SimpleLogger {
     /// Initialize the logger
-    pub fn init() -> Result<()> {
-        let log_level = match var(LOG_ENV_VAR_NAME) {
-            Ok(result) => Ok(result),
-            Err(VarError::NotPresent) => Ok("trace".to_string()),
-            Err(e) => Err(e),
-        }?;
-        let logger = Self {
-            log_level: LevelFilter::from_str(log_level.as_str())?,
+    pub fn init(config: &LoggerConfig) -> Result<()> {
+        let levels = Levels::parse(&config.level, DEFAULT_LOG_LEVEL)?;
+        let sink = match config.mode {
+            LogMode::Sync => Sink::Stderr,
+            LogMode::Async => Sink::Async(AsyncWriter::start(
+                config.queue_capacity,
+                config.overflow,
+                config.format,
+                std::io::stderr(),
+            )?),
         };
-        set_max_level(logger.log_level);
-        set_boxed_logger(Box::new(logger))?;
+        let logger = LOGGER.get_or_init(|| Self {
+            levels,
+            format: config.format,
+            sink,
+        });
+        set_logger(logger)?;
+        set_max_level(logger.levels.max());
         Ok(())
     }
+
+    /// The adjustable log levels of the installed logger, if one has been initialized
+    pub fn levels() -> Option<&'static Levels> {
+        LOGGER.get().map(|logger| &logger.levels)
+    }
 }
 
 impl Log for SimpleLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.log_level
+        metadata.level() <= self.levels.level_for(metadata.target())
     }
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            eprintln!(
-                "{}: {}: {}: {}",
-                DateTime::now(),
-                record.level(),
-                record.target(),
-                record.args()
-            );
+            let line = render(self.format, DateTime::now(), record);
+            match &self.sink {
+                Sink::Stderr => eprintln!("{line}"),
+                Sink::Async(writer) => writer.push(line),
+            }
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        if let Sink::Async(writer) = &self.sink {
+            writer.flush();
+        }
+    }
+}
+
+// Helper function that renders a record in the given output format (without a trailing newline)
+fn render(format: LogFormat, now: DateTime, record: &Record) -> String {
+    match format {
+        LogFormat::Text => format!(
+            "{}: {}: {}: {}",
+            now,
+            record.level(),
+            record.target(),
+            record.args()
+        ),
+        LogFormat::Json => format_json(now, record),
+    }
+}
+
+// Helper function that renders a record as a single-line JSON object.
+//
+// Optional record metadata (module, file and line) is emitted as `null` when absent so that every
+// line carries the same set of keys. The record's key-value pairs follow, with their values as
+// strings, and those whose keys clash with one of these members prefixed with `kv.`.
+fn format_json(now: DateTime, record: &Record) -> String {
+    let mut line = String::with_capacity(128);
+    line.push_str("{\"ts\":");
+    push_json_str(&mut line, &now.to_rfc3339());
+    line.push_str(",\"level\":");
+    push_json_str(&mut line, record.level().as_str());
+    line.push_str(",\"target\":");
+    push_json_str(&mut line, record.target());
+    line.push_str(",\"msg\":");
+    push_json_str(&mut line, &record.args().to_string());
+    line.push_str(",\"module\":");
+    push_json_opt_str(&mut line, record.module_path());
+    line.push_str(",\"file\":");
+    push_json_opt_str(&mut line, record.file());
+    line.push_str(",\"line\":");
+    match record.line() {
+        Some(number) => {
+            let _ = write!(line, "{number}");
+        }
+        None => line.push_str("null"),
+    }
+    let _ = record.key_values().visit(&mut JsonMembers(&mut line));
+    line.push('}');
+    line
+}
+
+/// The members every JSON line starts with, which key-value pairs must not duplicate
+const JSON_MEMBERS: [&str; 7] = ["ts", "level", "target", "msg", "module", "file", "line"];
+
+/// Appends the key-value pairs it visits to a JSON object as members
+struct JsonMembers<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for JsonMembers<'_> {
+    fn visit_pair(
+        &mut self,
+        key: kv::Key<'kvs>,
+        value: kv::Value<'kvs>,
+    ) -> result::Result<(), kv::Error> {
+        self.0.push(',');
+        match JSON_MEMBERS.contains(&key.as_str()) {
+            true => push_json_str(self.0, &format!("kv.{key}")),
+            false => push_json_str(self.0, key.as_str()),
+        }
+        self.0.push(':');
+        push_json_str(self.0, &value.to_string());
+        Ok(())
+    }
+}
+
+// Helper function that appends an optional string as a JSON string or `null`
+fn push_json_opt_str(out: &mut String, value: Option<&str>) {
+    match value {
+        Some(value) => push_json_str(out, value),
+        None => out.push_str("null"),
+    }
+}
+
+// Helper function that appends a string as a quoted and escaped JSON string (Cf. RFC-8259 7)
+fn push_json_str(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0C}' => out.push_str("\\f"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::*;
+
+    fn json_str(value: &str) -> String {
+        let mut out = String::new();
+        push_json_str(&mut out, value);
+        out
+    }
+
+    #[test]
+    fn json_escaping() {
+        assert_eq!(r#""plain""#, json_str("plain"));
+        assert_eq!(r#""say \"hi\"""#, json_str("say \"hi\""));
+        assert_eq!(r#""C:\\temp""#, json_str("C:\\temp"));
+        assert_eq!(r#""a\nb\tc\r""#, json_str("a\nb\tc\r"));
+        assert_eq!(r#""\u0000\u001f\b\f""#, json_str("\u{0}\u{1f}\u{8}\u{c}"));
+        assert_eq!(r#""héllo €""#, json_str("héllo €"));
+    }
+
+    #[test]
+    fn json_line() {
+        let now = DateTime::from_epoch(1_000_000_000, 0);
+        let line = format_json(
+            now,
+            &Record::builder()
+                .args(format_args!("got \"{}\"", "quoted"))
+                .level(Level::Warn)
+                .target("ptodd::server")
+                .module_path(Some("ptodd::server"))
+                .file(Some("src/server/mod.rs"))
+                .line(Some(42))
+                .build(),
+        );
+        assert_eq!(
+            concat!(
+                r#"{"ts":"2001-09-09T01:46:40.000Z","level":"WARN","target":"ptodd::server","#,
+                r#""msg":"got \"quoted\"","module":"ptodd::server","file":"src/server/mod.rs","line":42}"#
+            ),
+            line
+        );
+    }
+
+    #[test]
+    fn json_line_with_key_values() {
+        let pairs: [(&str, kv::Value); 2] = [
+            ("peer", kv::Value::from("10.0.0.1:4000")),
+            ("quote", kv::Value::from("say \"hi\"")),
+        ];
+        let line = format_json(
+            DateTime::from_epoch(0, 0),
+            &Record::builder()
+                .args(format_args!("accepted"))
+                .level(Level::Info)
+                .target("t")
+                .key_values(&pairs)
+                .build(),
+        );
+        assert!(line.ends_with(r#""line":null,"peer":"10.0.0.1:4000","quote":"say \"hi\""}"#));
+    }
+
+    #[test]
+    fn json_line_with_clashing_keys() {
+        let pairs: [(&str, kv::Value); 2] = [
+            ("level", kv::Value::from("x")),
+            ("msg", kv::Value::from("spoofed")),
+        ];
+        let line = format_json(
+            DateTime::from_epoch(0, 0),
+            &Record::builder()
+                .args(format_args!("accepted"))
+                .level(Level::Warn)
+                .target("t")
+                .key_values(&pairs)
+                .build(),
+        );
+        assert!(line.contains(r#""level":"WARN","target":"t","msg":"accepted","#));
+        assert!(line.ends_with(r#""line":null,"kv.level":"x","kv.msg":"spoofed"}"#));
+    }
+
+    #[test]
+    fn json_line_without_location() {
+        let line = format_json(
+            DateTime::from_epoch(0, 0),
+            &Record::builder()
+                .args(format_args!("x"))
+                .level(Level::Info)
+                .target("t")
+                .build(),
+        );
+        assert!(line.ends_with(r#""module":null,"file":null,"line":null}"#));
+    }
+
+    #[test]
+    fn log_mode() {
+        assert_eq!(LogMode::Async, "ASYNC".parse().unwrap());
+        assert_eq!(LogMode::Sync, "sync".parse().unwrap());
+        assert!("later".parse::<LogMode>().is_err());
+    }
+
+    #[test]
+    fn log_format() {
+        assert_eq!(LogFormat::Json, "JSON".parse().unwrap());
+        assert_eq!(LogFormat::Text, "text".parse().unwrap());
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
 }
@@ -0,0 +1,265 @@
+//! Background log writer
+//!
+//! Log lines are pushed onto a bounded in-memory queue and written to an output (stderr in practice)
+//! in batches by a dedicated thread so that logging threads never contend on the stderr lock.
+
+use std::{
+    collections::VecDeque,
+    io::Write,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread,
+};
+
+use log::Level;
+
+use super::*;
+
+/// What to do with a new log line when the queue is full
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for the writer to make room
+    #[default]
+    Block,
+    /// Discard the new line
+    DropNewest,
+    /// Discard the oldest queued line to make room for the new one
+    DropOldest,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "block" => Ok(OverflowPolicy::Block),
+            "drop-newest" => Ok(OverflowPolicy::DropNewest),
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            _ => Err(format!(
+                "invalid log overflow policy: '{value}' (expected 'block', 'drop-newest' or 'drop-oldest')"
+            )
+            .into()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Lines waiting to be written
+    lines: VecDeque<String>,
+    /// Lines accepted but not yet written (queued plus the batch being written)
+    pending: usize,
+    /// Lines discarded due to overflow since the last drop notice
+    dropped: u64,
+}
+
+/// A bounded queue drained by a dedicated writer thread
+#[derive(Debug)]
+pub(super) struct AsyncWriter {
+    capacity: usize,
+    policy: OverflowPolicy,
+    format: LogFormat,
+    state: Mutex<State>,
+    /// Signalled when lines are queued
+    not_empty: Condvar,
+    /// Signalled when the writer removes lines from the queue
+    not_full: Condvar,
+    /// Signalled when every pending line has been written
+    drained: Condvar,
+}
+
+impl AsyncWriter {
+    /// Creates the queue and starts its writer thread, which writes to `output`
+    pub(super) fn start(
+        capacity: usize,
+        policy: OverflowPolicy,
+        format: LogFormat,
+        output: impl Write + Send + 'static,
+    ) -> Result<Arc<AsyncWriter>> {
+        if capacity == 0 {
+            return Err("log queue capacity must be greater than zero".into());
+        }
+        let writer = Arc::new(AsyncWriter {
+            capacity,
+            policy,
+            format,
+            state: Mutex::new(State::default()),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            drained: Condvar::new(),
+        });
+        let background = Arc::clone(&writer);
+        thread::Builder::new()
+            .name("log-writer".to_string())
+            .spawn(move || background.run(output))?;
+        Ok(writer)
+    }
+
+    /// Queues a line, applying the overflow policy when the queue is full
+    pub(super) fn push(&self, line: String) {
+        let mut state = self.lock();
+        while state.lines.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::Block => {
+                    state = self
+                        .not_full
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+                OverflowPolicy::DropNewest => {
+                    state.dropped += 1;
+                    return;
+                }
+                OverflowPolicy::DropOldest => {
+                    state.lines.pop_front();
+                    state.pending -= 1;
+                    state.dropped += 1;
+                }
+            }
+        }
+        state.lines.push_back(line);
+        state.pending += 1;
+        self.not_empty.notify_one();
+    }
+
+    /// Blocks until every line queued so far has been written
+    pub(super) fn flush(&self) {
+        let mut state = self.lock();
+        while state.pending > 0 || state.dropped > 0 {
+            self.not_empty.notify_one();
+            state = self
+                .drained
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    // Writer thread body: repeatedly takes everything queued and writes it as one batch
+    fn run(&self, mut output: impl Write) {
+        let mut batch = String::new();
+        loop {
+            let (lines, dropped) = {
+                let mut state = self.lock();
+                while state.lines.is_empty() && state.dropped == 0 {
+                    state = self
+                        .not_empty
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+                self.not_full.notify_all();
+                (
+                    std::mem::take(&mut state.lines),
+                    std::mem::take(&mut state.dropped),
+                )
+            };
+            batch.clear();
+            if dropped > 0 {
+                batch.push_str(&self.drop_notice(dropped));
+                batch.push('\n');
+            }
+            for line in &lines {
+                batch.push_str(line);
+                batch.push('\n');
+            }
+            // There is nowhere left to report a failure to write to the log output
+            let _ = output.write_all(batch.as_bytes());
+            let mut state = self.lock();
+            state.pending -= lines.len();
+            if state.pending == 0 && state.dropped == 0 {
+                self.drained.notify_all();
+            }
+        }
+    }
+
+    // Renders the record reporting how many lines were discarded due to overflow
+    fn drop_notice(&self, dropped: u64) -> String {
+        render(
+            self.format,
+            DateTime::now(),
+            &Record::builder()
+                .args(format_args!(
+                    "log queue overflow: {dropped} log records dropped"
+                ))
+                .level(Level::Warn)
+                .target(module_path!())
+                .module_path(Some(module_path!()))
+                .file(Some(file!()))
+                .line(Some(line!()))
+                .build(),
+        )
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn overflow_policy() {
+        assert_eq!(OverflowPolicy::Block, "block".parse().unwrap());
+        assert_eq!(OverflowPolicy::DropNewest, "drop-newest".parse().unwrap());
+        assert_eq!(OverflowPolicy::DropOldest, "Drop-Oldest".parse().unwrap());
+        assert!("drop".parse::<OverflowPolicy>().is_err());
+    }
+
+    /// An in-memory log output, shared with the writer thread
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn lines(&self) -> Vec<String> {
+            let output = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            output.lines().map(str::to_string).collect()
+        }
+    }
+
+    #[test]
+    fn flush_drains_queue() {
+        for policy in [
+            OverflowPolicy::Block,
+            OverflowPolicy::DropNewest,
+            OverflowPolicy::DropOldest,
+        ] {
+            let output = Captured::default();
+            let writer = AsyncWriter::start(2, policy, LogFormat::Text, output.clone()).unwrap();
+            for n in 0..50 {
+                writer.push(format!("line {n}"));
+            }
+            writer.flush();
+            let state = writer.lock();
+            assert_eq!((0, 0), (state.pending, state.dropped));
+            assert!(state.lines.is_empty());
+            let lines = output.lines();
+            let (written, notices): (Vec<_>, Vec<_>) =
+                lines.iter().partition(|line| line.starts_with("line "));
+            let dropped: usize = notices
+                .iter()
+                .filter_map(|notice| {
+                    let count = notice.split("log queue overflow: ").nth(1)?;
+                    count.split(' ').next()?.parse::<usize>().ok()
+                })
+                .sum();
+            assert_eq!(!notices.is_empty(), dropped > 0, "{policy:?}: {notices:?}");
+            assert_eq!(50, written.len() + dropped, "{policy:?}");
+            match policy {
+                OverflowPolicy::Block => assert_eq!(50, written.len()),
+                OverflowPolicy::DropNewest => assert_eq!("line 0", written[0]),
+                OverflowPolicy::DropOldest => assert_eq!("line 49", written[written.len() - 1]),
+            }
+        }
+    }
+}
@@ -1,22 +1,50 @@
 //! Provides the backend implementation for the ptodd.org website.
 
-use log::{debug, info, warn};
+use std::process::ExitCode;
 
+use log::{debug, error, info, warn};
+
+use config::Config;
 use logger::SimpleLogger;
-use server::Server;
+use server::{ListenFds, Server};
 
+mod cidr;
+mod config;
+mod json;
 mod logger;
 mod server;
 mod time;
 mod url;
 
-const DEFAULT_ADDR: &str = "localhost:6502";
-
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
 
-fn main() -> Result<()> {
-    SimpleLogger::init()?;
-    Server::new(DEFAULT_ADDR)?.run()?;
-    Ok(())
+fn main() -> ExitCode {
+    // First, while this is the only thread: taking the descriptors clears their variables
+    let listen_fds = ListenFds::take_from_env();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(config::Error::Help) => {
+            println!("{}", config::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("ptodd: {e}");
+            return ExitCode::from(2);
+        }
+    };
+    match run(&config, listen_fds) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ptodd: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(config: &Config, listen_fds: ListenFds) -> Result<()> {
+    SimpleLogger::init(&config.log)?;
+    let result = Server::new(config, listen_fds).and_then(|server| server.run());
+    log::logger().flush();
+    Ok(result?)
 }
@@ -0,0 +1,118 @@
+//! Access Control
+//!
+//! Restricts route groups to networks, such as the admin routes to internal addresses. The access
+//! list with the longest prefix matching a request's path applies to it. Its rules are evaluated in
+//! order and the first one matching the client's address decides; a request no rule matches is
+//! allowed, so a list meant to admit only some networks ends with `deny all`. A denied request gets
+//! a 403 (Forbidden). Access control is a [`Middleware`] layer.
+//!
+//! The client's address is the connection's peer address or, from a trusted proxy, the address the
+//! proxy reports (see [`proxy`] and [`forwarded`]). Only `all` matches a request whose client
+//! address is unknown.
+
+use std::net::IpAddr;
+
+use crate::config::{AccessAction, AccessLists};
+
+use super::*;
+use middleware::Middleware;
+
+/// Decides which clients may make a request
+#[derive(Debug, Default)]
+pub(super) struct AccessControl {
+    lists: AccessLists,
+}
+
+impl AccessControl {
+    pub(super) fn new(lists: &AccessLists) -> AccessControl {
+        AccessControl {
+            lists: lists.clone(),
+        }
+    }
+
+    /// Whether the request's client may make it
+    pub(super) fn permits(&self, request: &Request) -> bool {
+        self.permits_at(request.target.path(), request.client.ip)
+    }
+
+    // Whether a client at `ip` may request `path`
+    fn permits_at(&self, path: &str, ip: Option<IpAddr>) -> bool {
+        let Some(list) = self
+            .lists
+            .lists
+            .iter()
+            .filter(|list| path.starts_with(&list.prefix))
+            .max_by_key(|list| list.prefix.len())
+        else {
+            return true;
+        };
+        list.rules
+            .iter()
+            .find(|rule| match (rule.network, ip) {
+                (None, _) => true,
+                (Some(network), Some(ip)) => network.contains(ip),
+                (Some(_), None) => false,
+            })
+            .is_none_or(|rule| rule.action == AccessAction::Allow)
+    }
+}
+
+impl Middleware for AccessControl {
+    fn before(&self, request: &Request) -> Option<Response> {
+        if self.permits(request) {
+            return None;
+        }
+        warn!(
+            "access: denied {:?} a request for {}",
+            request.client.ip, request.target
+        );
+        Some(Response::text(Status::FORBIDDEN, "forbidden\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::AccessList;
+
+    use super::*;
+
+    fn access() -> AccessControl {
+        let list = |prefix: &str, rules: &[&str]| AccessList {
+            name: prefix.to_string(),
+            prefix: prefix.to_string(),
+            rules: rules.iter().map(|rule| rule.parse().unwrap()).collect(),
+        };
+        AccessControl::new(&AccessLists {
+            lists: vec![
+                list("/admin/", &["allow 10.0.0.0/8", "allow ::1", "deny all"]),
+                list("/admin/metrics", &["allow 192.168.0.0/16", "deny all"]),
+                list("/", &["deny 203.0.113.0/24"]),
+            ],
+        })
+    }
+
+    #[test]
+    fn rules() {
+        let access = access();
+        let ip = |ip: &str| ip.parse::<IpAddr>().ok();
+        assert!(access.permits_at("/admin/log", ip("10.1.2.3")));
+        assert!(access.permits_at("/admin/log", ip("::ffff:10.1.2.3")));
+        assert!(access.permits_at("/admin/log", ip("::1")));
+        assert!(!access.permits_at("/admin/log", ip("192.168.1.1")));
+        assert!(!access.permits_at("/admin/log", None));
+        // The longest matching prefix decides
+        assert!(access.permits_at("/admin/metrics", ip("192.168.1.1")));
+        assert!(!access.permits_at("/admin/metrics", ip("10.1.2.3")));
+        // Allowed when no rule matches
+        assert!(access.permits_at("/", ip("198.51.100.1")));
+        assert!(access.permits_at("/", None));
+        assert!(!access.permits_at("/", ip("203.0.113.9")));
+        assert!(AccessControl::default().permits_at("/admin/log", None));
+        let mut request = Request::parse(&["GET /admin/log HTTP/1.1".to_string()]).unwrap();
+        request.client.ip = ip("192.168.1.1");
+        assert_eq!(
+            Some(Status::FORBIDDEN),
+            access.before(&request).map(|r| r.status)
+        );
+    }
+}
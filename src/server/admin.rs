@@ -0,0 +1,467 @@
+//! Administrative Routes
+//!
+//! Admin routes are disabled unless an admin token is configured, in which case every request must
+//! present it as a bearer token (`Authorization: Bearer <token>`). The token is checked by the
+//! [`Authorization`] middleware layer, run for the requests under [`PREFIX`].
+//!
+//! * `GET /admin/log` returns the log levels as `RUST_LOG`-style directives
+//! * `PUT /admin/log?level=<level>[&target=<target>]` sets the global or a per-target level
+//! * `DELETE /admin/log?target=<target>` removes a per-target level
+//! * `GET /admin/pool` returns the thread pool's statistics, followed by each worker's, then the
+//!   connection counts
+//! * `GET /admin/pool/{worker}` returns one worker's statistics
+//! * `GET /admin/metrics` returns the same statistics in the Prometheus text format
+//!
+//! The log level parameters may also be sent in the body of a `PUT` or `DELETE`, as a form or as a
+//! JSON object (e.g. `{"level": "debug", "target": "ptodd::server"}`).
+
+use crate::logger::{parse_level, SimpleLogger};
+
+use super::*;
+use connections::{ConnectionLimits, ConnectionStats};
+use extract::{Fields, Form, Json, PathParams, Query};
+use middleware::Middleware;
+use pool::{PoolMonitor, PoolStats, WorkerStats};
+
+/// Path prefix shared by all admin routes
+pub(super) const PREFIX: &str = "/admin/";
+
+#[derive(Debug, Clone)]
+pub(super) struct Admin {
+    token: Option<String>,
+    pool: PoolMonitor,
+    connections: Arc<ConnectionLimits>,
+}
+
+impl Admin {
+    pub(super) fn new(
+        token: Option<String>,
+        pool: PoolMonitor,
+        connections: Arc<ConnectionLimits>,
+    ) -> Admin {
+        Admin {
+            token: token.filter(|token| !token.is_empty()),
+            pool,
+            connections,
+        }
+    }
+
+    /// The middleware layer requiring the admin token
+    pub(super) fn authorization(&self) -> Authorization {
+        Authorization {
+            token: self.token.clone(),
+        }
+    }
+
+    /// Adds the admin routes to a router, unless they are disabled. Their requests must pass the
+    /// [`Authorization`] layer.
+    pub(super) fn routes(self, router: Router) -> Router {
+        if self.token.is_none() {
+            return router;
+        }
+        let (pool, worker) = (self.clone(), self.clone());
+        router
+            .get("/admin/log", log_levels)
+            .with_route(RequestMethod::Put, "/admin/log", log_levels)
+            .with_route(RequestMethod::Delete, "/admin/log", log_levels)
+            .get("/admin/pool", move |_: &Request| {
+                pool_stats(&pool.pool.stats(), &pool.connections.stats())
+            })
+            .get("/admin/pool/{worker}", move |request: &Request| {
+                worker.worker_stats(request)
+            })
+            .get("/admin/metrics", move |_: &Request| {
+                Response::new(Status::OK)
+                    .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                    .with_body(metrics(&self.pool.stats(), &self.connections.stats()))
+            })
+    }
+
+    // Describes the worker named by the path
+    fn worker_stats(&self, request: &Request) -> Result<Response> {
+        let id: usize = request.extract::<PathParams>()?.0.get("worker")?;
+        let stats = self.pool.stats();
+        match stats.per_worker.iter().find(|worker| worker.id == id) {
+            Some(worker) => Ok(Response::text(Status::OK, format!("{worker}\n"))),
+            None => Err(not_found(format!("no worker {id}"))),
+        }
+    }
+}
+
+/// Rejects the requests that do not present the admin token with a 401 (Unauthorized). Without a
+/// token, the admin routes are disabled and every request is let through to find none.
+#[derive(Debug)]
+pub(super) struct Authorization {
+    token: Option<String>,
+}
+
+impl Middleware for Authorization {
+    fn before(&self, request: &Request) -> Option<Response> {
+        let token = self.token.as_ref()?;
+        if is_authorized(request, token) {
+            return None;
+        }
+        warn!(
+            "admin: rejected unauthorized request for {}",
+            request.target
+        );
+        Some(
+            Response::text(Status::UNAUTHORIZED, "unauthorized\n")
+                .with_header("WWW-Authenticate", "Bearer realm=\"admin\""),
+        )
+    }
+}
+
+// Describes the pool, then each of its workers on a line of its own, then the connections
+fn pool_stats(stats: &PoolStats, connections: &ConnectionStats) -> String {
+    let mut text = format!("{stats}\n");
+    for worker in &stats.per_worker {
+        text.push_str(&format!("{worker}\n"));
+    }
+    text.push_str(&format!("{connections}\n"));
+    text
+}
+
+// Renders the pool's and the connections' statistics as Prometheus metrics
+//
+// Cf. <https://prometheus.io/docs/instrumenting/exposition_formats/>
+fn metrics(stats: &PoolStats, connections: &ConnectionStats) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+        text.push_str(&format!(
+            "# HELP ptodd_{name} {help}\n# TYPE ptodd_{name} {kind}\n"
+        ));
+        for (labels, value) in samples {
+            text.push_str(&format!("ptodd_{name}{labels} {value}\n"));
+        }
+    };
+    let single = |value: usize| [(String::new(), value.to_string())];
+    metric(
+        "pool_workers",
+        "gauge",
+        "Workers running.",
+        &single(stats.workers),
+    );
+    metric(
+        "pool_workers_min",
+        "gauge",
+        "Minimum workers.",
+        &single(stats.min_workers),
+    );
+    metric(
+        "pool_workers_max",
+        "gauge",
+        "Maximum workers.",
+        &single(stats.max_workers),
+    );
+    metric(
+        "pool_busy",
+        "gauge",
+        "Workers running a job.",
+        &single(stats.busy),
+    );
+    metric(
+        "pool_queued",
+        "gauge",
+        "Jobs waiting for a worker.",
+        &single(stats.queued),
+    );
+    metric(
+        "pool_queue_depth",
+        "gauge",
+        "Most jobs that may wait.",
+        &single(stats.queue_depth),
+    );
+    metric(
+        "pool_rejected_total",
+        "counter",
+        "Jobs refused.",
+        &single(stats.rejected),
+    );
+    metric(
+        "pool_spawned_total",
+        "counter",
+        "Workers started.",
+        &single(stats.spawned),
+    );
+    metric(
+        "pool_retired_total",
+        "counter",
+        "Idle workers exited.",
+        &single(stats.retired),
+    );
+    metric(
+        "pool_panics_total",
+        "counter",
+        "Jobs that panicked.",
+        &single(stats.panics),
+    );
+    metric(
+        "pool_overdue_total",
+        "counter",
+        "Jobs that ran past their deadline.",
+        &single(stats.overdue),
+    );
+    metric(
+        "pool_jobs_completed_total",
+        "counter",
+        "Jobs finished.",
+        &single(stats.completed),
+    );
+    metric(
+        "pool_job_seconds_total",
+        "counter",
+        "Time spent running finished jobs.",
+        &[(String::new(), stats.job_time.as_secs_f64().to_string())],
+    );
+    let per_worker = |value: &dyn Fn(&WorkerStats) -> String| {
+        stats
+            .per_worker
+            .iter()
+            .map(|worker| (format!("{{worker=\"{}\"}}", worker.id), value(worker)))
+            .collect::<Vec<_>>()
+    };
+    metric(
+        "pool_worker_jobs_completed_total",
+        "counter",
+        "Jobs finished by each worker.",
+        &per_worker(&|worker| worker.completed.to_string()),
+    );
+    metric(
+        "pool_worker_job_seconds_total",
+        "counter",
+        "Time each worker spent running jobs.",
+        &per_worker(&|worker| worker.job_time.as_secs_f64().to_string()),
+    );
+    metric(
+        "pool_worker_longest_job_seconds",
+        "gauge",
+        "The longest job each worker has run.",
+        &per_worker(&|worker| worker.longest_job.as_secs_f64().to_string()),
+    );
+    metric(
+        "pool_worker_running_job_seconds",
+        "gauge",
+        "How long each worker's current job has been running; 0 when idle.",
+        &per_worker(&|worker| worker.running.unwrap_or_default().as_secs_f64().to_string()),
+    );
+    metric(
+        "connections_open",
+        "gauge",
+        "Connections open.",
+        &single(connections.open),
+    );
+    metric(
+        "connections_max",
+        "gauge",
+        "Most connections open at once.",
+        &single(connections.max),
+    );
+    metric(
+        "connections_max_per_ip",
+        "gauge",
+        "Most connections open at once from one address.",
+        &single(connections.max_per_ip),
+    );
+    metric(
+        "connections_refused_total",
+        "counter",
+        "Connections refused because a connection limit was reached.",
+        &[
+            (
+                "{limit=\"global\"}".to_string(),
+                connections.refused.to_string(),
+            ),
+            (
+                "{limit=\"per_ip\"}".to_string(),
+                connections.refused_per_ip.to_string(),
+            ),
+        ],
+    );
+    text
+}
+
+// Reads or adjusts the log levels
+fn log_levels(request: &Request) -> Result<Response> {
+    let Some(levels) = SimpleLogger::levels() else {
+        return Err(not_found("logger not installed"));
+    };
+    let params = level_params(request)?;
+    let invalid = |e: crate::Error| Error::InvalidRequest(e.to_string());
+    match request.method {
+        RequestMethod::Put => {
+            let level = parse_level(&params.get::<String>("level")?).map_err(invalid)?;
+            match params.value("target") {
+                Some(target) => levels.set_target(target, level).map_err(invalid)?,
+                None => levels.set_global(level),
+            }
+        }
+        RequestMethod::Delete => {
+            let target: String = params.get("target")?;
+            if !levels.clear_target(&target) {
+                return Err(not_found(format!("no level set for target '{target}'")));
+            }
+        }
+        _ => return Ok(Response::text(Status::OK, format!("{levels}\n"))),
+    }
+    warn!("admin: log levels changed to '{levels}'");
+    Ok(Response::text(Status::OK, format!("{levels}\n")))
+}
+
+// Helper function that reads the log level parameters from the request's body, as JSON or a form,
+// or else from its query
+fn level_params(request: &Request) -> Result<Fields> {
+    if request.body.is_empty() {
+        return Ok(request.extract::<Query>()?.0);
+    }
+    match extract::media_type(request).as_deref() {
+        Some(extract::JSON) => request.extract::<Json>()?.fields(),
+        _ => Ok(request.extract::<Form>()?.0),
+    }
+}
+
+// Helper function that builds the error for an admin resource that does not exist
+fn not_found(message: impl Into<String>) -> Error {
+    Error::Rejected(Status::NOT_FOUND.0, message.into())
+}
+
+// Helper function that checks the request's bearer token against the configured token
+fn is_authorized(request: &Request, token: &str) -> bool {
+    request
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|presented| constant_time_eq(presented.trim().as_bytes(), token.as_bytes()))
+}
+
+// Helper function that compares two byte strings in time independent of where they first differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(lines: &[&str]) -> Request {
+        Request::parse(
+            &lines
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    // Routes a request through the admin routes alone, as if it had passed the authorization layer
+    fn route(admin: Admin, request_line: &str) -> Response {
+        Router::admin(PathBuf::from("."), admin).route(&mut request(&[request_line]))
+    }
+
+    fn connections() -> Arc<ConnectionLimits> {
+        Arc::new(ConnectionLimits::new(10, 2, Vec::new()))
+    }
+
+    fn admin(token: &str) -> Admin {
+        let pool = ThreadPool::build(PoolSize::fixed(1)).unwrap();
+        Admin::new(Some(token.to_string()), pool.monitor(), connections())
+    }
+
+    #[test]
+    fn authorization() {
+        let authorization = admin("secret").authorization();
+        let denied = authorization
+            .before(&request(&[
+                "GET /admin/log HTTP/1.1",
+                "Authorization: Bearer wrong",
+            ]))
+            .unwrap();
+        assert_eq!(Status::UNAUTHORIZED, denied.status);
+        let missing = authorization
+            .before(&request(&["GET /admin/log HTTP/1.1"]))
+            .unwrap();
+        assert_eq!(Status::UNAUTHORIZED, missing.status);
+        assert!(authorization
+            .before(&request(&[
+                "GET /admin/log HTTP/1.1",
+                "Authorization: Bearer secret",
+            ]))
+            .is_none());
+        assert!(is_authorized(
+            &request(&["GET /admin/log HTTP/1.1", "authorization: Bearer secret"]),
+            "secret"
+        ));
+    }
+
+    #[test]
+    fn disabled_without_token() {
+        let admin = admin("");
+        let unauthorized = request(&["GET /admin/log HTTP/1.1"]);
+        assert!(admin.authorization().before(&unauthorized).is_none());
+        assert_eq!(
+            Status::NOT_FOUND,
+            route(admin, "GET /admin/log HTTP/1.1").status
+        );
+    }
+
+    #[test]
+    fn pool_stats() {
+        let pool = ThreadPool::build(PoolSize::fixed(1)).unwrap();
+        let admin = Admin::new(Some("secret".to_string()), pool.monitor(), connections());
+        let response = route(admin.clone(), "GET /admin/pool HTTP/1.1");
+        assert_eq!(Status::OK, response.status);
+        assert!(String::from_utf8(response.body)
+            .unwrap()
+            .starts_with("workers 1 (min 1, max 1), busy 0, pending 0, queued 0"));
+        let worker = route(admin.clone(), "GET /admin/pool/0 HTTP/1.1");
+        assert_eq!(Status::OK, worker.status);
+        assert!(String::from_utf8(worker.body)
+            .unwrap()
+            .starts_with("worker 0: idle"));
+        let missing = route(admin.clone(), "GET /admin/pool/7 HTTP/1.1");
+        assert_eq!(Status::NOT_FOUND, missing.status);
+        let invalid = route(admin.clone(), "GET /admin/pool/first HTTP/1.1");
+        assert_eq!(Status::BAD_REQUEST, invalid.status);
+        let other = route(admin, "POST /admin/pool HTTP/1.1");
+        assert_eq!(Status::METHOD_NOT_ALLOWED, other.status);
+    }
+
+    #[test]
+    fn metrics() {
+        let pool = ThreadPool::build(PoolSize::fixed(1)).unwrap();
+        pool.execute(|_| {}).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().completed < 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let admin = Admin::new(Some("secret".to_string()), pool.monitor(), connections());
+        let response = route(admin, "GET /admin/metrics HTTP/1.1");
+        assert_eq!(Status::OK, response.status);
+        let text = String::from_utf8(response.body).unwrap();
+        assert!(text.contains("# TYPE ptodd_pool_workers gauge\nptodd_pool_workers 1\n"));
+        assert!(text.contains("\nptodd_pool_jobs_completed_total 1\n"));
+        assert!(text.contains("\nptodd_pool_worker_jobs_completed_total{worker=\"0\"} 1\n"));
+        assert!(text.contains("\nptodd_connections_refused_total{limit=\"per_ip\"} 0\n"));
+    }
+
+    #[test]
+    fn level_params() {
+        let body = |content_type: &str, body: &str| {
+            let mut request = request(&["PUT /admin/log?level=warn HTTP/1.1", content_type]);
+            request.body = body.as_bytes().to_vec();
+            super::level_params(&request)
+        };
+        let query = super::level_params(&request(&["PUT /admin/log?level=warn HTTP/1.1"]));
+        assert_eq!(Some("warn"), query.unwrap().value("level"));
+        let form = body(
+            "Content-Type: application/x-www-form-urlencoded",
+            "level=debug",
+        );
+        assert_eq!(Some("debug"), form.unwrap().value("level"));
+        let json = body("Content-Type: application/json", r#"{"level": "trace"}"#);
+        assert_eq!(Some("trace"), json.unwrap().value("level"));
+        assert!(matches!(
+            body("Content-Type: text/plain", "level=debug"),
+            Err(Error::InvalidRequest(_))
+        ));
+    }
+}
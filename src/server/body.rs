@@ -0,0 +1,151 @@
+//! Request Body Reading
+//!
+//! Reads the body of a request (RFC-9112 6) from a connection once its head has been read. Only
+//! bodies framed by a `Content-Length` header field are supported:
+//!
+//! * a request without `Content-Length` has no body
+//! * a `Content-Length` that is not a number, or several that disagree, get a 400 (Bad Request)
+//! * a body larger than `max_body_size` gets a 413 (Content Too Large) before any of it is read
+//! * a request with a `Transfer-Encoding` (e.g. chunked) gets a 501 (Not Implemented)
+//! * a body not received before the socket's read timeout gets a 408 (Request Timeout)
+//!
+//! Cf. <https://datatracker.ietf.org/doc/html/rfc9112#name-message-body>
+
+use super::*;
+
+/// Reads the body of a request whose head has been read, of at most `max` bytes
+pub(super) fn read_body(
+    reader: &mut impl BufRead,
+    request: &Request,
+    max: usize,
+) -> Result<Vec<u8>> {
+    if request.header("Transfer-Encoding").is_some() {
+        return Err(rejected(
+            Status::NOT_IMPLEMENTED,
+            "transfer codings are not supported",
+        ));
+    }
+    let Some(length) = content_length(request)? else {
+        return Ok(Vec::new());
+    };
+    if length > max {
+        return Err(rejected(
+            Status::CONTENT_TOO_LARGE,
+            format!("request body larger than {max} bytes"),
+        ));
+    }
+    let mut body = Vec::with_capacity(length);
+    match reader.take(length as u64).read_to_end(&mut body) {
+        Ok(_) => {}
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            return Err(rejected(
+                Status::REQUEST_TIMEOUT,
+                "request body not received in time",
+            ))
+        }
+        Err(e) => return Err(Error::Io(e)),
+    }
+    if body.len() < length {
+        return Err(rejected(
+            Status::BAD_REQUEST,
+            "connection closed within the body",
+        ));
+    }
+    Ok(body)
+}
+
+// Helper function that reads the request's `Content-Length`, which may be repeated, or given as a
+// list, as long as every value is the same (Cf. RFC-9110 8.6)
+fn content_length(request: &Request) -> Result<Option<usize>> {
+    let mut length = None;
+    for value in request
+        .header_values("Content-Length")
+        .flat_map(|value| value.split(','))
+    {
+        let value = value.trim();
+        let parsed = match value.bytes().all(|byte| byte.is_ascii_digit()) {
+            true => value.parse::<usize>().ok(),
+            false => None,
+        }
+        .ok_or_else(|| {
+            rejected(
+                Status::BAD_REQUEST,
+                format!("invalid Content-Length: '{value}'"),
+            )
+        })?;
+        if length.is_some_and(|length| length != parsed) {
+            return Err(rejected(
+                Status::BAD_REQUEST,
+                "conflicting Content-Length values",
+            ));
+        }
+        length = Some(parsed);
+    }
+    Ok(length)
+}
+
+// Helper function that builds the error for a rejected request body
+fn rejected(status: Status, message: impl Into<String>) -> Error {
+    Error::Rejected(status.0, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(head: &[&str], body: &[u8], max: usize) -> Result<Vec<u8>> {
+        let head: Vec<_> = head.iter().map(|line| line.to_string()).collect();
+        let request = Request::parse(&head).unwrap();
+        read_body(&mut io::Cursor::new(body), &request, max)
+    }
+
+    fn status(result: Result<Vec<u8>>) -> u16 {
+        match result {
+            Err(Error::Rejected(status, _)) => status,
+            other => panic!("expected a rejection, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn content_length() {
+        let post = "POST / HTTP/1.1";
+        assert!(read(&[post], b"ignored", 16).unwrap().is_empty());
+        assert_eq!(
+            b"a=1".to_vec(),
+            read(&[post, "Content-Length: 3"], b"a=1&b=2", 16).unwrap()
+        );
+        assert_eq!(
+            b"a=1".to_vec(),
+            read(
+                &[post, "Content-Length: 3, 3", "Content-Length: 3"],
+                b"a=1",
+                16
+            )
+            .unwrap()
+        );
+        assert_eq!(400, status(read(&[post, "Content-Length: +3"], b"a=1", 16)));
+        assert_eq!(
+            400,
+            status(read(
+                &[post, "Content-Length: 3", "Content-Length: 4"],
+                b"a=1",
+                16
+            ))
+        );
+        assert_eq!(400, status(read(&[post, "Content-Length: 4"], b"a=1", 16)));
+        assert_eq!(413, status(read(&[post, "Content-Length: 17"], b"", 16)));
+        assert_eq!(
+            501,
+            status(read(
+                &[post, "Transfer-Encoding: chunked"],
+                b"0\r\n\r\n",
+                16
+            ))
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Connection Limits
+//!
+//! Caps the connections open at once, in total and from any one address, so that a client opening
+//! connections faster than it sends requests cannot exhaust the server's file descriptors or
+//! workers. Each accepted connection takes a [`ConnectionSlot`], given back when the connection is
+//! closed; a connection over either cap is refused with a minimal 503 as soon as it is accepted.
+//!
+//! The per-address cap counts the connection's peer address: the client's own address is not known
+//! until the request is read. A reverse proxy relays many clients' connections from one address, so
+//! connections from trusted proxies (see [`proxy`]) count towards the total only, as do Unix domain
+//! socket connections.
+
+use std::{collections::HashMap, net::IpAddr};
+
+use super::*;
+use crate::cidr::Cidr;
+
+/// Counts the open connections against the configured caps
+#[derive(Debug)]
+pub(super) struct ConnectionLimits {
+    max: usize,
+    max_per_ip: usize,
+    /// The networks of the trusted proxies, exempt from the per-address cap
+    exempt: Vec<Cidr>,
+    open: Mutex<Open>,
+    refused: AtomicUsize,
+    refused_per_ip: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct Open {
+    total: usize,
+    /// Open connections by peer address; addresses with none are removed
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Which cap a connection was refused by
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum Refusal {
+    Global,
+    PerIp,
+}
+
+/// A snapshot of the connection counts
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) struct ConnectionStats {
+    /// Connections open
+    pub(super) open: usize,
+    /// Addresses with connections open
+    pub(super) clients: usize,
+    pub(super) max: usize,
+    pub(super) max_per_ip: usize,
+    /// Connections refused because the total cap was reached
+    pub(super) refused: usize,
+    /// Connections refused because their address' cap was reached
+    pub(super) refused_per_ip: usize,
+}
+
+/// An open connection's place in the counts, given back when dropped
+#[derive(Debug)]
+pub(super) struct ConnectionSlot {
+    limits: Arc<ConnectionLimits>,
+    ip: Option<IpAddr>,
+}
+
+impl ConnectionLimits {
+    /// Creates the caps, exempting the addresses in `trusted_proxies` from the per-address one
+    pub(super) fn new(
+        max: usize,
+        max_per_ip: usize,
+        trusted_proxies: Vec<Cidr>,
+    ) -> ConnectionLimits {
+        ConnectionLimits {
+            max,
+            max_per_ip,
+            exempt: trusted_proxies,
+            open: Mutex::new(Open::default()),
+            refused: AtomicUsize::new(0),
+            refused_per_ip: AtomicUsize::new(0),
+        }
+    }
+
+    /// Takes a slot for a connection from `ip` (`None` for a Unix domain socket), unless a cap has
+    /// been reached
+    pub(super) fn admit(
+        self: &Arc<Self>,
+        ip: Option<IpAddr>,
+    ) -> result::Result<ConnectionSlot, Refusal> {
+        let ip = ip
+            .map(|ip| ip.to_canonical())
+            .filter(|&ip| !self.exempt.iter().any(|cidr| cidr.contains(ip)));
+        let mut open = pool::lock(&self.open);
+        if open.total >= self.max {
+            self.refused.fetch_add(1, Ordering::Relaxed);
+            return Err(Refusal::Global);
+        }
+        if let Some(ip) = ip {
+            let count = open.per_ip.entry(ip).or_default();
+            if *count >= self.max_per_ip {
+                self.refused_per_ip.fetch_add(1, Ordering::Relaxed);
+                return Err(Refusal::PerIp);
+            }
+            *count += 1;
+        }
+        open.total += 1;
+        Ok(ConnectionSlot {
+            limits: Arc::clone(self),
+            ip,
+        })
+    }
+
+    pub(super) fn stats(&self) -> ConnectionStats {
+        let open = pool::lock(&self.open);
+        ConnectionStats {
+            open: open.total,
+            clients: open.per_ip.len(),
+            max: self.max,
+            max_per_ip: self.max_per_ip,
+            refused: self.refused.load(Ordering::Relaxed),
+            refused_per_ip: self.refused_per_ip.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut open = pool::lock(&self.limits.open);
+        open.total -= 1;
+        if let Some(ip) = self.ip {
+            if let Some(count) = open.per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    open.per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refusal::Global => write!(f, "too many connections"),
+            Refusal::PerIp => write!(f, "too many connections from this address"),
+        }
+    }
+}
+
+impl fmt::Display for ConnectionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "connections {} (max {}), clients {} (max {} each), refused {}, refused per client {}",
+            self.open, self.max, self.clients, self.max_per_ip, self.refused, self.refused_per_ip
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps() {
+        let limits = Arc::new(ConnectionLimits::new(3, 2, Vec::new()));
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "::ffff:10.0.0.2".parse().unwrap();
+        let first = limits.admit(Some(a)).unwrap();
+        let _second = limits.admit(Some(a)).unwrap();
+        assert_eq!(Refusal::PerIp, limits.admit(Some(a)).unwrap_err());
+        let _third = limits.admit(Some(b)).unwrap();
+        assert_eq!(Refusal::Global, limits.admit(None).unwrap_err());
+        drop(first);
+        let _unix = limits.admit(None).unwrap();
+        assert_eq!(
+            ConnectionStats {
+                open: 3,
+                clients: 2,
+                max: 3,
+                max_per_ip: 2,
+                refused: 1,
+                refused_per_ip: 1,
+            },
+            limits.stats()
+        );
+    }
+
+    #[test]
+    fn slots_are_given_back() {
+        let limits = Arc::new(ConnectionLimits::new(1, 1, Vec::new()));
+        let ip = "10.0.0.1".parse().ok();
+        drop(limits.admit(ip).unwrap());
+        drop(limits.admit(ip).unwrap());
+        assert_eq!((0, 0), (limits.stats().open, limits.stats().clients));
+    }
+
+    #[test]
+    fn trusted_proxies_are_exempt() {
+        let proxies = vec!["10.0.0.0/24".parse().unwrap()];
+        let limits = Arc::new(ConnectionLimits::new(4, 1, proxies));
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let _proxied: Vec<_> = (0..3).map(|_| limits.admit(Some(proxy)).unwrap()).collect();
+        let _direct = limits.admit(Some(client)).unwrap();
+        // Proxied connections still count towards the total
+        assert_eq!(Refusal::Global, limits.admit(Some(proxy)).unwrap_err());
+        assert_eq!((4, 1), (limits.stats().open, limits.stats().clients));
+    }
+}
@@ -0,0 +1,85 @@
+//! Job Deadlines
+//!
+//! Every job the pool runs is given a [`Deadline`]: a time budget and a cancellation token in one.
+//! Handlers that may take a while poll it and give up once it reports cancellation, which happens
+//! when the budget runs out or when the pool cancels the job (e.g. when abandoning in-flight
+//! requests at shutdown). Cancellation is cooperative; nothing stops a handler that does not poll.
+
+use std::sync::atomic::AtomicBool;
+
+use super::*;
+
+/// How long `Deadline::sleep` sleeps between checks for cancellation
+const SLEEP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A job's time budget and cancellation token. Clones share the token.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Deadline {
+    /// When the budget runs out; `None` for no limit
+    at: Option<Instant>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Deadline {
+    /// A deadline `budget` from now; an overlong budget (e.g. `Duration::MAX`) means no limit
+    pub(super) fn after(budget: Duration) -> Deadline {
+        Deadline {
+            at: Instant::now().checked_add(budget),
+            cancelled: Arc::default(),
+        }
+    }
+
+    /// Cancels the job
+    pub(super) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the job's budget has run out
+    pub(super) fn expired(&self) -> bool {
+        self.at.is_some_and(|at| Instant::now() >= at)
+    }
+
+    /// Whether the job should give up, because it was cancelled or its budget has run out
+    pub(super) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst) || self.expired()
+    }
+
+    /// Sleeps for `duration`, returning early with `false` if the job is cancelled meanwhile
+    pub(super) fn sleep(&self, duration: Duration) -> bool {
+        let until = Instant::now() + duration;
+        loop {
+            if self.is_cancelled() {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= until {
+                return true;
+            }
+            thread::sleep(SLEEP_POLL_INTERVAL.min(until - now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget() {
+        let deadline = Deadline::after(Duration::from_millis(30));
+        assert!(!deadline.is_cancelled());
+        assert!(!deadline.sleep(Duration::from_secs(5)));
+        assert!(deadline.expired() && deadline.is_cancelled());
+    }
+
+    #[test]
+    fn cancel() {
+        let deadline = Deadline::after(Duration::MAX);
+        assert!(deadline.sleep(Duration::from_millis(1)));
+        let token = deadline.clone();
+        token.cancel();
+        assert!(deadline.is_cancelled() && !deadline.expired());
+        assert!(!deadline.sleep(Duration::from_secs(5)));
+        assert!(!Deadline::default().is_cancelled());
+    }
+}
@@ -0,0 +1,305 @@
+//! Event-Driven Connection Handling (Linux)
+//!
+//! With the `epoll` I/O engine, accepted connections wait in an `epoll` set rather than on a
+//! worker thread. A connection is handed to the thread pool only once its request starts to
+//! arrive, so idle connections cost a file descriptor but no thread. The listening sockets and the
+//! server's wakeup self-pipe (see [`super::wakeup`]) are in the same set, so the loop sleeps until
+//! there is something to do, or until the next idle connection is due to be closed.
+//!
+//! Connections that send nothing within the socket timeout are closed, as the threaded engine's
+//! read timeout would. Connections still waiting when the server stops are closed.
+//!
+//! Cf. epoll(7)
+
+use std::{
+    collections::HashMap,
+    net::TcpStream,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        raw::c_int,
+        unix::net::UnixStream,
+    },
+};
+
+use super::*;
+
+/// Most events taken from the kernel per wait
+const MAX_EVENTS: usize = 64;
+
+mod sys {
+    use super::c_int;
+    pub(super) const EPOLL_CLOEXEC: c_int = 0o2000000;
+    pub(super) const EPOLL_CTL_ADD: c_int = 1;
+    pub(super) const EPOLL_CTL_DEL: c_int = 2;
+    pub(super) const EPOLLIN: u32 = 0x001;
+    pub(super) const EPOLLRDHUP: u32 = 0x2000;
+    pub(super) const EINTR: i32 = 4;
+
+    /// `struct epoll_event`, which is packed on x86-64 only
+    #[repr(C)]
+    #[cfg_attr(target_arch = "x86_64", repr(packed))]
+    #[derive(Debug, Copy, Clone, Default)]
+    pub(super) struct EpollEvent {
+        pub(super) events: u32,
+        pub(super) data: u64,
+    }
+}
+
+mod ffi {
+    use super::{c_int, sys::EpollEvent};
+
+    extern "C" {
+        pub(super) fn epoll_create1(flags: c_int) -> c_int;
+        pub(super) fn epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *mut EpollEvent)
+            -> c_int;
+        pub(super) fn epoll_wait(
+            epfd: c_int,
+            events: *mut EpollEvent,
+            max_events: c_int,
+            timeout: c_int,
+        ) -> c_int;
+    }
+}
+
+/// An `epoll` instance: a set of file descriptors to watch for readiness, each with a token
+#[derive(Debug)]
+struct Epoll(OwnedFd);
+
+impl Epoll {
+    fn new() -> io::Result<Epoll> {
+        let fd = unsafe { ffi::epoll_create1(sys::EPOLL_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Epoll(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Watches `fd` for input (or the peer hanging up), reporting it with `token`
+    fn add(&self, fd: RawFd, token: u64) -> io::Result<()> {
+        let mut event = sys::EpollEvent {
+            events: sys::EPOLLIN | sys::EPOLLRDHUP,
+            data: token,
+        };
+        let result =
+            unsafe { ffi::epoll_ctl(self.0.as_raw_fd(), sys::EPOLL_CTL_ADD, fd, &mut event) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Stops watching `fd`
+    fn delete(&self, fd: RawFd) -> io::Result<()> {
+        let mut event = sys::EpollEvent::default();
+        let result =
+            unsafe { ffi::epoll_ctl(self.0.as_raw_fd(), sys::EPOLL_CTL_DEL, fd, &mut event) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` (indefinitely if `None`) for watched descriptors to become ready,
+    /// returning their tokens. A wait interrupted by a signal returns no tokens.
+    fn wait(
+        &self,
+        events: &mut [sys::EpollEvent],
+        timeout: Option<Duration>,
+    ) -> io::Result<Vec<u64>> {
+        let timeout = timeout.map_or(-1, |timeout| {
+            // Rounded up, so that the loop does not wake just before a connection's deadline
+            c_int::try_from(timeout.as_nanos().div_ceil(1_000_000)).unwrap_or(c_int::MAX)
+        });
+        let max_events = c_int::try_from(events.len()).unwrap_or(c_int::MAX);
+        let ready = unsafe {
+            ffi::epoll_wait(self.0.as_raw_fd(), events.as_mut_ptr(), max_events, timeout)
+        };
+        if ready < 0 {
+            let e = io::Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(sys::EINTR) => Ok(Vec::new()),
+                _ => Err(e),
+            };
+        }
+        Ok(events[..ready as usize]
+            .iter()
+            .map(|event| event.data)
+            .collect())
+    }
+}
+
+/// An accepted connection of either kind
+#[derive(Debug)]
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            Stream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+/// A connection waiting for its request
+#[derive(Debug)]
+struct Waiting {
+    stream: Stream,
+    /// The client's address; `None` for a Unix domain socket
+    peer: Option<SocketAddr>,
+    /// The index of the listener that accepted the connection
+    listener: usize,
+    accepted: Instant,
+    /// The connection's place in the connection counts
+    slot: ConnectionSlot,
+}
+
+impl Server {
+    // The epoll engine: waits for connections and for their requests in an `epoll` set, handing
+    // each connection to the thread pool once its request arrives
+    pub(super) fn event_loop(&self, stop: &dyn Fn() -> bool) -> Result<bool> {
+        let epoll = Epoll::new()?;
+        for (token, listener) in self.listeners.iter().enumerate() {
+            epoll.add(listener.socket.as_raw_fd(), token as u64)?;
+        }
+        let wakeup_token = self.listeners.len() as u64;
+        epoll.add(self.wakeup.receiver_fd(), wakeup_token)?;
+        let mut waiting: HashMap<u64, Waiting> = HashMap::new();
+        let mut next_token = wakeup_token + 1;
+        let mut events = [sys::EpollEvent::default(); MAX_EVENTS];
+        while !stop() {
+            if self.restart_requested() {
+                return Ok(true);
+            }
+            let timeout = waiting
+                .values()
+                .map(|connection| {
+                    self.socket_timeout
+                        .saturating_sub(connection.accepted.elapsed())
+                })
+                .min();
+            for token in epoll.wait(&mut events, timeout)? {
+                if token == wakeup_token {
+                    self.wakeup.clear();
+                    continue;
+                }
+                match usize::try_from(token)
+                    .ok()
+                    .filter(|&index| index < self.listeners.len())
+                {
+                    Some(index) => {
+                        self.accept_all(index, &epoll, &mut waiting, &mut next_token);
+                    }
+                    None => {
+                        let Some(connection) = waiting.remove(&token) else {
+                            continue;
+                        };
+                        epoll
+                            .delete(connection.stream.as_raw_fd())
+                            .unwrap_or_else(|e| warn!("epoll: {}", e));
+                        let router = &self.listeners[connection.listener].router;
+                        let slot = connection.slot;
+                        let dispatched = match connection.stream {
+                            Stream::Tcp(stream) => {
+                                self.dispatch(stream, connection.peer, router, slot)
+                            }
+                            Stream::Unix(stream) => self.dispatch(stream, None, router, slot),
+                        };
+                        dispatched.unwrap_or_else(|e| warn!("dispatch: {}", e));
+                    }
+                }
+            }
+            waiting.retain(|_, connection| {
+                let expired = connection.accepted.elapsed() >= self.socket_timeout;
+                if expired {
+                    debug!("Closing idle connection from {:?}", connection.peer);
+                    epoll
+                        .delete(connection.stream.as_raw_fd())
+                        .unwrap_or_else(|e| warn!("epoll: {}", e));
+                }
+                !expired
+            });
+        }
+        Ok(false)
+    }
+
+    // Accepts every pending connection on a listener and adds them to the `epoll` set
+    fn accept_all(
+        &self,
+        index: usize,
+        epoll: &Epoll,
+        waiting: &mut HashMap<u64, Waiting>,
+        next_token: &mut u64,
+    ) {
+        let listener = &self.listeners[index];
+        loop {
+            let accepted = match &listener.socket {
+                Socket::Tcp(socket) => socket
+                    .accept()
+                    .map(|(stream, peer)| (Stream::Tcp(stream), Some(peer))),
+                Socket::Unix(socket) => socket
+                    .accept()
+                    .map(|(stream, _)| (Stream::Unix(stream), None)),
+            };
+            let result = accepted.and_then(|(mut stream, peer)| {
+                // Blocking, with timeouts, for the worker that will handle the request
+                match &stream {
+                    Stream::Tcp(stream) => {
+                        stream.set_nonblocking(false)?;
+                        stream.set_read_timeout(Some(self.socket_timeout))?;
+                        stream.set_write_timeout(Some(self.socket_timeout))?;
+                    }
+                    Stream::Unix(stream) => {
+                        stream.set_nonblocking(false)?;
+                        stream.set_read_timeout(Some(self.socket_timeout))?;
+                        stream.set_write_timeout(Some(self.socket_timeout))?;
+                    }
+                }
+                let Some(slot) = self.admit(&mut stream, peer) else {
+                    return Ok(());
+                };
+                let token = *next_token;
+                *next_token += 1;
+                epoll.add(stream.as_raw_fd(), token)?;
+                waiting.insert(
+                    token,
+                    Waiting {
+                        stream,
+                        peer,
+                        listener: index,
+                        accepted: Instant::now(),
+                        slot,
+                    },
+                );
+                Ok(())
+            });
+            match result {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    warn!("accept ({}): {}", listener.addr, e);
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -7,8 +7,25 @@ pub type Result<T> = result::Result<T, Error>;
 #[derive(Debug)]
 pub enum Error {
     InvalidRequest(String),
+    /// A request refused with the given status code
+    Rejected(u16, String),
     Channel(String),
     Io(std::io::Error),
+    Bind(String, std::io::Error),
+    /// A failure of the server's own, such as a handler panicking
+    Internal(String),
+}
+
+impl Error {
+    /// The status of the response to a request that failed with this error (see [`error_page`])
+    pub(super) fn status(&self) -> Status {
+        match self {
+            Error::InvalidRequest(_) => Status::BAD_REQUEST,
+            Error::Rejected(status, _) => Status(*status),
+            Error::Channel(_) => Status::SERVICE_UNAVAILABLE,
+            Error::Io(_) | Error::Bind(..) | Error::Internal(_) => Status::INTERNAL_SERVER_ERROR,
+        }
+    }
 }
 
 impl<T> From<mpsc::SendError<T>> for Error {
@@ -27,8 +44,11 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::InvalidRequest(e) => write!(f, "invalid request: {e}"),
+            Error::Rejected(status, e) => write!(f, "rejected request ({status}): {e}"),
             Error::Channel(s) => write!(f, "channel: {s}"),
             Error::Io(e) => write!(f, "io: {e}"),
+            Error::Bind(addr, e) => write!(f, "cannot bind {addr}: {e}"),
+            Error::Internal(e) => write!(f, "internal: {e}"),
         }
     }
 }
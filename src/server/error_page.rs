@@ -0,0 +1,265 @@
+//! Error Pages
+//!
+//! Renders the response to a request that failed with an [`Error`], with the status
+//! [`Error::status`] maps it to. Clients whose `Accept` header prefers JSON to HTML get problem
+//! details (RFC 9457) as `application/problem+json`; others get an HTML page from the first of
+//! these templates found in the error pages directory (the site's root unless configured):
+//!
+//! * `<status>.html`, e.g. `404.html`
+//! * `error.html`
+//! * otherwise a built-in page
+//!
+//! Templates are read for each error, so they can be edited while the server runs. In them,
+//! `{{status}}`, `{{title}}` and `{{detail}}` are replaced by the status code, its reason phrase and
+//! a description of the problem, HTML-escaped. Server errors (5xx) are logged, and described to the
+//! client only by their reason phrase.
+//!
+//! Cf. <https://www.rfc-editor.org/rfc/rfc9457>
+
+use crate::json::Value;
+
+use super::*;
+
+/// The page rendered when the error pages directory has no template for an error
+const BUILT_IN_TEMPLATE: &str = "\
+<!DOCTYPE html>
+<html lang=\"en\">
+    <head>
+        <meta charset=\"utf-8\">
+        <title>{{status}} {{title}}</title>
+    </head>
+    <body>
+        <h1>{{title}}</h1>
+        <p>{{detail}}</p>
+    </body>
+</html>
+";
+
+/// The media type of problem details
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Renders errors as responses
+#[derive(Debug, Clone)]
+pub(super) struct ErrorPages {
+    /// The directory containing the templates
+    dir: PathBuf,
+}
+
+impl ErrorPages {
+    pub(super) fn new(dir: PathBuf) -> ErrorPages {
+        ErrorPages { dir }
+    }
+
+    /// The response to a request that failed with `error`, in the format the request prefers;
+    /// `request` is `None` when the request could not be read
+    pub(super) fn render(&self, error: &Error, request: Option<&Request>) -> Response {
+        let status = error.status();
+        let title = match status.reason() {
+            "" => "Error",
+            reason => reason,
+        };
+        let detail = match (status.0, error) {
+            (500.., _) => {
+                match request {
+                    Some(request) => {
+                        error!("{} {} failed: {}", request.method, request.target, error)
+                    }
+                    None => error!("Request failed: {}", error),
+                }
+                None
+            }
+            (_, Error::InvalidRequest(detail) | Error::Rejected(_, detail)) => {
+                Some(detail.as_str())
+            }
+            _ => None,
+        };
+        match request {
+            Some(request) if prefers_json(request) => problem(status, title, detail, request),
+            _ => self.page(status, title, detail),
+        }
+    }
+
+    // Renders the HTML page for an error from its template
+    fn page(&self, status: Status, title: &str, detail: Option<&str>) -> Response {
+        let template = [format!("{}.html", status.0), "error.html".to_string()]
+            .iter()
+            .find_map(|name| match fs::read_to_string(self.dir.join(name)) {
+                Ok(template) => Some(template),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    warn!("Cannot read error page template {}: {}", name, e);
+                    None
+                }
+            })
+            .unwrap_or_else(|| BUILT_IN_TEMPLATE.to_string());
+        let html = template
+            .replace("{{status}}", &status.0.to_string())
+            .replace("{{title}}", &escape_html(title))
+            .replace("{{detail}}", &escape_html(detail.unwrap_or_default()));
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(html)
+    }
+}
+
+// Helper function that renders an error as problem details, for the request's path
+fn problem(status: Status, title: &str, detail: Option<&str>, request: &Request) -> Response {
+    let mut members = vec![
+        ("type".to_string(), Value::String("about:blank".to_string())),
+        ("title".to_string(), Value::String(title.to_string())),
+        ("status".to_string(), Value::Number(f64::from(status.0))),
+    ];
+    if let Some(detail) = detail {
+        members.push(("detail".to_string(), Value::String(detail.to_string())));
+    }
+    members.push((
+        "instance".to_string(),
+        Value::String(request.target.path().to_string()),
+    ));
+    Response::new(status)
+        .with_header("Content-Type", PROBLEM_JSON)
+        .with_body(Value::Object(members).to_string())
+}
+
+// Helper function that decides whether the request's `Accept` header ranks a JSON media type above
+// HTML; without one, HTML is preferred (Cf. RFC-9110 12.5.1)
+fn prefers_json(request: &Request) -> bool {
+    let ranges: Vec<_> = request
+        .header_values("Accept")
+        .flat_map(|value| value.split(','))
+        .filter_map(media_range)
+        .collect();
+    if ranges.is_empty() {
+        return false;
+    }
+    let json = quality(&ranges, PROBLEM_JSON).max(quality(&ranges, extract::JSON));
+    json > quality(&ranges, "text/html")
+}
+
+// Helper function that parses a media range and its weight, e.g. `application/*;q=0.5`
+fn media_range(value: &str) -> Option<(String, f32)> {
+    let mut parts = value.split(';');
+    let range = parts.next()?.trim().to_ascii_lowercase();
+    if !range.contains('/') {
+        return None;
+    }
+    let weight = parts
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+        .map_or(Some(1.0), |(_, q)| q.trim().parse::<f32>().ok())?;
+    Some((range, weight.clamp(0.0, 1.0)))
+}
+
+// Helper function that returns the weight the most specific matching range gives a media type, or
+// 0 if no range matches it
+fn quality(ranges: &[(String, f32)], media_type: &str) -> f32 {
+    let kind = media_type.split('/').next().unwrap_or_default();
+    ranges
+        .iter()
+        .filter_map(|(range, weight)| {
+            let specificity = match range.split_once('/') {
+                _ if range == media_type => 3,
+                Some((range_kind, "*")) if range_kind == kind => 2,
+                Some(("*", "*")) => 1,
+                _ => return None,
+            };
+            Some((specificity, *weight))
+        })
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map_or(0.0, |(_, weight)| weight)
+}
+
+// Helper function that escapes text for inclusion in HTML
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(accept: Option<&str>) -> Request {
+        let mut head = vec!["GET /items/7 HTTP/1.1".to_string()];
+        head.extend(accept.map(|accept| format!("Accept: {accept}")));
+        Request::parse(&head).unwrap()
+    }
+
+    fn body(response: &Response) -> String {
+        String::from_utf8(response.body.clone()).unwrap()
+    }
+
+    #[test]
+    fn negotiation() {
+        assert!(!prefers_json(&request(None)));
+        assert!(!prefers_json(&request(Some("*/*"))));
+        assert!(!prefers_json(&request(Some(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"
+        ))));
+        assert!(prefers_json(&request(Some("application/json"))));
+        assert!(prefers_json(&request(Some(
+            "text/html;q=0.5, application/problem+json"
+        ))));
+        assert!(prefers_json(&request(Some("application/*, text/*;q=0.1"))));
+        assert!(!prefers_json(&request(Some("application/json;q=0, */*"))));
+    }
+
+    #[test]
+    fn problem_details() {
+        let pages = ErrorPages::new(PathBuf::from("/no/such/dir"));
+        let error = Error::InvalidRequest("invalid path parameter 'id': 'x'".to_string());
+        let response = pages.render(&error, Some(&request(Some("application/json"))));
+        assert_eq!(Status::BAD_REQUEST, response.status);
+        assert_eq!(
+            Some(&("Content-Type".to_string(), PROBLEM_JSON.to_string())),
+            response.headers.first()
+        );
+        assert_eq!(
+            r#"{"type":"about:blank","title":"Bad Request","status":400,"detail":"invalid path parameter 'id': 'x'","instance":"/items/7"}"#,
+            body(&response)
+        );
+        // Server errors, a file of the server's own gone missing among them, are not described
+        let error = Error::Io(io::Error::new(io::ErrorKind::NotFound, "hello.html"));
+        let response = pages.render(&error, Some(&request(Some("application/json"))));
+        assert_eq!(Status::INTERNAL_SERVER_ERROR, response.status);
+        assert!(!body(&response).contains("detail"));
+    }
+
+    #[test]
+    fn templates() {
+        let dir = std::env::temp_dir().join(format!("ptodd-error-pages-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("404.html"), "gone").unwrap();
+        fs::write(dir.join("error.html"), "{{status}} {{title}}: {{detail}}").unwrap();
+        let pages = ErrorPages::new(dir.clone());
+        let not_found = Error::Rejected(404, "no page".to_string());
+        assert_eq!(
+            "gone",
+            body(&pages.render(&not_found, Some(&request(None))))
+        );
+        let invalid = Error::InvalidRequest("<script>".to_string());
+        assert_eq!(
+            "400 Bad Request: &lt;script&gt;",
+            body(&pages.render(&invalid, None))
+        );
+        let unavailable = Error::Channel("closed".to_string());
+        assert_eq!(
+            "503 Service Unavailable: ",
+            body(&pages.render(&unavailable, None))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        let built_in = pages.render(&invalid, None);
+        assert!(body(&built_in).contains("<h1>Bad Request</h1>"));
+        assert!(body(&built_in).contains("<p>&lt;script&gt;</p>"));
+    }
+}
@@ -0,0 +1,296 @@
+//! Request Extractors
+//!
+//! Typed access to the parts of a request a handler reads: the parameters its route's pattern
+//! captured from the path ([`PathParams`]), the query ([`Query`]), the header fields ([`Headers`]),
+//! and the body as a form ([`Form`]) or as JSON ([`Json`]). Each is extracted with
+//! [`Request::extract`], and reads its values as any type that implements `FromStr`.
+//!
+//! Every failure, from a body of the wrong content type to a missing value or one that does not
+//! parse, is an `Error::InvalidRequest` naming the problem, which a handler passes on with `?` to
+//! answer with a 400 (Bad Request).
+
+use std::str::FromStr;
+
+use crate::{json, url};
+
+use super::*;
+
+/// A type built from a request
+pub(super) trait FromRequest: Sized {
+    fn from_request(request: &Request) -> Result<Self>;
+}
+
+/// Named values taken from a request
+#[derive(Debug, Clone)]
+pub(super) struct Fields {
+    /// What the values are, as named in errors, e.g. "query parameter"
+    kind: &'static str,
+    /// Whether names are matched case-insensitively, as header field names are
+    ignore_case: bool,
+    values: Vec<(String, String)>,
+}
+
+impl Fields {
+    /// The value with the given name (the first one, if repeated)
+    pub(super) fn value(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(field, _)| match self.ignore_case {
+                true => field.eq_ignore_ascii_case(name),
+                false => field == name,
+            })
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value with the given name, parsed; an error if it is missing or does not parse
+    pub(super) fn get<T: FromStr>(&self, name: &str) -> Result<T> {
+        self.optional(name)?
+            .ok_or_else(|| invalid(format!("missing {} '{name}'", self.kind)))
+    }
+
+    /// The value with the given name, parsed, if present; an error if it does not parse
+    pub(super) fn optional<T: FromStr>(&self, name: &str) -> Result<Option<T>> {
+        self.value(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid {} '{name}': '{value}'", self.kind)))
+            })
+            .transpose()
+    }
+}
+
+/// The parameters captured from the path by the route's pattern, percent-decoded
+#[derive(Debug, Clone)]
+pub(super) struct PathParams(pub(super) Fields);
+
+/// The query's parameters
+#[derive(Debug, Clone)]
+pub(super) struct Query(pub(super) Fields);
+
+/// The header fields, whose names match case-insensitively
+#[derive(Debug, Clone)]
+#[cfg_attr(not(test), allow(dead_code))] // No route reads header fields this way yet
+pub(super) struct Headers(pub(super) Fields);
+
+/// The fields of an `application/x-www-form-urlencoded` body
+#[derive(Debug, Clone)]
+pub(super) struct Form(pub(super) Fields);
+
+/// An `application/json` body
+#[derive(Debug, Clone)]
+pub(super) struct Json(pub(super) json::Value);
+
+impl FromRequest for PathParams {
+    fn from_request(request: &Request) -> Result<Self> {
+        let values = request
+            .params
+            .iter()
+            .map(|(name, value)| {
+                url::percent_decode(value)
+                    .map(|value| (name.clone(), value))
+                    .map_err(|e| invalid(format!("invalid path parameter '{name}': {e}")))
+            })
+            .collect::<Result<_>>()?;
+        Ok(PathParams(Fields {
+            kind: "path parameter",
+            ignore_case: false,
+            values,
+        }))
+    }
+}
+
+impl FromRequest for Query {
+    fn from_request(request: &Request) -> Result<Self> {
+        let values = request
+            .target
+            .query_pairs()
+            .map_err(|e| invalid(format!("invalid query: {e}")))?;
+        Ok(Query(Fields {
+            kind: "query parameter",
+            ignore_case: false,
+            values,
+        }))
+    }
+}
+
+impl FromRequest for Headers {
+    fn from_request(request: &Request) -> Result<Self> {
+        Ok(Headers(Fields {
+            kind: "header field",
+            ignore_case: true,
+            values: request.headers.clone(),
+        }))
+    }
+}
+
+impl FromRequest for Form {
+    fn from_request(request: &Request) -> Result<Self> {
+        let values = url::form_decode(body_text(request, FORM)?)
+            .map_err(|e| invalid(format!("invalid form: {e}")))?;
+        Ok(Form(Fields {
+            kind: "form field",
+            ignore_case: false,
+            values,
+        }))
+    }
+}
+
+impl FromRequest for Json {
+    fn from_request(request: &Request) -> Result<Self> {
+        body_text(request, JSON)?
+            .parse()
+            .map(Json)
+            .map_err(|e| invalid(e.to_string()))
+    }
+}
+
+impl Json {
+    /// The members of a JSON object whose values are strings, numbers or booleans, as fields; an
+    /// error if the body is not an object or a member's value is an array or object. Members whose
+    /// value is `null` are left out.
+    pub(super) fn fields(&self) -> Result<Fields> {
+        let json::Value::Object(members) = &self.0 else {
+            return Err(invalid("expected a JSON object"));
+        };
+        let values = members
+            .iter()
+            .filter_map(|(name, value)| {
+                let value = match value {
+                    json::Value::Null => return None,
+                    json::Value::String(value) => Ok(value.clone()),
+                    json::Value::Bool(_) | json::Value::Number(_) => Ok(value.to_string()),
+                    _ => Err(invalid(format!(
+                        "JSON field '{name}' must be a string, number or boolean"
+                    ))),
+                };
+                Some(value.map(|value| (name.clone(), value)))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Fields {
+            kind: "JSON field",
+            ignore_case: false,
+            values,
+        })
+    }
+}
+
+/// The media type of form bodies
+pub(super) const FORM: &str = "application/x-www-form-urlencoded";
+
+/// The media type of JSON bodies
+pub(super) const JSON: &str = "application/json";
+
+/// The request's media type, lowercase and without parameters, e.g. `text/html` for
+/// `Content-Type: text/HTML; charset=utf-8`
+pub(super) fn media_type(request: &Request) -> Option<String> {
+    let value = request.header("Content-Type")?;
+    let media_type = value.split(';').next().unwrap_or(value).trim();
+    Some(media_type.to_ascii_lowercase())
+}
+
+// Helper function that returns the request's body as text, checking that it is of the given media
+// type
+fn body_text<'a>(request: &'a Request, expected: &str) -> Result<&'a str> {
+    if media_type(request).as_deref() != Some(expected) {
+        return Err(invalid(format!("expected an {expected} body")));
+    }
+    std::str::from_utf8(&request.body).map_err(|_| invalid("request body is not valid UTF-8"))
+}
+
+// Helper function that builds the error for a request an extractor failed on
+fn invalid(message: impl Into<String>) -> Error {
+    Error::InvalidRequest(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(head: &[&str], body: &str) -> Request {
+        let head: Vec<_> = head.iter().map(|line| line.to_string()).collect();
+        let mut request = Request::parse(&head).unwrap();
+        request.body = body.as_bytes().to_vec();
+        request
+    }
+
+    fn message(result: Result<impl fmt::Debug>) -> String {
+        match result {
+            Err(Error::InvalidRequest(message)) => message,
+            other => panic!("expected an invalid request, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn path_and_query() {
+        let mut get = request(&["GET /pool/3?verbose=true&page=x HTTP/1.1"], "");
+        get.params = vec![("worker".to_string(), "%33".to_string())];
+        let params = get.extract::<PathParams>().unwrap().0;
+        assert_eq!(3, params.get::<usize>("worker").unwrap());
+        let query = get.extract::<Query>().unwrap().0;
+        assert!(query.get::<bool>("verbose").unwrap());
+        assert_eq!(None, query.optional::<u32>("limit").unwrap());
+        assert_eq!(
+            "invalid query parameter 'page': 'x'",
+            message(query.optional::<u32>("page"))
+        );
+        assert_eq!(
+            "missing path parameter 'id'",
+            message(params.get::<String>("id"))
+        );
+        get.params = vec![("worker".to_string(), "%C3".to_string())];
+        assert!(message(get.extract::<PathParams>()).starts_with("invalid path parameter"));
+    }
+
+    #[test]
+    fn headers() {
+        let get = request(&["GET / HTTP/1.1", "X-Retries: 2"], "");
+        let headers = get.extract::<Headers>().unwrap().0;
+        assert_eq!(2, headers.get::<u8>("x-retries").unwrap());
+        assert_eq!(
+            "missing header field 'Accept'",
+            message(headers.get::<String>("Accept"))
+        );
+    }
+
+    #[test]
+    fn bodies() {
+        let form = request(
+            &[
+                "PUT / HTTP/1.1",
+                "Content-Type: application/x-www-form-urlencoded",
+            ],
+            "level=debug&target=ptodd%3A%3Aserver",
+        );
+        let fields = form.extract::<Form>().unwrap().0;
+        assert_eq!(Some("ptodd::server"), fields.value("target"));
+        assert_eq!(
+            "expected an application/json body",
+            message(form.extract::<Json>())
+        );
+        let json = request(
+            &[
+                "PUT / HTTP/1.1",
+                "Content-Type: Application/JSON; charset=utf-8",
+            ],
+            r#"{"level": "debug", "retries": 3, "quiet": true, "target": null}"#,
+        );
+        let fields = json.extract::<Json>().unwrap().fields().unwrap();
+        assert_eq!(3, fields.get::<u32>("retries").unwrap());
+        assert!(fields.get::<bool>("quiet").unwrap());
+        assert_eq!(None, fields.value("target"));
+        let nested = request(
+            &["PUT / HTTP/1.1", "Content-Type: application/json"],
+            r#"{"level": ["debug"]}"#,
+        );
+        assert_eq!(
+            "JSON field 'level' must be a string, number or boolean",
+            message(nested.extract::<Json>().unwrap().fields())
+        );
+        let truncated = request(
+            &["PUT / HTTP/1.1", "Content-Type: application/json"],
+            r#"{"level": "#,
+        );
+        assert!(message(truncated.extract::<Json>()).starts_with("invalid JSON"));
+    }
+}
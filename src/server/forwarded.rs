@@ -0,0 +1,285 @@
+//! Forwarded Headers
+//!
+//! HTTP reverse proxies report the client's address, and the scheme and host the client used, in
+//! the standard `Forwarded` header (RFC 7239) or the de facto `X-Forwarded-For`, `X-Forwarded-Proto`
+//! and `X-Forwarded-Host` headers. `Forwarded` is used when both are present.
+//!
+//! Cf. <https://datatracker.ietf.org/doc/html/rfc7239>
+//!
+//! Anyone can send these headers, so they are only believed when the connection comes from a trusted
+//! proxy. Each proxy appends the address it received the request from, so the list is read from the
+//! end: the client is the first address, counting back, that is not a trusted proxy's. A hop that
+//! hid its client (`for=unknown` or an obfuscated identifier) ends the search at the proxy that
+//! reported it.
+
+use std::net::{IpAddr, SocketAddr};
+
+use proxy::TrustedProxies;
+
+use super::*;
+
+/// What is known about the client that made a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct ClientInfo {
+    /// The client's address; unknown for a Unix domain socket connection that was not forwarded
+    pub(super) ip: Option<IpAddr>,
+    /// The scheme the client used: "http" or "https"
+    pub(super) scheme: &'static str,
+    /// The host the client asked for
+    pub(super) host: Option<String>,
+}
+
+impl Default for ClientInfo {
+    fn default() -> Self {
+        ClientInfo {
+            ip: None,
+            scheme: "http",
+            host: None,
+        }
+    }
+}
+
+impl ClientInfo {
+    /// Works out who made a request that arrived from `peer` (the connection's client address, which
+    /// may already have been replaced by a PROXY header)
+    pub(super) fn resolve(
+        request: &Request,
+        peer: Option<IpAddr>,
+        proxies: &TrustedProxies,
+    ) -> ClientInfo {
+        let mut client = ClientInfo {
+            ip: peer,
+            host: request.header("Host").map(String::from),
+            ..ClientInfo::default()
+        };
+        if !proxies.forwarded_headers || !proxies.trusts(peer) {
+            return client;
+        }
+        let forwarded = request.header_values("Forwarded").collect::<Vec<_>>();
+        let hops = if forwarded.is_empty() {
+            let mut hops: Vec<_> = list(request.header_values("X-Forwarded-For"))
+                .map(|node| Hop {
+                    ip: parse_node(node),
+                    ..Hop::default()
+                })
+                .collect();
+            // Reported once, by the nearest proxy, for the request as it first arrived
+            if let Some(last) = hops.last_mut() {
+                last.proto = list(request.header_values("X-Forwarded-Proto")).last();
+                last.host = list(request.header_values("X-Forwarded-Host")).last();
+            }
+            hops
+        } else {
+            list(forwarded.into_iter()).map(parse_element).collect()
+        };
+        for hop in hops.iter().rev() {
+            if let Some(scheme) = hop.proto.and_then(parse_scheme) {
+                client.scheme = scheme;
+            }
+            if let Some(host) = hop.host.filter(|host| !host.is_empty()) {
+                client.host = Some(host.to_string());
+            }
+            match hop.ip {
+                Some(ip) => client.ip = Some(ip),
+                None => break,
+            }
+            if !proxies.trusts(client.ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// One proxy's report of the request it received
+#[derive(Debug, Default)]
+struct Hop<'a> {
+    /// The address the request came from; `None` when hidden or unparseable
+    ip: Option<IpAddr>,
+    proto: Option<&'a str>,
+    host: Option<&'a str>,
+}
+
+// Helper function that parses a `Forwarded` element: `for=192.0.2.60;proto=http;by=203.0.113.43`
+fn parse_element(element: &str) -> Hop<'_> {
+    let mut hop = Hop::default();
+    for pair in split_unquoted(element, ';') {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = unquote(value.trim());
+        match name.trim().to_ascii_lowercase().as_str() {
+            "for" => hop.ip = parse_node(value),
+            "proto" => hop.proto = Some(value),
+            "host" => hop.host = Some(value),
+            _ => {}
+        }
+    }
+    hop
+}
+
+// Helper function that parses a node: an IPv4 address or bracketed IPv6 address, either optionally
+// with a port, or a bare IPv6 address as sent in `X-Forwarded-For`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| {
+            node.strip_prefix('[')
+                .and_then(|node| node.strip_suffix(']'))
+                .unwrap_or_default()
+                .parse()
+        })
+        .ok()
+}
+
+// Helper function that accepts the schemes a client may have used
+fn parse_scheme(proto: &str) -> Option<&'static str> {
+    match proto.to_ascii_lowercase().as_str() {
+        "http" => Some("http"),
+        "https" => Some("https"),
+        _ => None,
+    }
+}
+
+// Helper function that joins the comma separated lists of repeated header fields (Cf. RFC-9110 5.3)
+fn list<'a>(values: impl Iterator<Item = &'a str>) -> impl Iterator<Item = &'a str> {
+    values
+        .flat_map(|value| split_unquoted(value, ','))
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+// Helper function that splits on a separator that is not within a quoted string
+fn split_unquoted(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (at, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(&text[start..at]);
+                start = at + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+// Helper function that removes the quotes around a quoted string. Values of interest here never
+// contain escaped characters, so escapes are left as they are.
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_headers(headers: &[&str]) -> Request {
+        let mut lines = vec![
+            "GET / HTTP/1.1".to_string(),
+            "Host: internal:6502".to_string(),
+        ];
+        lines.extend(headers.iter().map(|header| header.to_string()));
+        Request::parse(&lines).unwrap()
+    }
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::new(
+            vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()],
+            false,
+            true,
+        )
+    }
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn forwarded() {
+        let request = with_headers(&[
+            "Forwarded: for=192.0.2.60;proto=https;host=ptodd.org, for=\"[fd00::1]:4711\"",
+            "Forwarded: for=10.0.0.2;proto=http",
+        ]);
+        let client = ClientInfo::resolve(&request, ip("10.0.0.1"), &proxies());
+        assert_eq!(
+            ClientInfo {
+                ip: ip("192.0.2.60"),
+                scheme: "https",
+                host: Some("ptodd.org".to_string()),
+            },
+            client
+        );
+    }
+
+    #[test]
+    fn x_forwarded() {
+        let request = with_headers(&[
+            "X-Forwarded-For: 198.51.100.7, 192.0.2.60:1234, 10.0.0.2",
+            "X-Forwarded-Proto: https",
+            "X-Forwarded-Host: ptodd.org",
+        ]);
+        let client = ClientInfo::resolve(&request, ip("10.0.0.1"), &proxies());
+        // 198.51.100.7 was reported by 192.0.2.60, which is not trusted to say so
+        assert_eq!(ip("192.0.2.60"), client.ip);
+        assert_eq!("https", client.scheme);
+        assert_eq!(Some("ptodd.org".to_string()), client.host);
+        let request = with_headers(&["X-Forwarded-For: 2001:db8::1"]);
+        assert_eq!(
+            ip("2001:db8::1"),
+            ClientInfo::resolve(&request, None, &proxies()).ip
+        );
+    }
+
+    #[test]
+    fn untrusted() {
+        let request = with_headers(&[
+            "Forwarded: for=192.0.2.60;proto=https",
+            "X-Forwarded-For: 192.0.2.61",
+        ]);
+        let client = ClientInfo::resolve(&request, ip("203.0.113.9"), &proxies());
+        assert_eq!(ip("203.0.113.9"), client.ip);
+        assert_eq!("http", client.scheme);
+        assert_eq!(Some("internal:6502".to_string()), client.host);
+        let disabled = TrustedProxies::new(vec!["10.0.0.0/8".parse().unwrap()], false, false);
+        assert_eq!(
+            ip("10.0.0.1"),
+            ClientInfo::resolve(&request, ip("10.0.0.1"), &disabled).ip
+        );
+    }
+
+    #[test]
+    fn hidden_client() {
+        let request = with_headers(&["Forwarded: for=unknown;proto=https, for=10.0.0.2"]);
+        let client = ClientInfo::resolve(&request, ip("10.0.0.1"), &proxies());
+        assert_eq!(ip("10.0.0.2"), client.ip);
+        assert_eq!("https", client.scheme);
+        let request = with_headers(&["Forwarded: for=_hidden, for=\"_x;y\";proto=ftp"]);
+        let client = ClientInfo::resolve(&request, ip("10.0.0.1"), &proxies());
+        assert_eq!(ip("10.0.0.1"), client.ip);
+        assert_eq!("http", client.scheme);
+    }
+
+    #[test]
+    fn nodes() {
+        assert_eq!(ip("192.0.2.1"), parse_node("192.0.2.1"));
+        assert_eq!(ip("192.0.2.1"), parse_node("192.0.2.1:80"));
+        assert_eq!(ip("2001:db8::1"), parse_node("[2001:db8::1]:4711"));
+        assert_eq!(ip("2001:db8::1"), parse_node("[2001:db8::1]"));
+        assert_eq!(ip("2001:db8::1"), parse_node("2001:db8::1"));
+        assert_eq!(None, parse_node("unknown"));
+        assert_eq!(None, parse_node("_gazonk"));
+        assert_eq!(
+            vec!["a", "\"b,c\"", " d"],
+            split_unquoted("a,\"b,c\", d", ',')
+        );
+    }
+}
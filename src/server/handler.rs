@@ -0,0 +1,115 @@
+//! Handlers
+//!
+//! A handler produces the response to the requests a route matches (see [`router`]). Any function
+//! or closure taking a `&Request` and returning a type that implements [`IntoResponse`] is a
+//! [`Handler`]: a [`Response`], text, a status paired with either, or a `Result` of those, so that
+//! a handler can pass on errors with `?`. An error is answered with its error page (see
+//! [`error_page`]). Handlers read the parts of the request they need with the extractors in
+//! [`extract`].
+
+use super::*;
+
+/// A type that can be sent as a response, or an error to answer with its error page
+pub(super) trait IntoResponse {
+    fn into_response(self) -> Result<Response>;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Result<Response> {
+        Ok(self)
+    }
+}
+
+/// Plain text answering with a 200 (OK)
+impl IntoResponse for String {
+    fn into_response(self) -> Result<Response> {
+        Ok(Response::text(Status::OK, self))
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Result<Response> {
+        Ok(Response::text(Status::OK, self))
+    }
+}
+
+/// A response with its status replaced
+impl<T: IntoResponse> IntoResponse for (Status, T) {
+    fn into_response(self) -> Result<Response> {
+        let (status, response) = self;
+        Ok(Response {
+            status,
+            ..response.into_response()?
+        })
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for result::Result<T, E> {
+    fn into_response(self) -> Result<Response> {
+        match self {
+            Ok(response) => response.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Result<Response> {
+        Err(self)
+    }
+}
+
+/// Answers the requests a route matches
+pub(super) trait Handler: Send + Sync {
+    fn call(&self, request: &Request) -> Result<Response>;
+}
+
+impl<F, R> Handler for F
+where
+    F: Fn(&Request) -> R + Send + Sync,
+    R: IntoResponse,
+{
+    fn call(&self, request: &Request) -> Result<Response> {
+        self(request).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(handler: &dyn Handler) -> Result<Response> {
+        handler.call(&Request::parse(&["GET / HTTP/1.1".to_string()]).unwrap())
+    }
+
+    fn greet(_: &Request) -> &'static str {
+        "hello\n"
+    }
+
+    #[test]
+    fn into_response() {
+        let response = get(&greet).unwrap();
+        assert_eq!(
+            (Status::OK, b"hello\n".to_vec()),
+            (response.status, response.body)
+        );
+        let created = get(&|_: &Request| (Status(201), format!("{}\n", 1 + 1))).unwrap();
+        assert_eq!(
+            (Status(201), b"2\n".to_vec()),
+            (created.status, created.body)
+        );
+        let invalid = get(&|_: &Request| -> Result<Response> {
+            Err(Error::InvalidRequest(
+                "missing query parameter 'id'".to_string(),
+            ))
+        });
+        assert!(matches!(invalid, Err(Error::InvalidRequest(_))));
+        let failed = get(&|_: &Request| {
+            (
+                Status(201),
+                Err::<&'static str, _>(Error::Internal("boom".to_string())),
+            )
+        });
+        assert!(matches!(failed, Err(Error::Internal(_))));
+    }
+}
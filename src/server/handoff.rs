@@ -0,0 +1,283 @@
+//! Listener Socket Handoff
+//!
+//! Supports zero-downtime restarts by passing listening sockets from one server process to the next.
+//! On a restart request the running server re-executes its binary with the listening sockets
+//! inherited (their descriptors are listed in `PTODD_LISTEN_FDS`), stops accepting, and drains its
+//! in-flight requests while the new process accepts on the very same sockets, so no connection is
+//! refused during the switch.
+//!
+//! Sockets passed by systemd socket activation (`LISTEN_FDS` and `LISTEN_PID`) are used the same way.
+//!
+//! The variables are read and cleared by [`ListenFds::take_from_env`] as the process starts, before
+//! it runs any other thread: changing the environment while other threads may read it is unsound.
+//!
+//! An inherited socket is used for a configured bind address when its local address is one the bind
+//! address resolves to, or for a Unix domain socket, when it is bound to the same path; inherited
+//! sockets that match no bind address are closed.
+
+use std::{
+    env,
+    os::{
+        fd::{AsRawFd, RawFd},
+        raw::c_int,
+    },
+    path::PathBuf,
+    process::Command,
+};
+
+use super::*;
+use crate::config::UNIX_SOCKET_PREFIX;
+use listener::Socket;
+
+/// Environment variable listing the descriptors handed to a restarted server
+const LISTEN_FDS_ENV_VAR_NAME: &str = "PTODD_LISTEN_FDS";
+
+/// systemd socket activation: the number of descriptors passed, starting at `SD_LISTEN_FDS_START`
+const SD_LISTEN_FDS_ENV_VAR_NAME: &str = "LISTEN_FDS";
+
+/// systemd socket activation: the process the descriptors are intended for
+const SD_LISTEN_PID_ENV_VAR_NAME: &str = "LISTEN_PID";
+
+/// The first descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// How long a newly started server has to fail before the restart is considered successful
+const STARTUP_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// How often a newly started server is checked for having exited during its grace period
+const STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+const F_GETFD: c_int = 1;
+const F_SETFD: c_int = 2;
+const FD_CLOEXEC: c_int = 1;
+
+extern "C" {
+    fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+}
+
+/// The descriptors of the listening sockets passed to this process through the environment
+#[derive(Debug, Default)]
+pub struct ListenFds(Vec<RawFd>);
+
+impl ListenFds {
+    /// Reads the descriptors listed in the environment, then removes the variables listing them.
+    /// Must be called before the process starts any other thread.
+    pub fn take_from_env() -> ListenFds {
+        let fds = inherited_fds(
+            env::var(LISTEN_FDS_ENV_VAR_NAME).ok().as_deref(),
+            env::var(SD_LISTEN_FDS_ENV_VAR_NAME).ok().as_deref(),
+            env::var(SD_LISTEN_PID_ENV_VAR_NAME).ok().as_deref(),
+            std::process::id(),
+        );
+        // Keep the descriptors from being passed on again to unrelated child processes
+        for name in [
+            LISTEN_FDS_ENV_VAR_NAME,
+            SD_LISTEN_FDS_ENV_VAR_NAME,
+            SD_LISTEN_PID_ENV_VAR_NAME,
+        ] {
+            env::remove_var(name);
+        }
+        ListenFds(fds)
+    }
+}
+
+/// Listening sockets inherited from a previous server process or from systemd
+#[derive(Debug)]
+pub(super) struct Inherited {
+    listeners: Vec<Socket>,
+}
+
+impl Inherited {
+    /// Takes ownership of the listening sockets passed to this process
+    pub(super) fn adopt(fds: ListenFds) -> Inherited {
+        let listeners = fds
+            .0
+            .into_iter()
+            .filter_map(|fd| {
+                // SAFETY: the descriptor was handed to this process for it to own
+                match set_cloexec(fd, true).and_then(|_| unsafe { Socket::from_raw_fd(fd) }) {
+                    Ok(socket) => {
+                        info!("Inherited listening socket {socket} (fd {fd})");
+                        Some(socket)
+                    }
+                    Err(e) => {
+                        warn!("Ignoring inherited descriptor {fd}: {e}");
+                        None
+                    }
+                }
+            })
+            .collect();
+        Inherited { listeners }
+    }
+
+    /// Returns an inherited listener for the bind address, or binds a new one (giving a new Unix
+    /// domain socket the permissions `mode`)
+    pub(super) fn take_or_bind(&mut self, bind: &str, mode: Option<u32>) -> Result<Socket> {
+        let position = match bind.strip_prefix(UNIX_SOCKET_PREFIX) {
+            Some(path) => self
+                .listeners
+                .iter()
+                .position(|socket| socket.unix_path().is_some_and(|p| p == Path::new(path))),
+            None => {
+                let addrs: Vec<_> = bind
+                    .to_socket_addrs()
+                    .map_err(|e| Error::Bind(bind.to_string(), e))?
+                    .collect();
+                self.listeners
+                    .iter()
+                    .position(|socket| socket.tcp_addr().is_some_and(|a| addrs.contains(&a)))
+            }
+        };
+        match position {
+            Some(position) => Ok(self.listeners.swap_remove(position)),
+            None => listener::bind(bind, mode),
+        }
+    }
+}
+
+impl Drop for Inherited {
+    fn drop(&mut self) {
+        for socket in &self.listeners {
+            warn!("Closing inherited listening socket {socket}: it matches no bind address");
+        }
+    }
+}
+
+/// Starts a new server process that inherits the listening sockets
+///
+/// Returns once the new process has survived its start-up grace period, or an error if it could not
+/// be started or exited during it.
+pub(super) fn spawn_successor(listeners: &[&Socket]) -> Result<()> {
+    let fds: Vec<RawFd> = listeners
+        .iter()
+        .map(|listener| listener.as_raw_fd())
+        .collect();
+    for &fd in &fds {
+        set_cloexec(fd, false)?;
+    }
+    let spawned = Command::new(current_exe()?)
+        .args(env::args_os().skip(1))
+        .env(
+            LISTEN_FDS_ENV_VAR_NAME,
+            fds.iter()
+                .map(|fd| fd.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+        .spawn();
+    // Only the child should inherit the descriptors
+    for &fd in &fds {
+        set_cloexec(fd, true)?;
+    }
+    let mut child = spawned?;
+    info!("Started new server process {}", child.id());
+    let deadline = Instant::now() + STARTUP_GRACE_PERIOD;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait()? {
+            return Err(Error::Io(io::Error::other(format!(
+                "new server process exited during start-up: {status}"
+            ))));
+        }
+        thread::sleep(STARTUP_POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+// Helper function that finds the running binary, even if it has since been replaced on disk (Linux
+// reports a replaced binary's path with a " (deleted)" suffix)
+fn current_exe() -> Result<PathBuf> {
+    let exe = env::current_exe()?;
+    match exe
+        .to_str()
+        .and_then(|path| path.strip_suffix(" (deleted)"))
+    {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => Ok(exe),
+    }
+}
+
+// Helper function that sets or clears a descriptor's close-on-exec flag
+fn set_cloexec(fd: RawFd, cloexec: bool) -> io::Result<()> {
+    // SAFETY: fcntl with F_GETFD/F_SETFD has no memory-safety requirements
+    let flags = unsafe { fcntl(fd, F_GETFD) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let flags = if cloexec {
+        flags | FD_CLOEXEC
+    } else {
+        flags & !FD_CLOEXEC
+    };
+    if unsafe { fcntl(fd, F_SETFD, flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Helper function that determines which descriptors were passed to this process
+fn inherited_fds(
+    ptodd_fds: Option<&str>,
+    sd_fds: Option<&str>,
+    sd_pid: Option<&str>,
+    pid: u32,
+) -> Vec<RawFd> {
+    let mut fds: Vec<RawFd> = ptodd_fds
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|fd| fd.trim().parse().ok())
+        .filter(|&fd| fd >= 0)
+        .collect();
+    if sd_pid.and_then(|sd_pid| sd_pid.parse::<u32>().ok()) == Some(pid) {
+        let count: RawFd = sd_fds.and_then(|count| count.parse().ok()).unwrap_or(0);
+        fds.extend(SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count);
+    }
+    fds.sort_unstable();
+    fds.dedup();
+    fds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fds_from_env() {
+        assert!(inherited_fds(None, None, None, 42).is_empty());
+        assert_eq!(vec![5, 7], inherited_fds(Some("7,5,x,-1"), None, None, 42));
+        assert_eq!(vec![3, 4], inherited_fds(None, Some("2"), Some("42"), 42));
+        assert!(inherited_fds(None, Some("2"), Some("41"), 42).is_empty());
+        assert_eq!(
+            vec![3, 4, 9],
+            inherited_fds(Some("9,3"), Some("2"), Some("42"), 42)
+        );
+    }
+
+    #[test]
+    fn take_or_bind() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let path = env::temp_dir().join(format!("ptodd-handoff-{}.sock", std::process::id()));
+        let unix = format!("{UNIX_SOCKET_PREFIX}{}", path.display());
+        let mut inherited = Inherited {
+            listeners: vec![Socket::Tcp(listener), listener::bind(&unix, None).unwrap()],
+        };
+        let taken = inherited.take_or_bind(&addr.to_string(), None).unwrap();
+        assert_eq!(Some(addr), taken.tcp_addr());
+        let taken = inherited.take_or_bind(&unix, None).unwrap();
+        assert_eq!(Some(path.clone()), taken.unix_path());
+        assert!(inherited.listeners.is_empty());
+        let bound = inherited.take_or_bind("127.0.0.1:0", None).unwrap();
+        assert_ne!(Some(addr), bound.tcp_addr());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cloexec() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let fd = listener.as_raw_fd();
+        set_cloexec(fd, false).unwrap();
+        assert_eq!(0, unsafe { fcntl(fd, F_GETFD) } & FD_CLOEXEC);
+        set_cloexec(fd, true).unwrap();
+        assert_eq!(FD_CLOEXEC, unsafe { fcntl(fd, F_GETFD) } & FD_CLOEXEC);
+    }
+}
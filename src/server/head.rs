@@ -0,0 +1,271 @@
+//! Request Head Reading
+//!
+//! Reads the request line and header fields of a request (RFC-9112 2.1) from a connection, byte by
+//! byte rather than as text, within limits that stop a client from holding a worker by sending an
+//! endless line, endless header fields or a trickle of bytes (the Slowloris attack):
+//!
+//! * a request line longer than `max_request_line` gets a 414 (URI Too Long)
+//! * a header field line longer than `max_header_size`, header fields adding up to more than
+//!   `max_header_size`, or more than `max_headers` of them get a 431 (Request Header Fields Too Large)
+//! * a head not received within `header_timeout` gets a 408 (Request Timeout). The time is checked
+//!   whenever bytes arrive, so a silent client is cut off by the socket's read timeout instead.
+//! * a head that is not UTF-8 gets a 400 (Bad Request)
+//!
+//! Cf. <https://datatracker.ietf.org/doc/html/rfc9112#name-message-format>
+
+use super::*;
+
+/// Limits on the requests a client may send
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) struct RequestLimits {
+    /// The longest request line accepted, in bytes, excluding the line ending
+    pub(super) max_request_line: usize,
+    /// The largest total size of the header field lines, in bytes, excluding line endings
+    pub(super) max_header_size: usize,
+    /// The most header fields accepted
+    pub(super) max_headers: usize,
+    /// How long the client may take to send the whole head
+    pub(super) header_timeout: Duration,
+    /// The largest request body accepted, in bytes (see [`body`])
+    pub(super) max_body_size: usize,
+}
+
+#[cfg(test)]
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_request_line: 8192,
+            max_header_size: 32768,
+            max_headers: 100,
+            header_timeout: Duration::from_secs(10),
+            max_body_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Reads a request head, returning its lines without their line endings: the request line, then
+/// the header field lines. Returns no lines if the connection closes before the request starts.
+pub(super) fn read_head(reader: &mut impl BufRead, limits: &RequestLimits) -> Result<Vec<String>> {
+    let deadline = Instant::now() + limits.header_timeout;
+    let mut lines = Vec::new();
+    let Some(request_line) = read_line(reader, limits.max_request_line, deadline)
+        .map_err(|e| too_large(e, Status::URI_TOO_LONG, "request line"))?
+    else {
+        return Ok(lines);
+    };
+    lines.push(request_line);
+    let mut header_size = 0;
+    loop {
+        let remaining = limits.max_header_size - header_size;
+        let line = read_line(reader, remaining, deadline)
+            .map_err(|e| too_large(e, Status::REQUEST_HEADER_FIELDS_TOO_LARGE, "header fields"))?
+            .ok_or_else(|| rejected(Status::BAD_REQUEST, "connection closed within the head"))?;
+        if line.is_empty() {
+            return Ok(lines);
+        }
+        if lines.len() > limits.max_headers {
+            return Err(rejected(
+                Status::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                format!("more than {} header fields", limits.max_headers),
+            ));
+        }
+        header_size += line.len();
+        lines.push(line);
+    }
+}
+
+/// Why a line could not be read
+#[derive(Debug)]
+enum LineError {
+    /// The line is longer than allowed
+    TooLong(usize),
+    Other(Error),
+}
+
+impl From<Error> for LineError {
+    fn from(err: Error) -> Self {
+        LineError::Other(err)
+    }
+}
+
+// Helper function that reads one line of at most `max` bytes, excluding its line ending (LF, or
+// CRLF), before `deadline`. Returns `None` at the end of the input.
+fn read_line(
+    reader: &mut impl BufRead,
+    max: usize,
+    deadline: Instant,
+) -> result::Result<Option<String>, LineError> {
+    let mut line = Vec::new();
+    loop {
+        if Instant::now() >= deadline {
+            return Err(timed_out().into());
+        }
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Err(timed_out().into())
+            }
+            Err(e) => return Err(Error::Io(e).into()),
+        };
+        if available.is_empty() {
+            if line.is_empty() {
+                return Ok(None);
+            }
+            return Err(rejected(Status::BAD_REQUEST, "connection closed within a line").into());
+        }
+        let (chunk, complete) = match available.iter().position(|&byte| byte == b'\n') {
+            Some(end) => (&available[..=end], true),
+            None => (available, false),
+        };
+        line.extend_from_slice(chunk);
+        let used = chunk.len();
+        reader.consume(used);
+        // Allow for the line ending until the line is complete
+        if line.len() > max + 2 {
+            return Err(LineError::TooLong(max));
+        }
+        if complete {
+            break;
+        }
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    if line.len() > max {
+        return Err(LineError::TooLong(max));
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| rejected(Status::BAD_REQUEST, "request head is not valid UTF-8").into())
+}
+
+// Helper function that turns a line that is too long into a rejection with the given status
+fn too_large(err: LineError, status: Status, what: &str) -> Error {
+    match err {
+        LineError::TooLong(max) => rejected(status, format!("{what} longer than {max} bytes")),
+        LineError::Other(err) => err,
+    }
+}
+
+// Helper function that rejects a head that took too long to arrive
+fn timed_out() -> Error {
+    rejected(Status::REQUEST_TIMEOUT, "request head not received in time")
+}
+
+// Helper function that builds the error for a rejected request head
+fn rejected(status: Status, message: impl Into<String>) -> Error {
+    Error::Rejected(status.0, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &[u8], limits: &RequestLimits) -> Result<Vec<String>> {
+        read_head(&mut io::Cursor::new(input), limits)
+    }
+
+    fn status(result: Result<Vec<String>>) -> u16 {
+        match result {
+            Err(Error::Rejected(status, _)) => status,
+            other => panic!("expected a rejection, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn head() {
+        let lines = read(
+            b"GET / HTTP/1.1\r\nHost: test\nAccept: */*\r\n\r\nbody",
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(vec!["GET / HTTP/1.1", "Host: test", "Accept: */*"], lines);
+        assert!(read(b"", &RequestLimits::default()).unwrap().is_empty());
+        assert_eq!(
+            400,
+            status(read(b"GET / HTTP/1.1\r\nHost", &RequestLimits::default()))
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let limits = RequestLimits {
+            max_request_line: 16,
+            max_header_size: 24,
+            max_headers: 2,
+            ..RequestLimits::default()
+        };
+        assert!(read(b"GET /012345 HTTP\r\n\r\n", &limits).is_ok());
+        assert_eq!(414, status(read(b"GET /0123456 HTTP\r\n\r\n", &limits)));
+        assert_eq!(414, status(read(&[b'A'; 1000], &limits)));
+        assert!(read(
+            b"GET / HTTP\r\nA: 0123456789\r\nB: 01234567\r\n\r\n",
+            &limits
+        )
+        .is_ok());
+        assert_eq!(
+            431,
+            status(read(
+                b"GET / HTTP\r\nA: 0123456789\r\nB: 012345678\r\n\r\n",
+                &limits
+            ))
+        );
+        assert_eq!(
+            431,
+            status(read(b"GET / HTTP\r\nA:\r\nB:\r\nC:\r\n\r\n", &limits))
+        );
+        let long_header = [b"GET / HTTP\r\nA: ".as_slice(), &[b'a'; 1000]].concat();
+        assert_eq!(431, status(read(&long_header, &limits)));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        assert_eq!(
+            400,
+            status(read(
+                b"GET /\xff HTTP/1.1\r\n\r\n",
+                &RequestLimits::default()
+            ))
+        );
+        assert_eq!(
+            400,
+            status(read(
+                b"GET / HTTP/1.1\r\nA: \xc3\x28\r\n\r\n",
+                &RequestLimits::default()
+            ))
+        );
+    }
+
+    /// Input that arrives a byte at a time, `delay` apart
+    struct Trickle {
+        input: io::Cursor<Vec<u8>>,
+        delay: Duration,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(self.delay);
+            self.input.read(&mut buf[..1])
+        }
+    }
+
+    #[test]
+    fn slow_client() {
+        let limits = RequestLimits {
+            header_timeout: Duration::from_millis(50),
+            ..RequestLimits::default()
+        };
+        let mut trickle = BufReader::new(Trickle {
+            input: io::Cursor::new(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n".to_vec()),
+            delay: Duration::from_millis(5),
+        });
+        assert_eq!(408, status(read_head(&mut trickle, &limits)));
+    }
+}
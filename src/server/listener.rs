@@ -0,0 +1,431 @@
+//! Listening Sockets
+//!
+//! A server may listen on several addresses at once, each with its own router. IPv6 sockets are bound
+//! with `IPV6_V6ONLY` set so that an IPv4 wildcard (`0.0.0.0:80`) and an IPv6 wildcard (`[::]:80`) can
+//! be bound side by side for dual-stack service; bind both to accept both.
+//!
+//! A bind address of the form `unix:<path>` listens on a Unix domain socket instead. A socket file
+//! left behind by a server that is no longer running is removed before binding; a path that is not a
+//! socket, or a socket another server still accepts connections on, is left alone and reported.
+//! The configured permissions apply to a new socket before it accepts any connection.
+
+use std::{
+    mem::size_of,
+    net::{SocketAddr, SocketAddrV6},
+    os::{
+        fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+        raw::{c_int, c_void},
+        unix::{
+            ffi::OsStrExt,
+            fs::{FileTypeExt, PermissionsExt},
+            net::{UnixListener, UnixStream},
+        },
+    },
+};
+
+use super::*;
+use crate::config::UNIX_SOCKET_PREFIX;
+
+/// Maximum length of the queue of pending connections
+const BACKLOG: c_int = 128;
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::{c_int, copy_path, io, Path};
+    pub(super) const AF_UNIX: c_int = 1;
+    pub(super) const AF_INET6: c_int = 10;
+    pub(super) const SOCK_STREAM: c_int = 1 | 0o2000000; // SOCK_CLOEXEC
+    pub(super) const SOL_SOCKET: c_int = 1;
+    pub(super) const SO_REUSEADDR: c_int = 2;
+    pub(super) const IPPROTO_IPV6: c_int = 41;
+    pub(super) const IPV6_V6ONLY: c_int = 26;
+
+    /// `struct sockaddr_in6`
+    #[repr(C)]
+    pub(super) struct SockAddrIn6 {
+        pub(super) sin6_family: u16,
+        pub(super) sin6_port: u16,
+        pub(super) sin6_flowinfo: u32,
+        pub(super) sin6_addr: [u8; 16],
+        pub(super) sin6_scope_id: u32,
+    }
+
+    impl SockAddrIn6 {
+        pub(super) fn new(port: u16, flowinfo: u32, addr: [u8; 16], scope_id: u32) -> Self {
+            SockAddrIn6 {
+                sin6_family: AF_INET6 as u16,
+                sin6_port: port.to_be(),
+                sin6_flowinfo: flowinfo.to_be(),
+                sin6_addr: addr,
+                sin6_scope_id: scope_id,
+            }
+        }
+    }
+
+    /// `struct sockaddr_un`
+    #[repr(C)]
+    pub(super) struct SockAddrUn {
+        pub(super) sun_family: u16,
+        pub(super) sun_path: [u8; 108],
+    }
+
+    impl SockAddrUn {
+        pub(super) fn new(path: &Path) -> io::Result<Self> {
+            let mut address = SockAddrUn {
+                sun_family: AF_UNIX as u16,
+                sun_path: [0; 108],
+            };
+            copy_path(path, &mut address.sun_path)?;
+            Ok(address)
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use super::{c_int, copy_path, io, Path};
+    pub(super) const AF_UNIX: c_int = 1;
+    pub(super) const AF_INET6: c_int = 30;
+    pub(super) const SOCK_STREAM: c_int = 1;
+    pub(super) const SOL_SOCKET: c_int = 0xffff;
+    pub(super) const SO_REUSEADDR: c_int = 0x0004;
+    pub(super) const IPPROTO_IPV6: c_int = 41;
+    pub(super) const IPV6_V6ONLY: c_int = 27;
+
+    /// `struct sockaddr_in6` (BSD layout, with a length prefix)
+    #[repr(C)]
+    pub(super) struct SockAddrIn6 {
+        pub(super) sin6_len: u8,
+        pub(super) sin6_family: u8,
+        pub(super) sin6_port: u16,
+        pub(super) sin6_flowinfo: u32,
+        pub(super) sin6_addr: [u8; 16],
+        pub(super) sin6_scope_id: u32,
+    }
+
+    impl SockAddrIn6 {
+        pub(super) fn new(port: u16, flowinfo: u32, addr: [u8; 16], scope_id: u32) -> Self {
+            SockAddrIn6 {
+                sin6_len: std::mem::size_of::<Self>() as u8,
+                sin6_family: AF_INET6 as u8,
+                sin6_port: port.to_be(),
+                sin6_flowinfo: flowinfo.to_be(),
+                sin6_addr: addr,
+                sin6_scope_id: scope_id,
+            }
+        }
+    }
+
+    /// `struct sockaddr_un` (BSD layout, with a length prefix)
+    #[repr(C)]
+    pub(super) struct SockAddrUn {
+        pub(super) sun_len: u8,
+        pub(super) sun_family: u8,
+        pub(super) sun_path: [u8; 104],
+    }
+
+    impl SockAddrUn {
+        pub(super) fn new(path: &Path) -> io::Result<Self> {
+            let mut address = SockAddrUn {
+                sun_len: std::mem::size_of::<Self>() as u8,
+                sun_family: AF_UNIX as u8,
+                sun_path: [0; 104],
+            };
+            copy_path(path, &mut address.sun_path)?;
+            Ok(address)
+        }
+    }
+}
+
+// Helper function that copies a socket path into a `sun_path`, leaving room for its terminating NUL
+fn copy_path(path: &Path, sun_path: &mut [u8]) -> io::Result<()> {
+    let bytes = path.as_os_str().as_bytes();
+    if bytes.len() >= sun_path.len() || bytes.contains(&0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the path is too long for a Unix domain socket, or contains a NUL byte",
+        ));
+    }
+    sun_path[..bytes.len()].copy_from_slice(bytes);
+    Ok(())
+}
+
+mod ffi {
+    use super::{c_int, c_void};
+
+    extern "C" {
+        pub(super) fn socket(domain: c_int, kind: c_int, protocol: c_int) -> c_int;
+        pub(super) fn setsockopt(
+            fd: c_int,
+            level: c_int,
+            name: c_int,
+            value: *const c_void,
+            len: u32,
+        ) -> c_int;
+        pub(super) fn bind(fd: c_int, addr: *const c_void, len: u32) -> c_int;
+        pub(super) fn listen(fd: c_int, backlog: c_int) -> c_int;
+        pub(super) fn close(fd: c_int) -> c_int;
+    }
+}
+
+/// A listening socket and the router for the connections it accepts
+#[derive(Debug)]
+pub(super) struct Listener {
+    /// The configured bind address
+    pub(super) addr: String,
+    pub(super) socket: Socket,
+    pub(super) router: Arc<Router>,
+}
+
+/// A TCP or Unix domain listening socket
+#[derive(Debug)]
+pub(super) enum Socket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Socket {
+    /// Takes ownership of a listening socket descriptor of either kind
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open descriptor that nothing else owns.
+    pub(super) unsafe fn from_raw_fd(fd: RawFd) -> io::Result<Socket> {
+        let tcp = TcpListener::from_raw_fd(fd);
+        if tcp.local_addr().is_ok() {
+            return Ok(Socket::Tcp(tcp));
+        }
+        let unix = UnixListener::from_raw_fd(tcp.into_raw_fd());
+        unix.local_addr()?;
+        Ok(Socket::Unix(unix))
+    }
+
+    /// The local TCP address, if this is a TCP socket
+    pub(super) fn tcp_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Socket::Tcp(listener) => listener.local_addr().ok(),
+            Socket::Unix(_) => None,
+        }
+    }
+
+    /// The path of the socket file, if this is a Unix domain socket bound to one
+    pub(super) fn unix_path(&self) -> Option<PathBuf> {
+        match self {
+            Socket::Tcp(_) => None,
+            Socket::Unix(listener) => listener
+                .local_addr()
+                .ok()
+                .and_then(|addr| addr.as_pathname().map(Path::to_path_buf)),
+        }
+    }
+
+    pub(super) fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Socket::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            Socket::Unix(listener) => listener.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Socket::Tcp(listener) => listener.as_raw_fd(),
+            Socket::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+impl fmt::Display for Socket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.tcp_addr(), self.unix_path()) {
+            (Some(addr), _) => write!(f, "{addr}"),
+            (_, Some(path)) => write!(f, "{UNIX_SOCKET_PREFIX}{}", path.display()),
+            _ => write!(f, "fd {}", self.as_raw_fd()),
+        }
+    }
+}
+
+/// Binds a listening socket for a bind address, giving a Unix domain socket the permissions `mode`
+pub(super) fn bind(addr: &str, mode: Option<u32>) -> Result<Socket> {
+    let bound = match addr.strip_prefix(UNIX_SOCKET_PREFIX) {
+        Some(path) => bind_unix(Path::new(path), mode).map(Socket::Unix),
+        None => bind_tcp(addr).map(Socket::Tcp),
+    };
+    bound.map_err(|e| Error::Bind(addr.to_string(), e))
+}
+
+// Helper function that binds to the first of the addresses `addr` resolves to that can be bound
+fn bind_tcp(addr: &str) -> io::Result<TcpListener> {
+    let mut last_error = None;
+    for socket_addr in addr.to_socket_addrs()? {
+        let bound = match socket_addr {
+            SocketAddr::V4(_) => TcpListener::bind(socket_addr),
+            SocketAddr::V6(v6) => bind_v6_only(v6),
+        };
+        match bound {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::other("address did not resolve")))
+}
+
+// Helper function that binds a Unix domain socket, first removing a stale socket file. The socket
+// file is given its permissions before the socket listens: until then connecting to it is refused,
+// so no client can connect before the permissions apply.
+fn bind_unix(path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+    remove_stale_socket(path)?;
+    let address = sys::SockAddrUn::new(path)?;
+    // SAFETY: plain system calls; the descriptor is owned as soon as it is created, so it is closed
+    // on error and otherwise handed to the UnixListener
+    let fd = unsafe {
+        let fd = ffi::socket(sys::AF_UNIX, sys::SOCK_STREAM, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = OwnedFd::from_raw_fd(fd);
+        let address_ptr = &address as *const sys::SockAddrUn as *const c_void;
+        if ffi::bind(
+            fd.as_raw_fd(),
+            address_ptr,
+            size_of::<sys::SockAddrUn>() as u32,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        fd
+    };
+    // Binding created the socket file, which must not outlive a socket that failed to listen
+    if let Err(e) = listen_unix(&fd, path, mode) {
+        let _ = fs::remove_file(path);
+        return Err(e);
+    }
+    Ok(UnixListener::from(fd))
+}
+
+// Helper function that gives a bound Unix domain socket's file its permissions, then starts the
+// socket listening
+fn listen_unix(fd: &OwnedFd, path: &Path, mode: Option<u32>) -> io::Result<()> {
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    // SAFETY: a plain system call on a descriptor the caller owns
+    if unsafe { ffi::listen(fd.as_raw_fd(), BACKLOG) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Helper function that removes a socket file no server is accepting connections on any more
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+        Ok(metadata) if !metadata.file_type().is_socket() => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "the path exists and is not a socket",
+        )),
+        Ok(_) => match UnixStream::connect(path) {
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another server is accepting connections on it",
+            )),
+            Err(_) => {
+                warn!("Removing stale socket {}", path.display());
+                fs::remove_file(path)
+            }
+        },
+    }
+}
+
+// Helper function that binds an IPv6 listening socket that does not also accept IPv4 connections
+fn bind_v6_only(addr: SocketAddrV6) -> io::Result<TcpListener> {
+    // SAFETY: plain system calls on a descriptor this function owns; the descriptor is closed on error
+    // and otherwise handed to a TcpListener, which takes ownership of it
+    unsafe {
+        let fd: RawFd = ffi::socket(sys::AF_INET6, sys::SOCK_STREAM, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fail = |fd| {
+            let e = io::Error::last_os_error();
+            ffi::close(fd);
+            Err(e)
+        };
+        let on: c_int = 1;
+        for (level, name) in [
+            (sys::IPPROTO_IPV6, sys::IPV6_V6ONLY),
+            (sys::SOL_SOCKET, sys::SO_REUSEADDR),
+        ] {
+            let value = &on as *const c_int as *const c_void;
+            if ffi::setsockopt(fd, level, name, value, size_of::<c_int>() as u32) < 0 {
+                return fail(fd);
+            }
+        }
+        let sockaddr = sys::SockAddrIn6::new(
+            addr.port(),
+            addr.flowinfo(),
+            addr.ip().octets(),
+            addr.scope_id(),
+        );
+        let sockaddr_ptr = &sockaddr as *const sys::SockAddrIn6 as *const c_void;
+        if ffi::bind(fd, sockaddr_ptr, size_of::<sys::SockAddrIn6>() as u32) < 0 {
+            return fail(fd);
+        }
+        if ffi::listen(fd, BACKLOG) < 0 {
+            return fail(fd);
+        }
+        Ok(TcpListener::from_raw_fd(fd))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dual_stack() {
+        let v4 = bind("0.0.0.0:0", None).unwrap().tcp_addr().unwrap();
+        let port = v4.port();
+        let v6 = match bind(&format!("[::]:{port}"), None) {
+            Ok(v6) => v6.tcp_addr().unwrap(),
+            // IPv6 is not available everywhere tests run
+            Err(Error::Bind(_, e)) if e.kind() == io::ErrorKind::AddrNotAvailable => return,
+            Err(e) => panic!("{e}"),
+        };
+        assert_eq!(port, v6.port());
+        assert!(v6.is_ipv6());
+    }
+
+    #[test]
+    fn bind_error() {
+        let taken = bind("127.0.0.1:0", None).unwrap();
+        let addr = taken.to_string();
+        let e = bind(&addr, None).unwrap_err();
+        assert!(e.to_string().starts_with(&format!("cannot bind {addr}: ")));
+    }
+
+    #[test]
+    fn unix_socket() {
+        let path = std::env::temp_dir().join(format!("ptodd-test-{}.sock", std::process::id()));
+        let addr = format!("{UNIX_SOCKET_PREFIX}{}", path.display());
+        let socket = bind(&addr, Some(0o600)).unwrap();
+        assert_eq!(addr, socket.to_string());
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+        // A socket still being listened on is not stale
+        assert!(bind(&addr, None).is_err());
+        drop(socket);
+        // Once its server has gone the socket file is stale and is replaced
+        let socket = bind(&addr, None).unwrap();
+        assert!(matches!(socket, Socket::Unix(_)));
+        fs::remove_file(&path).unwrap();
+        // Other files are never removed
+        fs::write(&path, "").unwrap();
+        assert!(bind(&addr, None).is_err());
+        fs::remove_file(&path).unwrap();
+        let long = format!("{UNIX_SOCKET_PREFIX}/tmp/{}.sock", "x".repeat(200));
+        assert!(bind(&long, None).is_err());
+    }
+}
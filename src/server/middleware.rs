@@ -0,0 +1,198 @@
+//! Middleware
+//!
+//! Behaviour that cuts across routes (logging, access control, rate limiting, ...) is written as a
+//! [`Middleware`] layer rather than in the handlers. A router runs its [`Chain`] of layers around
+//! every request: each layer's `before` hook in order, then the handler, then the `after` hooks in
+//! reverse order. A `before` hook returning a response short-circuits the chain: neither the later
+//! layers nor the handler run, and only the earlier layers' `after` hooks see the response.
+//!
+//! A layer may be limited to a route group, the requests whose path starts with a prefix.
+
+use super::*;
+
+/// A layer of behaviour around a router's handlers
+pub(super) trait Middleware: fmt::Debug + Send + Sync {
+    /// Runs before the request is handled; returning a response answers the request with it
+    fn before(&self, _request: &Request) -> Option<Response> {
+        None
+    }
+
+    /// Runs after the request is handled, returning the response to send in its place
+    fn after(&self, _request: &Request, response: Response) -> Response {
+        response
+    }
+}
+
+/// An ordered chain of middleware layers
+#[derive(Debug, Default, Clone)]
+pub(super) struct Chain {
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug, Clone)]
+struct Layer {
+    /// The path prefix of the requests the layer applies to; `None` for all requests
+    prefix: Option<String>,
+    middleware: Arc<dyn Middleware>,
+}
+
+impl Chain {
+    /// Adds a layer for all requests, inside the layers already added
+    pub(super) fn with(mut self, middleware: Arc<dyn Middleware>) -> Chain {
+        self.layers.push(Layer {
+            prefix: None,
+            middleware,
+        });
+        self
+    }
+
+    /// Adds a layer for the requests whose path starts with `prefix`, inside the layers already
+    /// added
+    pub(super) fn with_group(
+        mut self,
+        prefix: impl Into<String>,
+        middleware: Arc<dyn Middleware>,
+    ) -> Chain {
+        self.layers.push(Layer {
+            prefix: Some(prefix.into()),
+            middleware,
+        });
+        self
+    }
+
+    /// Runs the layers that apply to the request around `handler`
+    pub(super) fn run(
+        &self,
+        request: &Request,
+        handler: impl FnOnce(&Request) -> Response,
+    ) -> Response {
+        let path = request.target.path();
+        let layers: Vec<_> = self
+            .layers
+            .iter()
+            .filter(|layer| {
+                layer
+                    .prefix
+                    .as_ref()
+                    .is_none_or(|prefix| path.starts_with(prefix.as_str()))
+            })
+            .collect();
+        let mut passed = 0;
+        let mut response = None;
+        for layer in &layers {
+            response = layer.middleware.before(request);
+            if response.is_some() {
+                break;
+            }
+            passed += 1;
+        }
+        let mut response = match response {
+            Some(response) => response,
+            None => handler(request),
+        };
+        for layer in layers[..passed].iter().rev() {
+            response = layer.middleware.after(request, response);
+        }
+        response
+    }
+}
+
+/// Logs each request as it arrives and the status it was answered with
+#[derive(Debug)]
+pub(super) struct RequestLog;
+
+impl Middleware for RequestLog {
+    fn before(&self, request: &Request) -> Option<Response> {
+        match request.client.ip {
+            Some(ip) => info!("{} {} {}", ip, request.method, request.target),
+            None => info!("{} {}", request.method, request.target),
+        }
+        debug!("Request ({:?}): {:#?}", request.client, request.headers);
+        None
+    }
+
+    fn after(&self, request: &Request, response: Response) -> Response {
+        info!(
+            "{} {} -> {}",
+            request.method, request.target, response.status
+        );
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the hooks run, and answers requests itself when `answer` is set
+    #[derive(Debug)]
+    struct Trace {
+        name: &'static str,
+        answer: bool,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Trace {
+        fn before(&self, _request: &Request) -> Option<Response> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} before", self.name));
+            self.answer
+                .then(|| Response::text(Status::FORBIDDEN, self.name))
+        }
+
+        fn after(&self, _request: &Request, response: Response) -> Response {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} after", self.name));
+            response.with_header("X-Layer", self.name)
+        }
+    }
+
+    fn request(path: &str) -> Request {
+        Request::parse(&[format!("GET {path} HTTP/1.1")]).unwrap()
+    }
+
+    #[test]
+    fn order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let trace = |name, answer| {
+            Arc::new(Trace {
+                name,
+                answer,
+                calls: Arc::clone(&calls),
+            })
+        };
+        let chain = Chain::default()
+            .with(trace("outer", false))
+            .with_group("/admin/", trace("admin", true))
+            .with(trace("inner", false));
+        let handled = chain.run(&request("/"), |_| {
+            calls.lock().unwrap().push("handler".to_string());
+            Response::text(Status::OK, "ok")
+        });
+        assert_eq!(Status::OK, handled.status);
+        assert_eq!(
+            vec![
+                "outer before",
+                "inner before",
+                "handler",
+                "inner after",
+                "outer after"
+            ],
+            *calls.lock().unwrap()
+        );
+        let headers: Vec<_> = handled.headers.iter().map(|(_, value)| value).collect();
+        assert_eq!(vec!["text/plain; charset=utf-8", "inner", "outer"], headers);
+        calls.lock().unwrap().clear();
+        // The admin layer answers its group's requests itself
+        let answered = chain.run(&request("/admin/log"), |_| panic!("handler ran"));
+        assert_eq!(Status::FORBIDDEN, answered.status);
+        assert_eq!(
+            vec!["outer before", "admin before", "outer after"],
+            *calls.lock().unwrap()
+        );
+    }
+}
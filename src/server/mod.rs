@@ -4,28 +4,72 @@
 //! pool to handle incoming connections. It has no third-party crate dependencies.
 
 use std::{
+    cell::Cell,
     fmt, fs,
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
+    io::{self, prelude::*, BufReader},
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    os::fd::AsRawFd,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
     result,
-    sync::{mpsc, Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use access::AccessControl;
+use admin::Admin;
+use connections::{ConnectionLimits, ConnectionSlot};
+use deadline::Deadline;
 pub use error::{Error, Result};
-use pool::ThreadPool;
-use request::Request;
+use forwarded::ClientInfo;
+pub use handoff::ListenFds;
+use head::RequestLimits;
+use listener::{Listener, Socket};
+use middleware::{Chain, RequestLog};
+use pool::{PoolSize, ThreadPool};
+use proxy::TrustedProxies;
+use rate_limit::RateLimiter;
+use request::{Request, RequestMethod};
+use response::{Response, Status};
+use router::Router;
+use wakeup::Wakeup;
 
 use super::*;
+use crate::config::{Config, IoEngine};
 
+mod access;
+mod admin;
+mod body;
+mod connections;
+mod deadline;
+#[cfg(target_os = "linux")]
+mod epoll;
 mod error;
+mod error_page;
+mod extract;
+mod forwarded;
+mod handler;
+mod handoff;
+mod head;
+mod listener;
+mod middleware;
 mod pool;
+mod proxy;
+mod queue;
+mod rate_limit;
 mod request;
+mod response;
+mod router;
+mod signal;
+mod wakeup;
 mod worker;
 
-/// Tread pool size
-const DEFAULT_POOL_SIZE: usize = 4;
+/// How long clients turned away because the server is busy are asked to wait before retrying
+const OVERLOAD_RETRY_AFTER: Duration = Duration::from_secs(5);
 
 // TODO: HTTP/1.1 Support
 //  https://www.rfc-editor.org/rfc/rfc9110.txt (HTTP Semantics)
@@ -38,69 +82,642 @@ const DEFAULT_POOL_SIZE: usize = 4;
 /// A server, which listens for incoming connections and handles them.
 #[derive(Debug)]
 pub struct Server {
-    /// The address to bind the server to.
-    addr: String,
-    /// The listener, which listens for incoming connections.
-    listener: TcpListener,
-    /// The thread pool, which manages our worker threads.
+    /// The listeners, which listen for incoming connections, each with its own router.
+    listeners: Vec<Listener>,
+    /// The thread pool, shared by all listeners, which manages our worker threads.
     pool: ThreadPool,
+    /// How long in-flight requests are given to finish when shutting down.
+    shutdown_timeout: Duration,
+    /// How long a read from or write to a client may block.
+    socket_timeout: Duration,
+    /// How connections wait for their requests.
+    io_engine: IoEngine,
+    /// Limits on the requests clients may send.
+    limits: RequestLimits,
+    /// The proxies trusted to report clients' addresses.
+    proxies: Arc<TrustedProxies>,
+    /// The caps on open connections, shared by all listeners.
+    connections: Arc<ConnectionLimits>,
+    /// Whether connections are being turned away because the job queue is full.
+    shedding: Cell<bool>,
+    /// Wakes the accept loop when it should check whether to stop.
+    wakeup: Wakeup,
 }
 
 impl Server {
-    pub fn new(addr: impl Into<String>) -> Result<Server> {
-        let addr = addr.into();
+    /// Creates a server listening on each of the site's bind addresses and, when configured, on a
+    /// separate admin address. Listening sockets inherited from a previous server process (see
+    /// [`handoff`]), `listen_fds`, are used when they match a bind address.
+    pub fn new(config: &Config, listen_fds: ListenFds) -> Result<Server> {
+        let pool = ThreadPool::build(PoolSize {
+            min: config.min_workers,
+            max: config.max_workers,
+            idle_timeout: config.worker_idle_timeout,
+            queue_depth: config.queue_depth,
+            job_timeout: config.job_timeout,
+        })?;
+        let connections = Arc::new(ConnectionLimits::new(
+            config.max_connections,
+            config.max_connections_per_ip,
+            config.trusted_proxies.clone(),
+        ));
+        let admin = Admin::new(
+            config.admin_token.clone(),
+            pool.monitor(),
+            Arc::clone(&connections),
+        );
+        let chain = Chain::default()
+            .with(Arc::new(RequestLog))
+            .with(Arc::new(AccessControl::new(&config.access)))
+            .with(Arc::new(RateLimiter::new(&config.rate_limit)));
+        let admin_chain = chain
+            .clone()
+            .with_group(admin::PREFIX, Arc::new(admin.authorization()));
+        let error_pages = config.error_pages.as_ref().unwrap_or(&config.root);
+        let site = Arc::new(
+            match config.admin_bind {
+                Some(_) => Router::site(config.root.clone(), None).with_chain(chain),
+                None => Router::site(config.root.clone(), Some(admin.clone()))
+                    .with_chain(admin_chain.clone()),
+            }
+            .with_error_pages(error_pages.clone())
+            .with_trace(config.trace),
+        );
+        let mut routes: Vec<_> = config
+            .bind
+            .iter()
+            .map(|addr| (addr, Arc::clone(&site)))
+            .collect();
+        if let Some(addr) = &config.admin_bind {
+            let router = Router::admin(config.root.clone(), admin)
+                .with_chain(admin_chain)
+                .with_error_pages(error_pages.clone())
+                .with_trace(config.trace);
+            routes.push((addr, Arc::new(router)));
+        }
+        let mut inherited = handoff::Inherited::adopt(listen_fds);
+        let listeners = routes
+            .into_iter()
+            .map(|(addr, router)| {
+                Ok(Listener {
+                    addr: addr.clone(),
+                    socket: inherited.take_or_bind(addr, config.socket_mode)?,
+                    router,
+                })
+            })
+            .collect::<Result<_>>()?;
         Ok(Server {
-            addr: addr.clone(),
-            listener: TcpListener::bind(&addr)?,
-            pool: ThreadPool::build(DEFAULT_POOL_SIZE)?,
+            listeners,
+            pool,
+            shutdown_timeout: config.shutdown_timeout,
+            socket_timeout: config.socket_timeout,
+            io_engine: config.io_engine,
+            limits: RequestLimits {
+                max_request_line: config.max_request_line,
+                max_header_size: config.max_header_size,
+                max_headers: config.max_headers,
+                header_timeout: config.header_timeout,
+                max_body_size: config.max_body_size,
+            },
+            proxies: Arc::new(TrustedProxies::new(
+                config.trusted_proxies.clone(),
+                config.proxy_protocol,
+                config.forwarded_headers,
+            )),
+            connections,
+            shedding: Cell::new(false),
+            wakeup: Wakeup::new()?,
         })
     }
 
-    pub fn run(&self) -> Result<()> {
-        info!("Listening for connections on {}", &self.addr);
-        for stream_result in self.listener.incoming() {
-            self.pool.execute(|| match stream_result {
-                Ok(stream) => {
-                    handle_connection(stream).unwrap_or_else(|e| warn!("handle_connection: {}", e))
-                }
-                Err(e) => {
-                    warn!("thread: {}", e);
-                }
-            })?;
+    /// Accepts and handles connections until a shutdown signal is received, or a restart signal hands
+    /// the listening sockets to a new server process, then stops accepting and gives in-flight
+    /// requests up to the shutdown timeout to finish. Unix domain socket files are removed on
+    /// shutdown, but left for the new process on a restart.
+    pub fn run(mut self) -> Result<()> {
+        signal::install_handlers(&self.wakeup)?;
+        let restarted = self.serve(&signal::shutdown_requested)?;
+        info!(
+            "Shutting down; waiting up to {:?} for in-flight requests",
+            self.shutdown_timeout
+        );
+        for listener in self.listeners.drain(..) {
+            if let (false, Some(path)) = (restarted, listener.socket.unix_path()) {
+                fs::remove_file(&path)
+                    .unwrap_or_else(|e| warn!("Cannot remove socket {}: {}", path.display(), e));
+            }
+        }
+        if !self.pool.drain(self.shutdown_timeout) {
+            warn!(
+                "Shutdown timeout expired with {} requests in flight; abandoning them",
+                self.pool.pending()
+            );
+            self.pool.detach();
         }
-        info!("Shutting down");
         Ok(())
     }
+
+    // Serves connections with the configured I/O engine until `stop` returns true, or a restart hands
+    // the listening sockets to a new server process, returning whether one did
+    fn serve(&self, stop: &dyn Fn() -> bool) -> Result<bool> {
+        for listener in &self.listeners {
+            // Non-blocking so that accepting from a listener woken for nothing does not block the loop
+            listener.socket.set_nonblocking(true)?;
+            info!(
+                "Listening for connections on {} ({} engine)",
+                listener.addr, self.io_engine
+            );
+        }
+        match self.io_engine {
+            IoEngine::Threaded => self.accept_loop(stop),
+            #[cfg(target_os = "linux")]
+            IoEngine::Epoll => self.event_loop(stop),
+            #[cfg(not(target_os = "linux"))]
+            IoEngine::Epoll => Err(Error::Io(io::Error::from(io::ErrorKind::Unsupported))),
+        }
+    }
+
+    // The threaded engine: waits for the listeners to have connections, handing each connection to
+    // the thread pool as soon as it is accepted
+    fn accept_loop(&self, stop: &dyn Fn() -> bool) -> Result<bool> {
+        let mut fds: Vec<_> = self
+            .listeners
+            .iter()
+            .map(|listener| listener.socket.as_raw_fd())
+            .collect();
+        fds.push(self.wakeup.receiver_fd());
+        while !stop() {
+            if self.restart_requested() {
+                return Ok(true);
+            }
+            let readable = wakeup::wait_readable(&fds)?;
+            if readable[self.listeners.len()] {
+                self.wakeup.clear();
+            }
+            for (listener, _) in self
+                .listeners
+                .iter()
+                .zip(readable)
+                .filter(|(_, readable)| *readable)
+            {
+                let accepted = match &listener.socket {
+                    Socket::Tcp(socket) => socket.accept().and_then(|(mut stream, peer)| {
+                        stream.set_nonblocking(false)?;
+                        stream.set_read_timeout(Some(self.socket_timeout))?;
+                        stream.set_write_timeout(Some(self.socket_timeout))?;
+                        match self.admit(&mut stream, Some(peer)) {
+                            Some(slot) => self.dispatch(stream, Some(peer), &listener.router, slot),
+                            None => Ok(()),
+                        }
+                    }),
+                    Socket::Unix(socket) => socket.accept().and_then(|(mut stream, _)| {
+                        stream.set_nonblocking(false)?;
+                        stream.set_read_timeout(Some(self.socket_timeout))?;
+                        stream.set_write_timeout(Some(self.socket_timeout))?;
+                        match self.admit(&mut stream, None) {
+                            Some(slot) => self.dispatch(stream, None, &listener.router, slot),
+                            None => Ok(()),
+                        }
+                    }),
+                };
+                match accepted {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => warn!("accept ({}): {}", listener.addr, e),
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    // Checks for a restart request and, if there is one, hands the listening sockets to a new server
+    // process, returning whether that succeeded
+    fn restart_requested(&self) -> bool {
+        if !signal::take_restart_request() {
+            return false;
+        }
+        info!("Restart requested; handing the listening sockets to a new server process");
+        let sockets: Vec<_> = self.listeners.iter().map(|l| &l.socket).collect();
+        match handoff::spawn_successor(&sockets) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Restart failed; continuing to serve: {}", e);
+                false
+            }
+        }
+    }
+
+    // Takes a connection slot for a connection just accepted from `peer` (`None` for a Unix domain
+    // socket), or turns the connection away with a 503 if a connection limit has been reached
+    fn admit(&self, stream: &mut impl Write, peer: Option<SocketAddr>) -> Option<ConnectionSlot> {
+        match self.connections.admit(peer.map(|peer| peer.ip())) {
+            Ok(slot) => Some(slot),
+            Err(refusal) => {
+                debug!("Refusing connection from {:?}: {}", peer, refusal);
+                Response::text(Status::SERVICE_UNAVAILABLE, format!("{refusal}\n"))
+                    .with_header("Connection", "close")
+                    .write_to(stream)
+                    .unwrap_or_else(|e| debug!("Cannot refuse connection: {}", e));
+                None
+            }
+        }
+    }
+
+    // Hands an accepted connection, from `peer` (`None` for a Unix domain socket), to the thread pool,
+    // or turns it away with a 503 if the pool's queue is full. The connection's slot is given back
+    // once it has been handled.
+    fn dispatch<S>(
+        &self,
+        mut stream: S,
+        peer: Option<SocketAddr>,
+        router: &Arc<Router>,
+        slot: ConnectionSlot,
+    ) -> io::Result<()>
+    where
+        S: Read + Write + Send + 'static,
+    {
+        if !self.pool.admit() {
+            if !self.shedding.replace(true) {
+                warn!("Job queue full; turning connections away");
+            }
+            debug!("Turning away connection from {:?}", peer);
+            return Response::text(
+                Status::SERVICE_UNAVAILABLE,
+                "server busy; try again later\n",
+            )
+            .with_header("Retry-After", OVERLOAD_RETRY_AFTER.as_secs().to_string())
+            .with_header("Connection", "close")
+            .write_to(&mut stream)
+            .map_err(|e| io::Error::other(e.to_string()));
+        }
+        if self.shedding.replace(false) {
+            info!("Job queue has room again: {}", self.pool.stats());
+        }
+        let router = Arc::clone(router);
+        let proxies = Arc::clone(&self.proxies);
+        let limits = self.limits;
+        self.pool
+            .execute(move |deadline| {
+                serve(stream, peer, &router, &proxies, &limits, deadline);
+                drop(slot);
+            })
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+}
+
+/// Handles a connection on a worker. Should handling it panic, the panic is logged and the client
+/// gets a 500 rather than a dropped connection.
+fn serve(
+    mut stream: impl Read + Write,
+    peer: Option<SocketAddr>,
+    router: &Router,
+    proxies: &TrustedProxies,
+    limits: &RequestLimits,
+    deadline: &Deadline,
+) {
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        handle_connection(&mut stream, peer, router, proxies, limits, deadline)
+    }));
+    match outcome {
+        Ok(result) => result.unwrap_or_else(|e| warn!("handle_connection: {}", e)),
+        Err(payload) => {
+            let error = Error::Internal(format!(
+                "handling a connection from {:?} panicked: {}",
+                peer,
+                pool::panic_message(payload.as_ref())
+            ));
+            router
+                .error_response(&error, None)
+                .with_header("Connection", "close")
+                .write_to(&mut stream)
+                .unwrap_or_else(|e| warn!("Cannot report a panic to the client: {}", e));
+        }
+    }
 }
 
-fn handle_connection(mut stream: TcpStream) -> Result<()> {
+/// Reads a request from a connection of any kind and writes the router's response to it. With the
+/// PROXY protocol enabled, connections from trusted proxies must start with a PROXY header, whose
+/// source address then replaces the proxy's as the client's address. A request that cannot be read,
+/// such as one beyond the limits (see [`head`] and [`body`]), is answered with the router's error
+/// page for it. The request carries the job's deadline for the router's handlers to poll. A HEAD
+/// request is answered with the head of the response alone.
+fn handle_connection(
+    mut stream: impl Read + Write,
+    peer: Option<SocketAddr>,
+    router: &Router,
+    proxies: &TrustedProxies,
+    limits: &RequestLimits,
+    deadline: &Deadline,
+) -> Result<()> {
     info!("handling a connection");
-    let http_request: Vec<_> = BufReader::new(&mut stream)
-        .lines()
-        .map(|result| result.unwrap())
-        .take_while(|line| !line.is_empty())
-        .collect();
-    let request = Request::parse(&http_request)?;
-    info!("{}", http_request[0]);
-    info!("Method: {}", request.method);
-    info!("Target: {}", request.target);
-    debug!("Request ({:?}): {:#?}", stream.peer_addr()?, http_request);
-    let (status_line, filename) = match http_request[0].as_str() {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"),
-        "GET /sleep HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(5));
-            ("HTTP/1.1 200 OK", "hello.html")
-        }
-        _ => ("HTTP/1.1 404 NOT FOUND", "404.html"),
+    let mut reader = BufReader::new(&mut stream);
+    let peer = peer.map(|peer| peer.ip());
+    let peer = match proxies.proxy_protocol && proxies.trusts(peer) {
+        true => {
+            let header = proxy::read_header(&mut reader)?;
+            debug!("PROXY header from {:?}: {}", peer, header);
+            header.source.map(|source| source.ip()).or(peer)
+        }
+        false => peer,
     };
-    let contents = fs::read_to_string(filename)?;
-    // TODO: Send date in response header (Cf. RFC-9110 6.6.1)
-    stream.write_all(
-        format!(
-            "{status_line}\r\nContent-Length: {}\r\n\r\n{contents}",
-            contents.len()
+    let mut request = match read_request(&mut reader, limits) {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok(()),
+        Err(Error::Io(e)) => return Err(Error::Io(e)),
+        Err(e) => {
+            warn!("Rejected request from {:?}: {}", peer, e);
+            return router
+                .error_response(&e, None)
+                .with_header("Connection", "close")
+                .write_to(&mut stream);
+        }
+    };
+    request.client = ClientInfo::resolve(&request, peer, proxies);
+    request.deadline = deadline.clone();
+    let response = router.route(&mut request);
+    match request.method {
+        RequestMethod::Head => response.write_head_to(&mut stream),
+        _ => response.write_to(&mut stream),
+    }
+}
+
+// Helper function that reads a request's head, then its body, within the limits. Returns `None` if
+// the connection closes before the request starts.
+fn read_request(reader: &mut impl BufRead, limits: &RequestLimits) -> Result<Option<Request>> {
+    let head = head::read_head(reader, limits)?;
+    if head.is_empty() {
+        return Ok(None);
+    }
+    let mut request = Request::parse(&head)?;
+    request.body = body::read_body(reader, &request, limits.max_body_size)?;
+    Ok(Some(request))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::net::UnixStream, sync::atomic::AtomicBool};
+
+    use super::*;
+
+    /// An in-memory connection: reads come from `input`, writes go to `output`
+    struct Connection {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Connection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Connection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn any_stream() {
+        let mut connection = Connection {
+            input: io::Cursor::new(b"GET /missing HTTP/1.1\r\nHost: test\r\n\r\n".to_vec()),
+            output: Vec::new(),
+        };
+        let router = Router::site(PathBuf::from("."), None);
+        handle_connection(
+            &mut connection,
+            None,
+            &router,
+            &TrustedProxies::default(),
+            &RequestLimits::default(),
+            &Deadline::default(),
         )
-        .as_bytes(),
-    )?;
-    Ok(())
+        .unwrap();
+        let response = String::from_utf8(connection.output).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn rejected_head() {
+        let mut connection = Connection {
+            input: io::Cursor::new(b"GET /\xff HTTP/1.1\r\n\r\n".to_vec()),
+            output: Vec::new(),
+        };
+        let router = Router::site(PathBuf::from("."), None);
+        handle_connection(
+            &mut connection,
+            None,
+            &router,
+            &TrustedProxies::default(),
+            &RequestLimits::default(),
+            &Deadline::default(),
+        )
+        .unwrap();
+        let response = String::from_utf8(connection.output).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn responses() {
+        let router = Router::site(PathBuf::from("."), None);
+        let respond = |input: &[u8]| {
+            let mut connection = Connection {
+                input: io::Cursor::new(input.to_vec()),
+                output: Vec::new(),
+            };
+            handle_connection(
+                &mut connection,
+                None,
+                &router,
+                &TrustedProxies::default(),
+                &RequestLimits::default(),
+                &Deadline::default(),
+            )
+            .unwrap();
+            String::from_utf8(connection.output).unwrap()
+        };
+        let unsupported = respond(b"PATCH / HTTP/1.1\r\n\r\n");
+        assert!(unsupported.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+        let version = respond(b"GET / HTTP/2.0\r\n\r\n");
+        assert!(version.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"));
+        let missing = respond(b"GET /missing HTTP/1.1\r\nAccept: application/json\r\n\r\n");
+        assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(missing.contains("Content-Type: application/problem+json\r\n"));
+        assert!(missing.ends_with(r#""detail":"nothing found at /missing","instance":"/missing"}"#));
+        let get = respond(b"GET / HTTP/1.1\r\n\r\n");
+        let head = respond(b"HEAD / HTTP/1.1\r\n\r\n");
+        assert_eq!(get.split_once("\r\n\r\n").unwrap().0, head.trim_end());
+        assert!(head.ends_with("\r\n\r\n"));
+    }
+
+    /// A connection that panics when read
+    struct Exploding(Vec<u8>);
+
+    impl Read for Exploding {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            panic!("boom");
+        }
+    }
+
+    impl Write for Exploding {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn panic_is_500() {
+        let mut connection = Exploding(Vec::new());
+        let router = Router::site(PathBuf::from("."), None);
+        serve(
+            &mut connection,
+            None,
+            &router,
+            &TrustedProxies::default(),
+            &RequestLimits::default(),
+            &Deadline::default(),
+        );
+        let response = String::from_utf8(connection.0).unwrap();
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+    }
+
+    /// A server running on an ephemeral port in a thread of its own until `stop` is set
+    struct Running {
+        addr: SocketAddr,
+        connections: Arc<ConnectionLimits>,
+        stop: Arc<AtomicBool>,
+        /// Wakes the server's accept loop to notice `stop`
+        wakeup: UnixStream,
+        thread: thread::JoinHandle<Result<bool>>,
+    }
+
+    impl Running {
+        fn start(io_engine: IoEngine, workers: usize) -> Running {
+            Running::with(Config {
+                bind: vec!["127.0.0.1:0".to_string()],
+                min_workers: workers,
+                max_workers: workers,
+                io_engine,
+                ..Config::default()
+            })
+        }
+
+        fn with(config: Config) -> Running {
+            let server = Server::new(&config, ListenFds::default()).unwrap();
+            let addr = server.listeners[0].socket.tcp_addr().unwrap();
+            let connections = Arc::clone(&server.connections);
+            let wakeup = server.wakeup.sender().unwrap();
+            let stop = Arc::new(AtomicBool::new(false));
+            let stopped = Arc::clone(&stop);
+            let thread = thread::spawn(move || server.serve(&|| stopped.load(Ordering::SeqCst)));
+            Running {
+                addr,
+                connections,
+                stop,
+                wakeup,
+                thread,
+            }
+        }
+
+        fn get(&self, path: &str) -> String {
+            self.try_get(path).unwrap()
+        }
+
+        fn try_get(&self, path: &str) -> io::Result<String> {
+            let mut stream = std::net::TcpStream::connect(self.addr)?;
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: test\r\n\r\n")?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        }
+
+        fn stop(self) {
+            self.stop.store(true, Ordering::SeqCst);
+            (&self.wakeup).write_all(&[1]).unwrap();
+            assert!(!self.thread.join().unwrap().unwrap());
+        }
+    }
+
+    #[test]
+    fn engines() {
+        let mut engines = vec![IoEngine::Threaded];
+        if cfg!(target_os = "linux") {
+            engines.push(IoEngine::Epoll);
+        }
+        for engine in engines {
+            let server = Running::start(engine, 2);
+            assert!(
+                server.get("/").starts_with("HTTP/1.1 200 OK\r\n"),
+                "{engine}"
+            );
+            assert!(server
+                .get("/missing")
+                .starts_with("HTTP/1.1 404 Not Found\r\n"));
+            server.stop();
+        }
+    }
+
+    #[test]
+    fn connection_limits() {
+        let mut engines = vec![IoEngine::Threaded];
+        if cfg!(target_os = "linux") {
+            engines.push(IoEngine::Epoll);
+        }
+        for io_engine in engines {
+            let server = Running::with(Config {
+                bind: vec!["127.0.0.1:0".to_string()],
+                min_workers: 2,
+                max_workers: 2,
+                max_connections_per_ip: 1,
+                io_engine,
+                ..Config::default()
+            });
+            let idle = std::net::TcpStream::connect(server.addr).unwrap();
+            // Refused as soon as it is accepted, without waiting for a request
+            let mut refused = std::net::TcpStream::connect(server.addr).unwrap();
+            let mut response = String::new();
+            refused.read_to_string(&mut response).unwrap();
+            assert!(
+                response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+                "{io_engine}"
+            );
+            // The slot is given back once the idle connection is closed
+            drop(idle);
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !server
+                .try_get("/")
+                .is_ok_and(|response| response.starts_with("HTTP/1.1 200 OK\r\n"))
+            {
+                assert!(Instant::now() < deadline, "{io_engine}");
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert!(server.connections.stats().refused_per_ip > 0);
+            server.stop();
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn idle_connections_cost_no_worker() {
+        let server = Running::start(IoEngine::Epoll, 1);
+        let idle: Vec<_> = (0..3)
+            .map(|_| std::net::TcpStream::connect(server.addr).unwrap())
+            .collect();
+        // With a single worker, the threaded engine would leave this waiting on the idle connections
+        let started = Instant::now();
+        assert!(server.get("/").starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        drop(idle);
+        server.stop();
+    }
 }
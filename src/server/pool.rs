@@ -1,67 +1,721 @@
 //! Basic Thread Pool Implementation
+//!
+//! The pool keeps at least its minimum number of workers running. When a job is submitted and every
+//! worker is already busy the pool starts another worker, up to its maximum; workers above the
+//! minimum exit once they have been idle for the idle timeout.
+//!
+//! At most `queue_depth` jobs wait for a worker; beyond that, jobs are refused so that a burst of
+//! connections cannot use up memory. Callers check [`ThreadPool::admit`] to turn work away early.
+//!
+//! A job that panics is caught and logged without harming its worker. Should a worker thread die
+//! anyway, it is replaced by a new one.
+
+use std::{
+    any::Any,
+    sync::{atomic::AtomicU64, MutexGuard},
+};
 
 use super::*;
 
-use self::worker::Worker;
+use self::{deadline::Deadline, queue::WorkQueue, worker::Worker};
+
+pub(super) type Job = Box<dyn FnOnce(&Deadline) + Send + 'static>;
+
+/// How often `drain` checks whether in-flight jobs have finished
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often, at most, the watchdog checks for jobs that have run past their deadline
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many workers a pool may have, how long extra workers may sit idle, and how many jobs may
+/// wait for a worker
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) struct PoolSize {
+    pub(super) min: usize,
+    pub(super) max: usize,
+    pub(super) idle_timeout: Duration,
+    pub(super) queue_depth: usize,
+    /// How long a job may run before it is cancelled and reported as overdue
+    pub(super) job_timeout: Duration,
+}
+
+impl PoolSize {
+    /// A pool that always has exactly `size` workers
+    #[cfg(test)]
+    pub(super) fn fixed(size: usize) -> PoolSize {
+        PoolSize {
+            min: size,
+            max: size,
+            idle_timeout: Duration::MAX,
+            queue_depth: usize::MAX,
+            job_timeout: Duration::MAX,
+        }
+    }
+}
+
+/// A snapshot of a pool's activity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct PoolStats {
+    /// Workers currently running
+    pub(super) workers: usize,
+    /// Workers currently running a job
+    pub(super) busy: usize,
+    /// Jobs submitted but not yet finished (queued or running)
+    pub(super) pending: usize,
+    /// Jobs waiting for a worker
+    pub(super) queued: usize,
+    pub(super) min_workers: usize,
+    pub(super) max_workers: usize,
+    pub(super) queue_depth: usize,
+    /// Jobs refused because the queue was full
+    pub(super) rejected: usize,
+    /// Workers started since the pool was built, including the initial ones
+    pub(super) spawned: usize,
+    /// Workers that exited after being idle
+    pub(super) retired: usize,
+    /// Jobs that panicked
+    pub(super) panics: usize,
+    /// Jobs that ran past their deadline
+    pub(super) overdue: usize,
+    /// Jobs finished, including those that panicked
+    pub(super) completed: usize,
+    /// Time spent running the finished jobs
+    pub(super) job_time: Duration,
+    /// The running workers, by id
+    pub(super) per_worker: Vec<WorkerStats>,
+}
+
+impl PoolStats {
+    /// The mean time a finished job took, if any have finished
+    pub(super) fn mean_job_time(&self) -> Option<Duration> {
+        let completed = u32::try_from(self.completed).ok().filter(|&n| n > 0)?;
+        Some(self.job_time / completed)
+    }
+}
+
+/// A snapshot of one worker's activity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct WorkerStats {
+    pub(super) id: usize,
+    /// How long the job the worker is running has been running, if it is running one
+    pub(super) running: Option<Duration>,
+    /// Jobs finished, including those that panicked
+    pub(super) completed: usize,
+    /// Time spent running jobs
+    pub(super) job_time: Duration,
+    /// The longest time a job has taken
+    pub(super) longest_job: Duration,
+}
+
+impl fmt::Display for WorkerStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "worker {}: ", self.id)?;
+        match self.running {
+            Some(running) => write!(f, "running for {running:?}")?,
+            None => write!(f, "idle")?,
+        }
+        write!(
+            f,
+            ", completed {}, job time {:?}, longest job {:?}",
+            self.completed, self.job_time, self.longest_job
+        )
+    }
+}
+
+impl fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "workers {} (min {}, max {}), busy {}, pending {}, queued {} (max {}), rejected {}, \
+             spawned {}, retired {}, panics {}, overdue {}, completed {}, mean job {:?}",
+            self.workers,
+            self.min_workers,
+            self.max_workers,
+            self.busy,
+            self.pending,
+            self.queued,
+            self.queue_depth,
+            self.rejected,
+            self.spawned,
+            self.retired,
+            self.panics,
+            self.overdue,
+            self.completed,
+            self.mean_job_time().unwrap_or_default()
+        )
+    }
+}
+
+/// State shared between a pool, its workers and its monitors
+#[derive(Debug)]
+pub(super) struct Shared {
+    /// Jobs waiting for a worker
+    pub(super) queue: WorkQueue<Job>,
+    /// The worker threads, including any that have exited but not yet been forgotten
+    threads: Mutex<Vec<Worker>>,
+    pub(super) size: PoolSize,
+    pub(super) workers: AtomicUsize,
+    pub(super) busy: AtomicUsize,
+    pending: AtomicUsize,
+    pub(super) queued: AtomicUsize,
+    rejected: AtomicUsize,
+    spawned: AtomicUsize,
+    pub(super) retired: AtomicUsize,
+    pub(super) panics: AtomicUsize,
+    overdue: AtomicUsize,
+    completed: AtomicUsize,
+    /// Time spent running the finished jobs, in nanoseconds
+    job_nanos: AtomicU64,
+}
+
+impl Shared {
+    /// Reserves a place for one more worker, returning whether the pool is below its maximum
+    fn reserve_worker(&self) -> bool {
+        self.workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |workers| {
+                (workers < self.size.max).then_some(workers + 1)
+            })
+            .is_ok()
+    }
+
+    /// Gives up an idle worker's place, returning whether the pool is above its minimum
+    pub(super) fn retire_worker(&self) -> bool {
+        let retired = self
+            .workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |workers| {
+                (workers > self.size.min).then(|| workers - 1)
+            })
+            .is_ok();
+        if retired {
+            self.retired.fetch_add(1, Ordering::SeqCst);
+        }
+        retired
+    }
+
+    /// Starts a worker in a place already reserved for it, forgetting workers that have exited
+    pub(super) fn spawn_worker(self: &Arc<Self>) -> Result<()> {
+        let id = self.spawned.fetch_add(1, Ordering::SeqCst);
+        let worker = Worker::new(id, Arc::clone(self)).inspect_err(|_| {
+            self.workers.fetch_sub(1, Ordering::SeqCst);
+        })?;
+        debug!(
+            "Started worker {id}: {}",
+            PoolMonitor(Arc::clone(self)).stats()
+        );
+        let mut threads = lock(&self.threads);
+        threads.retain(|worker| {
+            worker
+                .thread
+                .as_ref()
+                .is_some_and(|thread| !thread.is_finished())
+        });
+        threads.push(worker);
+        Ok(())
+    }
+
+    /// Counts a finished job that ran for `elapsed`
+    pub(super) fn record_job(&self, elapsed: Duration) {
+        self.completed.fetch_add(1, Ordering::SeqCst);
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.job_nanos.fetch_add(nanos, Ordering::SeqCst);
+    }
+
+    /// Cancels the running jobs that are past their deadline, reporting each once
+    fn cancel_overdue(&self) {
+        for worker in lock(&self.threads).iter() {
+            let mut activity = lock(&worker.activity);
+            let (Some(started), Some(deadline)) = (activity.job_started, &activity.deadline) else {
+                continue;
+            };
+            if activity.overdue || !deadline.expired() {
+                continue;
+            }
+            deadline.cancel();
+            activity.overdue = true;
+            self.overdue.fetch_add(1, Ordering::SeqCst);
+            warn!(
+                "Worker {}: job has run for {:?}, over its {:?} budget; cancelling it",
+                worker.id,
+                started.elapsed(),
+                self.size.job_timeout
+            );
+        }
+    }
+
+    /// Cancels every running job
+    fn cancel_all(&self) {
+        for worker in lock(&self.threads).iter() {
+            if let Some(deadline) = &lock(&worker.activity).deadline {
+                deadline.cancel();
+            }
+        }
+    }
+
+    /// Replaces a worker whose thread is dying from a panic
+    pub(super) fn replace_worker(self: &Arc<Self>, id: usize) {
+        self.workers.fetch_sub(1, Ordering::SeqCst);
+        if self.reserve_worker() {
+            match self.spawn_worker() {
+                Ok(()) => warn!("Worker {id} died; started a replacement"),
+                Err(e) => error!("Worker {id} died and cannot be replaced: {}", e),
+            }
+        }
+    }
+}
+
+/// Locks a mutex, carrying on with the data of one poisoned by a panicking thread: the pool's
+/// counters and queues are left consistent at every point a panic can occur
+pub(super) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
 
-pub(super) type Job = Box<dyn FnOnce() + Send + 'static>;
+/// The message a panic was raised with
+pub(super) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message,
+        _ => "(non-string panic payload)",
+    }
+}
+
+/// A handle for reading a pool's statistics, which may outlive the pool
+#[derive(Debug, Clone)]
+pub(super) struct PoolMonitor(Arc<Shared>);
+
+impl PoolMonitor {
+    pub(super) fn stats(&self) -> PoolStats {
+        let shared = &self.0;
+        PoolStats {
+            workers: shared.workers.load(Ordering::SeqCst),
+            busy: shared.busy.load(Ordering::SeqCst),
+            pending: shared.pending.load(Ordering::SeqCst),
+            queued: shared.queued.load(Ordering::SeqCst),
+            min_workers: shared.size.min,
+            max_workers: shared.size.max,
+            queue_depth: shared.size.queue_depth,
+            rejected: shared.rejected.load(Ordering::SeqCst),
+            spawned: shared.spawned.load(Ordering::SeqCst),
+            retired: shared.retired.load(Ordering::SeqCst),
+            panics: shared.panics.load(Ordering::SeqCst),
+            overdue: shared.overdue.load(Ordering::SeqCst),
+            completed: shared.completed.load(Ordering::SeqCst),
+            job_time: Duration::from_nanos(shared.job_nanos.load(Ordering::SeqCst)),
+            per_worker: self.per_worker(),
+        }
+    }
+
+    // Helper function that reads the activity of each running worker
+    fn per_worker(&self) -> Vec<WorkerStats> {
+        let now = Instant::now();
+        let mut per_worker: Vec<_> = lock(&self.0.threads)
+            .iter()
+            .filter(|worker| {
+                worker
+                    .thread
+                    .as_ref()
+                    .is_some_and(|thread| !thread.is_finished())
+            })
+            .map(|worker| {
+                let activity = lock(&worker.activity);
+                WorkerStats {
+                    id: worker.id,
+                    running: activity
+                        .job_started
+                        .map(|started| now.saturating_duration_since(started)),
+                    completed: activity.completed,
+                    job_time: activity.job_time,
+                    longest_job: activity.longest_job,
+                }
+            })
+            .collect();
+        per_worker.sort_by_key(|worker| worker.id);
+        per_worker
+    }
+}
 
 #[derive(Debug)]
 pub(super) struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    shared: Arc<Shared>,
+    /// The watchdog thread, which stops when the sender is dropped; none when jobs have no deadline
+    watchdog: Option<(mpsc::Sender<()>, thread::JoinHandle<()>)>,
+}
+
+// Decrements the pending job count when a job finishes, even if it panics
+struct PendingGuard(Arc<Shared>);
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.0.pending.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 impl ThreadPool {
     /// Creates a new thread pool
     ///
-    /// Creates a thread pool of size 'size'.
-    ///
-    /// # Panics
-    ///
-    /// `new` will panic if the requested size of the pool is not greater than 1
-    ///
-    pub(super) fn build(size: usize) -> Result<ThreadPool> {
-        if size == 0 {
+    /// Creates a thread pool running `size.min` workers, which may grow to `size.max` workers.
+    /// Returns an error if the minimum is zero or greater than the maximum, or the queue depth is
+    /// zero. Unless the job timeout is unlimited, a watchdog thread cancels overdue jobs.
+    pub(super) fn build(size: PoolSize) -> Result<ThreadPool> {
+        if size.queue_depth == 0 {
             return Err(Error::Channel(
-                "Cannot create a zero sized thread pool".to_string(),
+                "Cannot create a thread pool without a job queue".to_string(),
             ));
         }
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver))?);
+        if size.min == 0 || size.min > size.max {
+            return Err(Error::Channel(format!(
+                "Cannot create a thread pool of between {} and {} workers",
+                size.min, size.max
+            )));
         }
-        Ok(ThreadPool {
-            workers,
-            sender: Some(sender),
-        })
+        let mut pool = ThreadPool {
+            watchdog: None,
+            shared: Arc::new(Shared {
+                queue: WorkQueue::new(),
+                threads: Mutex::new(Vec::with_capacity(size.min)),
+                size,
+                workers: AtomicUsize::new(0),
+                busy: AtomicUsize::new(0),
+                pending: AtomicUsize::new(0),
+                queued: AtomicUsize::new(0),
+                rejected: AtomicUsize::new(0),
+                spawned: AtomicUsize::new(0),
+                retired: AtomicUsize::new(0),
+                panics: AtomicUsize::new(0),
+                overdue: AtomicUsize::new(0),
+                completed: AtomicUsize::new(0),
+                job_nanos: AtomicU64::new(0),
+            }),
+        };
+        for _ in 0..size.min {
+            pool.shared.reserve_worker();
+            pool.shared.spawn_worker()?;
+        }
+        if Instant::now().checked_add(size.job_timeout).is_some() {
+            pool.watchdog = Some(watchdog(Arc::clone(&pool.shared))?);
+        }
+        Ok(pool)
+    }
+
+    /// Whether there is room in the queue for another job; a refusal is counted in the statistics
+    pub(super) fn admit(&self) -> bool {
+        let room = self.shared.queued.load(Ordering::SeqCst) < self.shared.size.queue_depth;
+        if !room {
+            self.shared.rejected.fetch_add(1, Ordering::SeqCst);
+        }
+        room
     }
 
+    /// Queues a job for a worker, returning an error if the queue is full. The job is passed its
+    /// deadline, which it should poll if it may run for long.
     pub(super) fn execute<F>(&self, f: F) -> Result<()>
     where
-        F: FnOnce() + Send + 'static,
+        F: FnOnce(&Deadline) + Send + 'static,
     {
-        let job = Box::new(f);
-        self.sender
-            .as_ref()
-            .ok_or(Error::Channel(
-                "expected 'sender' channel was 'None'".to_string(),
-            ))?
-            .send(job)?;
+        let depth = self.shared.size.queue_depth;
+        self.shared
+            .queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                (queued < depth).then_some(queued + 1)
+            })
+            .map_err(|_| {
+                self.shared.rejected.fetch_add(1, Ordering::SeqCst);
+                Error::Channel(format!("job queue is full ({depth} jobs)"))
+            })?;
+        let guard = PendingGuard(Arc::clone(&self.shared));
+        let pending = self.shared.pending.fetch_add(1, Ordering::SeqCst) + 1;
+        let job = Box::new(move |deadline: &Deadline| {
+            let _guard = guard;
+            f(deadline);
+        });
+        if let Err(e) = self.shared.queue.push(job) {
+            self.shared.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err(e.into());
+        }
+        // Grow when the job would otherwise wait for a busy worker
+        if pending > self.shared.workers.load(Ordering::SeqCst) && self.shared.reserve_worker() {
+            if let Err(e) = self.shared.spawn_worker() {
+                warn!("Cannot grow the thread pool: {}", e);
+            }
+        }
         Ok(())
     }
+
+    /// The number of jobs submitted but not yet finished
+    pub(super) fn pending(&self) -> usize {
+        self.shared.pending.load(Ordering::SeqCst)
+    }
+
+    /// A snapshot of the pool's activity
+    pub(super) fn stats(&self) -> PoolStats {
+        self.monitor().stats()
+    }
+
+    /// A handle for reading the pool's statistics from elsewhere
+    pub(super) fn monitor(&self) -> PoolMonitor {
+        PoolMonitor(Arc::clone(&self.shared))
+    }
+
+    /// Waits up to `timeout` for every submitted job to finish, returning whether they all did
+    pub(super) fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.pending() > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+        true
+    }
+
+    /// Cancels the running jobs and stops tracking the worker threads, so that dropping the pool
+    /// does not wait for them
+    pub(super) fn detach(&mut self) {
+        self.shared.cancel_all();
+        lock(&self.shared.threads).clear();
+    }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        drop(self.sender.take());
-        for worker in &mut self.workers {
+        self.shared.queue.close();
+        if let Some((stop, thread)) = self.watchdog.take() {
+            drop(stop);
+            let _ = thread.join();
+        }
+        // Popped one at a time, since a worker dying meanwhile adds its replacement to the list
+        loop {
+            let Some(mut worker) = lock(&self.shared.threads).pop() else {
+                break;
+            };
             debug!("Shutting down worker {}", worker.id);
             if let Some(thread) = worker.thread.take() {
-                thread.join().expect("unable to join associated thread");
+                if thread.join().is_err() {
+                    warn!("Worker {} panicked while shutting down", worker.id);
+                }
+            }
+        }
+    }
+}
+
+// Helper function that starts the watchdog thread, which checks for overdue jobs until its channel
+// is closed
+fn watchdog(shared: Arc<Shared>) -> Result<(mpsc::Sender<()>, thread::JoinHandle<()>)> {
+    let (stop, stopped) = mpsc::channel::<()>();
+    let interval = WATCHDOG_INTERVAL.min(shared.size.job_timeout / 2);
+    let thread = thread::Builder::new()
+        .name("pool-watchdog".to_string())
+        .spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                shared.cancel_overdue();
+            }
+        })?;
+    Ok((stop, thread))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drain() {
+        let pool = ThreadPool::build(PoolSize::fixed(2)).unwrap();
+        for _ in 0..4 {
+            pool.execute(|_| thread::sleep(Duration::from_millis(20)))
+                .unwrap();
+        }
+        assert!(pool.drain(Duration::from_secs(5)));
+        assert_eq!(0, pool.pending());
+    }
+
+    #[test]
+    fn drain_timeout() {
+        let mut pool = ThreadPool::build(PoolSize::fixed(1)).unwrap();
+        pool.execute(|_| thread::sleep(Duration::from_millis(500)))
+            .unwrap();
+        assert!(!pool.drain(Duration::from_millis(20)));
+        assert_eq!(1, pool.pending());
+        pool.detach();
+    }
+
+    #[test]
+    fn grow_and_shrink() {
+        let pool = ThreadPool::build(PoolSize {
+            min: 1,
+            max: 3,
+            idle_timeout: Duration::from_millis(50),
+            queue_depth: 16,
+            job_timeout: Duration::MAX,
+        })
+        .unwrap();
+        assert_eq!(1, pool.stats().workers);
+        let (sender, receiver) = mpsc::channel::<()>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..5 {
+            let receiver = Arc::clone(&receiver);
+            pool.execute(move |_| {
+                let _ = receiver.lock().unwrap().recv();
+            })
+            .unwrap();
+        }
+        // Every job was waiting for a free worker, but the pool stops at its maximum
+        let stats = pool.stats();
+        assert_eq!((3, 5, 3), (stats.workers, stats.pending, stats.spawned));
+        drop(sender);
+        assert!(pool.drain(Duration::from_secs(5)));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().workers > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let stats = pool.stats();
+        assert_eq!((1, 2), (stats.workers, stats.retired));
+    }
+
+    #[test]
+    fn queue_depth() {
+        let pool = ThreadPool::build(PoolSize {
+            queue_depth: 2,
+            ..PoolSize::fixed(1)
+        })
+        .unwrap();
+        let (sender, receiver) = mpsc::channel::<()>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let job = || {
+            let receiver = Arc::clone(&receiver);
+            move |_: &Deadline| {
+                let _ = receiver.lock().unwrap().recv();
             }
+        };
+        pool.execute(job()).unwrap();
+        // Wait for the worker to take the first job, leaving the queue empty
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().queued > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        pool.execute(job()).unwrap();
+        pool.execute(job()).unwrap();
+        assert!(!pool.admit());
+        assert!(pool.execute(job()).is_err());
+        let stats = pool.stats();
+        assert_eq!((3, 2, 2), (stats.pending, stats.queued, stats.rejected));
+        drop(sender);
+        assert!(pool.drain(Duration::from_secs(5)));
+        assert!(pool.admit());
+    }
+
+    #[test]
+    fn closed_queue() {
+        let pool = ThreadPool::build(PoolSize::fixed(1)).unwrap();
+        pool.shared.queue.close();
+        assert!(pool.execute(|_| {}).is_err());
+        let stats = pool.stats();
+        assert_eq!((0, 0), (stats.pending, stats.queued));
+        assert!(pool.drain(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn panicking_job() {
+        let pool = ThreadPool::build(PoolSize::fixed(1)).unwrap();
+        pool.execute(|_| panic!("job failed")).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move |_| sender.send(()).unwrap()).unwrap();
+        // The same worker goes on to run the next job
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(pool.drain(Duration::from_secs(5)));
+        let stats = pool.stats();
+        assert_eq!(
+            (1, 1, 1, 0),
+            (stats.workers, stats.spawned, stats.panics, stats.busy)
+        );
+    }
+
+    #[test]
+    fn replace_dead_worker() {
+        let pool = ThreadPool::build(PoolSize::fixed(2)).unwrap();
+        pool.shared.replace_worker(0);
+        let stats = pool.stats();
+        assert_eq!((2, 3), (stats.workers, stats.spawned));
+    }
+
+    #[test]
+    fn job_stats() {
+        let pool = ThreadPool::build(PoolSize::fixed(2)).unwrap();
+        let (started, running) = mpsc::channel();
+        let (finish, finished) = mpsc::channel::<()>();
+        pool.execute(move |_| {
+            started.send(()).unwrap();
+            let _ = finished.recv();
+        })
+        .unwrap();
+        pool.execute(|_| thread::sleep(Duration::from_millis(20)))
+            .unwrap();
+        running.recv_timeout(Duration::from_secs(5)).unwrap();
+        // Wait for the short job only
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().completed < 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
         }
+        let stats = pool.stats();
+        assert_eq!((1, 1), (stats.completed, stats.busy));
+        assert!(stats.mean_job_time().unwrap() >= Duration::from_millis(20));
+        assert_eq!(2, stats.per_worker.len());
+        let (running, idle): (Vec<_>, Vec<_>) = stats
+            .per_worker
+            .iter()
+            .partition(|worker| worker.running.is_some());
+        assert_eq!((1, 0), (running.len(), running[0].completed));
+        assert_eq!(1, idle[0].completed);
+        assert!(idle[0].longest_job >= Duration::from_millis(20));
+        drop(finish);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().completed < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(2, pool.stats().completed);
+    }
+
+    #[test]
+    fn watchdog() {
+        let pool = ThreadPool::build(PoolSize {
+            job_timeout: Duration::from_millis(50),
+            ..PoolSize::fixed(1)
+        })
+        .unwrap();
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move |deadline| {
+            // Ignores the deadline for long enough for the watchdog to notice
+            thread::sleep(Duration::from_millis(200));
+            sender.send(deadline.is_cancelled()).unwrap();
+        })
+        .unwrap();
+        assert_eq!(Ok(true), receiver.recv_timeout(Duration::from_secs(5)));
+        assert_eq!(1, pool.stats().overdue);
+    }
+
+    #[test]
+    fn panic_messages() {
+        let payload = std::panic::catch_unwind(|| panic!("at {}", 42)).unwrap_err();
+        assert_eq!("at 42", panic_message(payload.as_ref()));
+        let payload = std::panic::catch_unwind(|| panic!("static")).unwrap_err();
+        assert_eq!("static", panic_message(payload.as_ref()));
+    }
+
+    #[test]
+    fn invalid_size() {
+        assert!(ThreadPool::build(PoolSize::fixed(0)).is_err());
+        let size = PoolSize {
+            min: 4,
+            ..PoolSize::fixed(2)
+        };
+        assert!(ThreadPool::build(size).is_err());
+        let size = PoolSize {
+            queue_depth: 0,
+            ..PoolSize::fixed(2)
+        };
+        assert!(ThreadPool::build(size).is_err());
     }
 }
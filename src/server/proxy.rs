@@ -0,0 +1,439 @@
+//! PROXY Protocol
+//!
+//! Load balancers that forward TCP connections (such as AWS Network Load Balancers) can announce the
+//! original client's address in a header sent before any HTTP data, in either the text (v1) or the
+//! binary (v2) format of the HAProxy PROXY protocol.
+//!
+//! Cf. <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>
+//!
+//! When enabled, connections from trusted sources must start with a PROXY header; any other
+//! connection is taken to come directly from a client and is not inspected for one, so that clients
+//! cannot forge their address. Connections accepted on Unix domain sockets are trusted, since only
+//! local processes allowed by the socket's permissions can make them.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::cidr::Cidr;
+
+use super::*;
+
+/// The longest possible v1 header, including the final CRLF
+const V1_MAX_LEN: u64 = 107;
+
+/// The signature that starts every v2 header
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// v2 commands: a connection made by the proxy itself (e.g. a health check), or a proxied one
+const V2_LOCAL: u8 = 0x20;
+const V2_PROXY: u8 = 0x21;
+
+/// v2 address families (high nibble) and protocols (low nibble): TCP over IPv4 and IPv6
+const V2_TCP4: u8 = 0x11;
+const V2_TCP6: u8 = 0x21;
+
+/// The length of the addresses in a v2 header for the IPv4, IPv6 and Unix socket address families
+const V2_INET_LEN: usize = 12;
+const V2_INET6_LEN: usize = 36;
+const V2_UNIX_LEN: usize = 216;
+
+/// v2 TLV types
+const PP2_TYPE_ALPN: u8 = 0x01;
+const PP2_TYPE_AUTHORITY: u8 = 0x02;
+const PP2_TYPE_CRC32C: u8 = 0x03;
+const PP2_TYPE_UNIQUE_ID: u8 = 0x05;
+/// AWS's custom TLV, whose first byte is a subtype (0x01 for the VPC endpoint ID)
+const PP2_TYPE_AWS: u8 = 0xEA;
+
+/// The proxies trusted to report clients' addresses, and how they report them
+#[derive(Debug, Clone, Default)]
+pub(super) struct TrustedProxies {
+    /// The networks the proxies connect from
+    networks: Vec<Cidr>,
+    /// Whether the proxies' connections start with a PROXY header
+    pub(super) proxy_protocol: bool,
+    /// Whether the proxies' `Forwarded` and `X-Forwarded-*` headers are believed (see [`forwarded`])
+    pub(super) forwarded_headers: bool,
+}
+
+impl TrustedProxies {
+    pub(super) fn new(
+        networks: Vec<Cidr>,
+        proxy_protocol: bool,
+        forwarded_headers: bool,
+    ) -> TrustedProxies {
+        TrustedProxies {
+            networks,
+            proxy_protocol,
+            forwarded_headers,
+        }
+    }
+
+    /// Whether `addr` belongs to a trusted proxy; the peers of Unix domain sockets (`None`) are
+    pub(super) fn trusts(&self, addr: Option<IpAddr>) -> bool {
+        addr.is_none_or(|addr| self.networks.iter().any(|cidr| cidr.contains(addr)))
+    }
+}
+
+/// A type-length-value field of a v2 header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Tlv {
+    pub(super) kind: u8,
+    pub(super) value: Vec<u8>,
+}
+
+/// A parsed PROXY header
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct ProxyHeader {
+    /// The original client's address, or `None` for connections the proxy made on its own behalf
+    /// or whose addresses it did not know
+    pub(super) source: Option<SocketAddr>,
+    /// The address the client connected to
+    pub(super) destination: Option<SocketAddr>,
+    /// Additional information sent in a v2 header
+    pub(super) tlvs: Vec<Tlv>,
+}
+
+impl fmt::Display for ProxyHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.source, self.destination) {
+            (Some(source), Some(destination)) => write!(f, "{source} -> {destination}")?,
+            _ => write!(f, "no addresses")?,
+        }
+        for tlv in &self.tlvs {
+            write!(f, ", {tlv}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Tlv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = String::from_utf8_lossy(&self.value);
+        match (self.kind, self.value.split_first()) {
+            (PP2_TYPE_ALPN, _) => write!(f, "alpn={text}"),
+            (PP2_TYPE_AUTHORITY, _) => write!(f, "authority={text}"),
+            (PP2_TYPE_CRC32C, _) => write!(f, "crc32c={}", hex(&self.value)),
+            (PP2_TYPE_UNIQUE_ID, _) => write!(f, "unique_id={}", hex(&self.value)),
+            (PP2_TYPE_AWS, Some((0x01, vpce))) => {
+                write!(f, "aws_vpce_id={}", String::from_utf8_lossy(vpce))
+            }
+            (kind, _) => write!(f, "{kind:#04x}={}", hex(&self.value)),
+        }
+    }
+}
+
+/// Reads a v1 or v2 PROXY header from the start of a connection, leaving the rest unread
+pub(super) fn read_header(reader: &mut impl BufRead) -> Result<ProxyHeader> {
+    match reader.fill_buf()?.first() {
+        Some(b'P') => read_v1(reader),
+        Some(b'\r') => read_v2(reader),
+        Some(_) => Err(invalid("missing PROXY header")),
+        None => Err(invalid("connection closed before PROXY header")),
+    }
+}
+
+// Helper function that reads a v1 (text) header: "PROXY TCP4 <src> <dst> <src port> <dst port>\r\n"
+fn read_v1(reader: &mut impl BufRead) -> Result<ProxyHeader> {
+    let mut line = Vec::new();
+    reader.take(V1_MAX_LEN).read_until(b'\n', &mut line)?;
+    let line = std::str::from_utf8(&line)
+        .ok()
+        .and_then(|line| line.strip_suffix("\r\n"))
+        .ok_or_else(|| invalid("v1 header is not a CRLF terminated line"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields[..] {
+        ["PROXY", "UNKNOWN", ..] => Ok(ProxyHeader::default()),
+        ["PROXY", family @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] =>
+        {
+            let addr = |ip: &str, port: &str| -> Result<SocketAddr> {
+                let ip: IpAddr = ip
+                    .parse()
+                    .map_err(|_| invalid(format!("v1 header: invalid address '{ip}'")))?;
+                if ip.is_ipv4() != (family == "TCP4") {
+                    return Err(invalid(format!(
+                        "v1 header: '{ip}' is not a {family} address"
+                    )));
+                }
+                let port = port
+                    .parse()
+                    .map_err(|_| invalid(format!("v1 header: invalid port '{port}'")))?;
+                Ok(SocketAddr::new(ip, port))
+            };
+            Ok(ProxyHeader {
+                source: Some(addr(source, source_port)?),
+                destination: Some(addr(destination, destination_port)?),
+                tlvs: Vec::new(),
+            })
+        }
+        _ => Err(invalid(format!("invalid v1 header: '{line}'"))),
+    }
+}
+
+// Helper function that reads a v2 (binary) header: a 16 byte preamble followed by the addresses and
+// any TLVs
+fn read_v2(reader: &mut impl BufRead) -> Result<ProxyHeader> {
+    let mut preamble = [0; 16];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..12] != V2_SIGNATURE {
+        return Err(invalid("invalid v2 signature"));
+    }
+    let (command, family) = (preamble[12], preamble[13]);
+    let mut body = vec![0; usize::from(u16::from_be_bytes([preamble[14], preamble[15]]))];
+    reader.read_exact(&mut body)?;
+    let addrs_len = match family >> 4 {
+        0x1 => V2_INET_LEN,
+        0x2 => V2_INET6_LEN,
+        0x3 => V2_UNIX_LEN,
+        // Addresses of an unspecified family are skipped along with anything following them
+        _ => body.len(),
+    };
+    if body.len() < addrs_len {
+        return Err(invalid("v2 header: truncated addresses"));
+    }
+    let (source, destination) = match (command, family) {
+        (V2_PROXY, V2_TCP4) => {
+            let ip = |at: usize| IpAddr::V4(Ipv4Addr::from(be_u32(&body[at..])));
+            (
+                Some(SocketAddr::new(ip(0), be_u16(&body[8..]))),
+                Some(SocketAddr::new(ip(4), be_u16(&body[10..]))),
+            )
+        }
+        (V2_PROXY, V2_TCP6) => {
+            let ip = |at: usize| IpAddr::V6(Ipv6Addr::from(be_u128(&body[at..])));
+            (
+                Some(SocketAddr::new(ip(0), be_u16(&body[32..]))),
+                Some(SocketAddr::new(ip(16), be_u16(&body[34..]))),
+            )
+        }
+        // LOCAL connections and other families (UDP, Unix sockets) carry no usable client address
+        (V2_PROXY | V2_LOCAL, _) => (None, None),
+        _ => {
+            return Err(invalid(format!(
+                "v2 header: unsupported command {command:#04x}"
+            )))
+        }
+    };
+    let tlvs = parse_tlvs(&body[addrs_len..])?;
+    if let Some(crc) = tlvs.iter().find(|tlv| tlv.kind == PP2_TYPE_CRC32C) {
+        verify_crc32c(&preamble, &body, addrs_len, &crc.value)?;
+    }
+    Ok(ProxyHeader {
+        source,
+        destination,
+        tlvs,
+    })
+}
+
+// Helper function that splits the end of a v2 header into TLVs
+fn parse_tlvs(mut bytes: &[u8]) -> Result<Vec<Tlv>> {
+    let mut tlvs = Vec::new();
+    while !bytes.is_empty() {
+        if bytes.len() < 3 {
+            return Err(invalid("v2 header: truncated TLV"));
+        }
+        let len = usize::from(be_u16(&bytes[1..]));
+        let value = bytes
+            .get(3..3 + len)
+            .ok_or_else(|| invalid("v2 header: truncated TLV"))?;
+        tlvs.push(Tlv {
+            kind: bytes[0],
+            value: value.to_vec(),
+        });
+        bytes = &bytes[3 + len..];
+    }
+    Ok(tlvs)
+}
+
+// Helper function that checks a v2 header's checksum, which is computed over the whole header with
+// the checksum's own value zeroed
+fn verify_crc32c(preamble: &[u8], body: &[u8], tlvs_at: usize, expected: &[u8]) -> Result<()> {
+    let expected: [u8; 4] = expected
+        .try_into()
+        .map_err(|_| invalid("v2 header: invalid CRC32C TLV"))?;
+    let mut zeroed = body.to_vec();
+    let mut at = tlvs_at;
+    while at < zeroed.len() {
+        let len = usize::from(be_u16(&zeroed[at + 1..]));
+        if zeroed[at] == PP2_TYPE_CRC32C {
+            zeroed[at + 3..at + 3 + len].fill(0);
+        }
+        at += 3 + len;
+    }
+    if crc32c(&[preamble, &zeroed].concat()) != u32::from_be_bytes(expected) {
+        return Err(invalid("v2 header: CRC32C mismatch"));
+    }
+    Ok(())
+}
+
+// Helper function that computes a CRC-32C (Castagnoli) checksum
+fn crc32c(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            (crc >> 1) ^ (0x82F6_3B78 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().unwrap())
+}
+
+fn be_u128(bytes: &[u8]) -> u128 {
+    u128::from_be_bytes(bytes[..16].try_into().unwrap())
+}
+
+fn invalid(message: impl fmt::Display) -> Error {
+    Error::InvalidRequest(format!("PROXY protocol: {message}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds a v2 header, filling in the checksum TLV if one is given
+    fn v2(command: u8, family: u8, addrs: &[u8], tlvs: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut body = addrs.to_vec();
+        for (kind, value) in tlvs {
+            body.push(*kind);
+            body.extend((value.len() as u16).to_be_bytes());
+            body.extend(*value);
+        }
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([command, family]);
+        header.extend((body.len() as u16).to_be_bytes());
+        header.extend(body);
+        if let Some(at) = tlvs.iter().position(|(kind, _)| *kind == PP2_TYPE_CRC32C) {
+            let offset = 16
+                + addrs.len()
+                + tlvs[..at]
+                    .iter()
+                    .map(|(_, value)| 3 + value.len())
+                    .sum::<usize>()
+                + 3;
+            let crc = crc32c(&header);
+            header[offset..offset + 4].copy_from_slice(&crc.to_be_bytes());
+        }
+        header
+    }
+
+    fn read(bytes: &[u8]) -> (Result<ProxyHeader>, String) {
+        let mut reader = io::Cursor::new(bytes);
+        let header = read_header(&mut reader);
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        (header, rest)
+    }
+
+    #[test]
+    fn v1() {
+        let (header, rest) =
+            read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\n");
+        let header = header.unwrap();
+        assert_eq!(Some("192.0.2.1:56324".parse().unwrap()), header.source);
+        assert_eq!(
+            Some("198.51.100.1:443".parse().unwrap()),
+            header.destination
+        );
+        assert_eq!("GET / HTTP/1.1\r\n", rest);
+        let (header, _) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 1 2\r\n");
+        assert_eq!(
+            Some("[2001:db8::1]:1".parse().unwrap()),
+            header.unwrap().source
+        );
+        let (header, _) = read(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n");
+        assert_eq!(ProxyHeader::default(), header.unwrap());
+        for invalid in [
+            &b"PROXY TCP4 2001:db8::1 192.0.2.1 1 2\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 1\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 1 99999\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 1 2\n",
+            b"GET / HTTP/1.1\r\n",
+            b"",
+        ] {
+            assert!(read(invalid).0.is_err());
+        }
+        // Headers longer than the protocol allows are not read without bound
+        let long = format!("PROXY TCP4 {}\r\n", "1".repeat(200));
+        assert!(read(long.as_bytes()).0.is_err());
+    }
+
+    #[test]
+    fn v2_addresses() {
+        let addrs = [192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb];
+        let mut bytes = v2(V2_PROXY, V2_TCP4, &addrs, &[]);
+        bytes.extend(b"GET / HTTP/1.1\r\n");
+        let (header, rest) = read(&bytes);
+        let header = header.unwrap();
+        assert_eq!(Some("192.0.2.1:56324".parse().unwrap()), header.source);
+        assert_eq!(
+            Some("198.51.100.1:443".parse().unwrap()),
+            header.destination
+        );
+        assert_eq!("GET / HTTP/1.1\r\n", rest);
+
+        let mut addrs = Vec::new();
+        addrs.extend("2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        addrs.extend("2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        addrs.extend([0, 1, 0, 2]);
+        let (header, _) = read(&v2(V2_PROXY, V2_TCP6, &addrs, &[]));
+        assert_eq!(
+            Some("[2001:db8::1]:1".parse().unwrap()),
+            header.unwrap().source
+        );
+
+        // Health checks by the proxy itself carry no client address
+        let (header, _) = read(&v2(V2_LOCAL, 0x00, &[], &[]));
+        assert_eq!(None, header.unwrap().source);
+        assert!(read(&v2(V2_PROXY, V2_TCP4, &[192, 0, 2, 1], &[]))
+            .0
+            .is_err());
+        assert!(read(&v2(0x22, V2_TCP4, &[0; 12], &[])).0.is_err());
+        let mut bad_signature = v2(V2_PROXY, V2_TCP4, &[0; 12], &[]);
+        bad_signature[11] = b'X';
+        assert!(read(&bad_signature).0.is_err());
+    }
+
+    #[test]
+    fn v2_tlvs() {
+        let addrs = [192, 0, 2, 1, 198, 51, 100, 1, 0, 1, 0, 2];
+        let tlvs: &[(u8, &[u8])] = &[
+            (PP2_TYPE_AUTHORITY, b"ptodd.org"),
+            (PP2_TYPE_CRC32C, &[0; 4]),
+            (PP2_TYPE_AWS, b"\x01vpce-0123"),
+        ];
+        let bytes = v2(V2_PROXY, V2_TCP4, &addrs, tlvs);
+        let header = read(&bytes).0.unwrap();
+        assert_eq!(3, header.tlvs.len());
+        let text = header.to_string();
+        assert!(text.starts_with("192.0.2.1:1 -> 198.51.100.1:2, authority=ptodd.org, crc32c="));
+        assert!(text.ends_with(", aws_vpce_id=vpce-0123"));
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() = b'4';
+        assert!(read(&corrupted).0.is_err());
+        let mut truncated = v2(V2_PROXY, V2_TCP4, &addrs, &[(PP2_TYPE_ALPN, b"h2")]);
+        truncated[15] -= 1;
+        assert!(read(&truncated).0.is_err());
+    }
+
+    #[test]
+    fn crc() {
+        // The standard CRC-32C check value
+        assert_eq!(0xE306_9283, crc32c(b"123456789"));
+    }
+
+    #[test]
+    fn trust() {
+        let proxies = TrustedProxies::new(vec!["10.0.0.0/8".parse().unwrap()], true, false);
+        assert!(proxies.trusts(Some("10.1.2.3".parse().unwrap())));
+        assert!(!proxies.trusts(Some("192.0.2.1".parse().unwrap())));
+        assert!(proxies.trusts(None));
+    }
+}
@@ -0,0 +1,264 @@
+//! Work Queue
+//!
+//! A multi-producer, multi-consumer FIFO queue shared by the pool and its workers. The lock is held
+//! only to push or pop an item, never while waiting: idle workers wait on a condition variable, and
+//! each push wakes exactly one of them. Closing the queue wakes them all; they finish the items
+//! already queued and are then told the queue is closed.
+//!
+//! Errors reuse those of `std::sync::mpsc`, which this queue replaces.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{RecvTimeoutError, SendError},
+        Condvar,
+    },
+};
+
+use super::*;
+
+use self::pool::lock;
+
+struct State<T> {
+    items: VecDeque<T>,
+    closed: bool,
+    /// Consumers waiting for an item, so that pushes only signal when someone will wake
+    waiting: usize,
+}
+
+/// A FIFO queue that any number of threads may push to and pop from
+pub(super) struct WorkQueue<T> {
+    state: Mutex<State<T>>,
+    /// Signalled when an item is pushed or the queue is closed
+    available: Condvar,
+}
+
+impl<T> WorkQueue<T> {
+    pub(super) fn new() -> WorkQueue<T> {
+        WorkQueue {
+            state: Mutex::new(State {
+                items: VecDeque::new(),
+                closed: false,
+                waiting: 0,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Adds an item and wakes one waiting consumer, returning the item if the queue is closed
+    pub(super) fn push(&self, item: T) -> result::Result<(), SendError<T>> {
+        let mut state = lock(&self.state);
+        if state.closed {
+            return Err(SendError(item));
+        }
+        state.items.push_back(item);
+        let waiting = state.waiting > 0;
+        drop(state);
+        if waiting {
+            self.available.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Takes the oldest item, waiting up to `timeout` for one to arrive. Once the queue is closed
+    /// and empty, returns `Disconnected`.
+    pub(super) fn pop_timeout(&self, timeout: Duration) -> result::Result<T, RecvTimeoutError> {
+        // An overlong timeout (e.g. `Duration::MAX`) means waiting indefinitely
+        let deadline = Instant::now().checked_add(timeout);
+        let mut state = lock(&self.state);
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                return Ok(item);
+            }
+            if state.closed {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state.waiting += 1;
+            state = match deadline {
+                Some(deadline) => {
+                    self.available
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                }
+                None => self
+                    .available
+                    .wait(state)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+            state.waiting -= 1;
+        }
+    }
+
+    /// Refuses further items and wakes every waiting consumer
+    pub(super) fn close(&self) {
+        lock(&self.state).closed = true;
+        self.available.notify_all();
+    }
+}
+
+impl<T> fmt::Debug for WorkQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = lock(&self.state);
+        f.debug_struct("WorkQueue")
+            .field("len", &state.items.len())
+            .field("closed", &state.closed)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fifo() {
+        let queue = WorkQueue::new();
+        queue.push(1).unwrap();
+        queue.push(2).unwrap();
+        assert_eq!(Ok(1), queue.pop_timeout(Duration::ZERO));
+        assert_eq!(Ok(2), queue.pop_timeout(Duration::ZERO));
+        assert_eq!(
+            Err(RecvTimeoutError::Timeout),
+            queue.pop_timeout(Duration::from_millis(10))
+        );
+    }
+
+    #[test]
+    fn close() {
+        let queue = WorkQueue::new();
+        queue.push(1).unwrap();
+        queue.close();
+        assert_eq!(Err(SendError(2)), queue.push(2));
+        // Items queued before closing are still handed out
+        assert_eq!(Ok(1), queue.pop_timeout(Duration::MAX));
+        assert_eq!(
+            Err(RecvTimeoutError::Disconnected),
+            queue.pop_timeout(Duration::MAX)
+        );
+    }
+
+    #[test]
+    fn wakes_waiting_consumers() {
+        let queue = Arc::new(WorkQueue::new());
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || {
+                    let mut sum = 0;
+                    while let Ok(item) = queue.pop_timeout(Duration::MAX) {
+                        sum += item;
+                    }
+                    sum
+                })
+            })
+            .collect();
+        for item in 1..=100 {
+            queue.push(item).unwrap();
+        }
+        queue.close();
+        let sum: i32 = consumers
+            .into_iter()
+            .map(|consumer| consumer.join().unwrap())
+            .sum();
+        assert_eq!(5050, sum);
+    }
+
+    /// Compares the throughput and wait times of this queue with those of the `mpsc::Receiver`
+    /// behind a `Mutex` that it replaced. Run with
+    /// `cargo test --release -- --ignored --nocapture benchmark`.
+    #[test]
+    #[ignore]
+    fn benchmark() {
+        const JOBS: usize = 200_000;
+        for workers in [4, 16, 64] {
+            for (design, (elapsed, mut waits)) in [
+                ("mutex receiver", run_mutex_receiver(workers, JOBS)),
+                ("work queue", run_work_queue(workers, JOBS)),
+            ] {
+                waits.sort_unstable();
+                let percentile = |p: usize| waits[(waits.len() - 1) * p / 100];
+                println!(
+                    "{workers:>2} workers, {design:<14}: {:>9.0} jobs/s, wait p50 {:>9?} \
+                     p99 {:>9?} max {:>9?}",
+                    JOBS as f64 / elapsed.as_secs_f64(),
+                    percentile(50),
+                    percentile(99),
+                    percentile(100)
+                );
+            }
+        }
+    }
+
+    // A job stamped with when it was queued; running it records how long it waited
+    type Stamped = Instant;
+
+    // Helper function that runs `jobs` jobs on `workers` threads popping from a `WorkQueue`,
+    // returning the time taken and each job's wait for a worker
+    fn run_work_queue(workers: usize, jobs: usize) -> (Duration, Vec<Duration>) {
+        let queue = Arc::new(WorkQueue::<Stamped>::new());
+        let start = Instant::now();
+        let threads: Vec<_> = (0..workers)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || {
+                    let mut waits = Vec::new();
+                    while let Ok(queued) = queue.pop_timeout(Duration::MAX) {
+                        waits.push(queued.elapsed());
+                    }
+                    waits
+                })
+            })
+            .collect();
+        for _ in 0..jobs {
+            queue.push(Instant::now()).unwrap();
+        }
+        queue.close();
+        collect(start, threads)
+    }
+
+    // Helper function that runs the same load through an `mpsc::Receiver` shared behind a `Mutex`,
+    // locked while waiting as the workers used to
+    fn run_mutex_receiver(workers: usize, jobs: usize) -> (Duration, Vec<Duration>) {
+        let (sender, receiver) = mpsc::channel::<Stamped>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let start = Instant::now();
+        let threads: Vec<_> = (0..workers)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || {
+                    let mut waits = Vec::new();
+                    loop {
+                        let message = receiver.lock().unwrap().recv();
+                        match message {
+                            Ok(queued) => waits.push(queued.elapsed()),
+                            Err(_) => break,
+                        }
+                    }
+                    waits
+                })
+            })
+            .collect();
+        for _ in 0..jobs {
+            sender.send(Instant::now()).unwrap();
+        }
+        drop(sender);
+        collect(start, threads)
+    }
+
+    // Helper function that waits for the benchmark's workers and gathers their measurements
+    fn collect(
+        start: Instant,
+        threads: Vec<thread::JoinHandle<Vec<Duration>>>,
+    ) -> (Duration, Vec<Duration>) {
+        let waits = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        (start.elapsed(), waits)
+    }
+}
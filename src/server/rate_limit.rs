@@ -0,0 +1,401 @@
+//! Per-Client Rate Limiting
+//!
+//! Each route group gives every client a token bucket holding up to `burst` tokens, refilled at the
+//! group's rate. A request takes a token; a request finding the bucket empty gets a 429 (Too Many
+//! Requests) with a `Retry-After` header. Responses in a group carry the client's quota in the
+//! `RateLimit-Limit`, `RateLimit-Remaining`, `RateLimit-Reset` and `RateLimit-Policy` headers.
+//!
+//! Clients are tracked by a hash of their key, so each costs the same few bytes whatever the key.
+//! A bucket that has refilled is as good as none, so idle clients are forgotten every
+//! [`EVICTION_INTERVAL`], and when a group tracks `max_clients` clients a new one takes the place of
+//! the least recently seen. Requests with no key (a Unix domain socket connection that was not
+//! forwarded, without the key header) are not limited. Rate limiting is a [`Middleware`] layer.
+//!
+//! Cf. <https://datatracker.ietf.org/doc/html/draft-ietf-httpapi-ratelimit-headers-07>
+
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+};
+
+use crate::config::{RateKey, RateLimits};
+
+use super::*;
+use middleware::Middleware;
+
+/// How often clients whose buckets have refilled are forgotten
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Rate limits requests by client, per route group
+#[derive(Debug)]
+pub(super) struct RateLimiter {
+    key: RateKey,
+    max_clients: usize,
+    groups: Vec<Group>,
+    hasher: RandomState,
+}
+
+/// A route group's rate limit and its clients' buckets
+#[derive(Debug)]
+struct Group {
+    prefix: String,
+    /// Tokens added per second
+    rate: f64,
+    /// Most tokens a bucket holds
+    burst: f64,
+    /// The `RateLimit-Policy` header value
+    policy: String,
+    clients: Mutex<Clients>,
+}
+
+#[derive(Debug)]
+struct Clients {
+    buckets: HashMap<u64, Bucket>,
+    /// When idle clients were last forgotten
+    evicted: Instant,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A client's standing in a route group after a request
+#[derive(Debug)]
+pub(super) struct Quota<'a> {
+    /// Whether the request may proceed
+    pub(super) allowed: bool,
+    limit: u32,
+    remaining: u32,
+    /// How long until the bucket is full again
+    reset: Duration,
+    /// How long until the bucket holds a token again
+    retry_after: Duration,
+    policy: &'a str,
+}
+
+impl RateLimiter {
+    pub(super) fn new(limits: &RateLimits) -> RateLimiter {
+        let now = Instant::now();
+        RateLimiter {
+            key: limits.key.clone(),
+            max_clients: limits.max_clients,
+            groups: limits
+                .groups
+                .iter()
+                .map(|group| Group {
+                    prefix: group.prefix.clone(),
+                    rate: group.rate.per_second(),
+                    burst: f64::from(group.burst()),
+                    policy: format!(
+                        "{};w={};burst={}",
+                        group.rate.requests,
+                        group.rate.per.as_secs(),
+                        group.burst()
+                    ),
+                    clients: Mutex::new(Clients {
+                        buckets: HashMap::new(),
+                        evicted: now,
+                    }),
+                })
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Takes a token from the client's bucket in the request's route group, returning the client's
+    /// quota, or `None` when the request is not limited
+    pub(super) fn check(&self, request: &Request) -> Option<Quota<'_>> {
+        self.check_at(request, Instant::now())
+    }
+
+    /// The client's quota in the request's route group, without taking a token
+    pub(super) fn peek(&self, request: &Request) -> Option<Quota<'_>> {
+        let now = Instant::now();
+        let group = self.group(request)?;
+        let key = self.client_key(request)?;
+        let tokens = match pool::lock(&group.clients).buckets.get(&key) {
+            Some(bucket) => group.refilled(bucket, now),
+            None => group.burst,
+        };
+        Some(group.quota(tokens >= 1.0, tokens))
+    }
+
+    // Takes a token as of `now`
+    fn check_at(&self, request: &Request, now: Instant) -> Option<Quota<'_>> {
+        let group = self.group(request)?;
+        let key = self.client_key(request)?;
+        let mut clients = pool::lock(&group.clients);
+        if now.saturating_duration_since(clients.evicted) >= EVICTION_INTERVAL {
+            group.evict_idle(&mut clients, now);
+        }
+        if !clients.buckets.contains_key(&key) && clients.buckets.len() >= self.max_clients {
+            group.evict_idle(&mut clients, now);
+            if clients.buckets.len() >= self.max_clients {
+                let oldest = clients
+                    .buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(&key, _)| key);
+                if let Some(oldest) = oldest {
+                    clients.buckets.remove(&oldest);
+                }
+            }
+        }
+        let bucket = clients.buckets.entry(key).or_insert(Bucket {
+            tokens: group.burst,
+            updated: now,
+        });
+        bucket.tokens = group.refilled(bucket, now);
+        bucket.updated = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        Some(group.quota(allowed, bucket.tokens))
+    }
+
+    // Helper function that finds the route group with the longest prefix matching the request's path
+    fn group(&self, request: &Request) -> Option<&Group> {
+        let path = request.target.path();
+        self.groups
+            .iter()
+            .filter(|group| path.starts_with(&group.prefix))
+            .max_by_key(|group| group.prefix.len())
+    }
+
+    // Helper function that hashes what identifies the request's client, if anything does
+    fn client_key(&self, request: &Request) -> Option<u64> {
+        if let RateKey::Header(name) = &self.key {
+            if let Some(value) = request.header(name) {
+                return Some(self.hasher.hash_one(("header", value)));
+            }
+        }
+        let ip = request.client.ip?.to_canonical();
+        Some(self.hasher.hash_one(("ip", ip)))
+    }
+}
+
+impl Group {
+    // The quota of a client with `tokens` left in its bucket
+    fn quota(&self, allowed: bool, tokens: f64) -> Quota<'_> {
+        Quota {
+            allowed,
+            limit: self.burst as u32,
+            remaining: tokens as u32,
+            reset: Duration::from_secs_f64((self.burst - tokens) / self.rate),
+            retry_after: Duration::from_secs_f64((1.0 - tokens).max(0.0) / self.rate),
+            policy: &self.policy,
+        }
+    }
+
+    // The tokens in a bucket once topped up for the time since it was last updated
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+
+    // Forgets the clients whose buckets have refilled
+    fn evict_idle(&self, clients: &mut Clients, now: Instant) {
+        let before = clients.buckets.len();
+        clients
+            .buckets
+            .retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+        clients.evicted = now;
+        if clients.buckets.len() < before {
+            debug!(
+                "rate limit ({}): forgot {} idle clients",
+                self.prefix,
+                before - clients.buckets.len()
+            );
+        }
+    }
+}
+
+impl Quota<'_> {
+    /// Adds the quota's `RateLimit-*` header fields to a response
+    pub(super) fn annotate(&self, response: Response) -> Response {
+        response
+            .with_header("RateLimit-Limit", self.limit.to_string())
+            .with_header("RateLimit-Remaining", self.remaining.to_string())
+            .with_header("RateLimit-Reset", whole_seconds(self.reset).to_string())
+            .with_header("RateLimit-Policy", self.policy)
+    }
+
+    /// The response to a request over the limit
+    pub(super) fn rejection(&self) -> Response {
+        let retry_after = whole_seconds(self.retry_after).max(1);
+        self.annotate(
+            Response::text(
+                Status::TOO_MANY_REQUESTS,
+                format!("too many requests; try again in {retry_after}s\n"),
+            )
+            .with_header("Retry-After", retry_after.to_string()),
+        )
+    }
+}
+
+impl Middleware for RateLimiter {
+    fn before(&self, request: &Request) -> Option<Response> {
+        let quota = self.check(request)?;
+        if quota.allowed {
+            return None;
+        }
+        debug!(
+            "Rate limited {:?}: {} {}",
+            request.client.ip, request.method, request.target
+        );
+        Some(quota.rejection())
+    }
+
+    fn after(&self, request: &Request, response: Response) -> Response {
+        match self.peek(request) {
+            Some(quota) => quota.annotate(response),
+            None => response,
+        }
+    }
+}
+
+// Helper function that rounds a duration up to whole seconds
+fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use crate::config::RateGroup;
+
+    use super::*;
+
+    fn limiter(key: RateKey, max_clients: usize) -> RateLimiter {
+        let group = |name: &str, prefix: &str, rate: &str, burst| RateGroup {
+            name: name.to_string(),
+            prefix: prefix.to_string(),
+            rate: rate.parse().unwrap(),
+            burst,
+        };
+        RateLimiter::new(&RateLimits {
+            key,
+            max_clients,
+            groups: vec![
+                group("site", "/", "1/s", Some(2)),
+                group("api", "/api/", "60/m", Some(1)),
+            ],
+        })
+    }
+
+    fn request(path: &str, ip: &str, headers: &[&str]) -> Request {
+        let mut lines = vec![format!("GET {path} HTTP/1.1")];
+        lines.extend(headers.iter().map(|line| line.to_string()));
+        let mut request = Request::parse(&lines).unwrap();
+        request.client.ip = ip.parse::<IpAddr>().ok();
+        request
+    }
+
+    #[test]
+    fn buckets() {
+        let limiter = limiter(RateKey::Ip, 100);
+        let now = Instant::now();
+        let site = request("/", "10.0.0.1", &[]);
+        let first = limiter.check_at(&site, now).unwrap();
+        assert!(first.allowed);
+        assert_eq!((2, 1), (first.limit, first.remaining));
+        assert!(limiter.check_at(&site, now).unwrap().allowed);
+        let limited = limiter.check_at(&site, now).unwrap();
+        assert!(!limited.allowed);
+        assert_eq!(Duration::from_secs(1), limited.retry_after);
+        let response = limited.rejection();
+        assert_eq!(Status::TOO_MANY_REQUESTS, response.status);
+        let header = |name: &str| {
+            response
+                .headers
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(Some("1"), header("Retry-After"));
+        assert_eq!(Some("0"), header("RateLimit-Remaining"));
+        assert_eq!(Some("2"), header("RateLimit-Reset"));
+        assert_eq!(Some("1;w=1;burst=2"), header("RateLimit-Policy"));
+        // Refills over time
+        let later = now + Duration::from_millis(1500);
+        assert!(limiter.check_at(&site, later).unwrap().allowed);
+        // The longest prefix decides the group, and clients have buckets of their own
+        let api = request("/api/items", "10.0.0.1", &[]);
+        assert!(limiter.check_at(&api, now).unwrap().allowed);
+        assert!(!limiter.check_at(&api, now).unwrap().allowed);
+        assert!(
+            limiter
+                .check_at(&request("/api/items", "10.0.0.2", &[]), now)
+                .unwrap()
+                .allowed
+        );
+        // Not limited without a key
+        assert!(limiter.check_at(&request("/", "", &[]), now).is_none());
+    }
+
+    #[test]
+    fn header_key() {
+        let limiter = limiter(RateKey::Header("X-Api-Key".to_string()), 100);
+        let now = Instant::now();
+        let api = |ip, key| request("/api/", ip, &[key]);
+        assert!(
+            limiter
+                .check_at(&api("10.0.0.1", "X-Api-Key: a"), now)
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            !limiter
+                .check_at(&api("10.0.0.2", "X-Api-Key: a"), now)
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            limiter
+                .check_at(&api("10.0.0.2", "X-Api-Key: b"), now)
+                .unwrap()
+                .allowed
+        );
+        // Falls back to the address without the header
+        assert!(
+            limiter
+                .check_at(&api("10.0.0.2", "Accept: */*"), now)
+                .unwrap()
+                .allowed
+        );
+    }
+
+    #[test]
+    fn eviction() {
+        let limiter = limiter(RateKey::Ip, 2);
+        let now = Instant::now();
+        let clients = || pool::lock(&limiter.groups[1].clients).buckets.len();
+        for (millis, ip) in [(0, "10.0.0.1"), (1, "10.0.0.2"), (2, "10.0.0.3")] {
+            limiter.check_at(
+                &request("/api/", ip, &[]),
+                now + Duration::from_millis(millis),
+            );
+        }
+        assert_eq!(2, clients());
+        // The least recently seen client was forgotten, so its bucket starts out full
+        assert!(
+            limiter
+                .check_at(
+                    &request("/api/", "10.0.0.1", &[]),
+                    now + Duration::from_millis(3)
+                )
+                .unwrap()
+                .allowed
+        );
+        // Buckets that have refilled are forgotten after the eviction interval
+        let later = now + EVICTION_INTERVAL;
+        limiter.check_at(&request("/", "10.0.0.4", &[]), later);
+        assert_eq!(2, clients());
+        limiter.check_at(&request("/api/", "10.0.0.4", &[]), later);
+        assert_eq!(1, clients());
+    }
+}
@@ -7,7 +7,7 @@ use super::*;
 /// Request Methods (RFC-9110 7.1)
 ///
 /// Cf. <https://datatracker.ietf.org/doc/html/rfc9110#name-overview>
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub(super) enum RequestMethod {
     Get,
     Head,
@@ -47,7 +47,10 @@ impl TryFrom<&str> for RequestMethod {
             "CONNECT" => Ok(RequestMethod::Connect),
             "OPTIONS" => Ok(RequestMethod::Options),
             "TRACE" => Ok(RequestMethod::Trace),
-            _ => Err(Error::InvalidRequest(format!("invalid method: {value}"))),
+            _ => Err(Error::Rejected(
+                Status::NOT_IMPLEMENTED.0,
+                format!("unsupported method: {value}"),
+            )),
         }
     }
 }
@@ -60,6 +63,18 @@ pub(super) struct Request {
     pub(super) method: RequestMethod,
     // The request target (RFC-9110 7.1)
     pub(super) target: Url,
+    // Header fields (RFC-9110 6.3) in the order received
+    pub(super) headers: Vec<(String, String)>,
+    // The message body (RFC-9110 6.4), empty if there is none (see [`body`])
+    pub(super) body: Vec<u8>,
+    // The parameters captured from the path by the matching route's pattern (see [`router`]), still
+    // percent-encoded
+    pub(super) params: Vec<(String, String)>,
+    // The client, as reported by trusted proxies (see [`proxy`] and [`forwarded`])
+    pub(super) client: ClientInfo,
+    // The deadline of the job handling the request, which long-running handlers poll (see
+    // [`deadline`])
+    pub(super) deadline: Deadline,
 }
 
 impl Request {
@@ -77,14 +92,102 @@ impl Request {
             )));
         }
         if control_data_parts[2] != "HTTP/1.1" {
-            return Err(Error::InvalidRequest(format!(
-                "unsupported HTTP version: expected 'HTTP/1.1', got '{}'",
-                control_data_parts[2]
-            )));
+            return Err(Error::Rejected(
+                Status::HTTP_VERSION_NOT_SUPPORTED.0,
+                format!(
+                    "unsupported HTTP version: expected 'HTTP/1.1', got '{}'",
+                    control_data_parts[2]
+                ),
+            ));
         }
         Ok(Request {
             method: control_data_parts[0].try_into()?,
             target: control_data_parts[1].into(),
+            headers: raw_request[1..]
+                .iter()
+                .map(|line| parse_header(line))
+                .collect::<Result<_>>()?,
+            body: Vec::new(),
+            params: Vec::new(),
+            client: ClientInfo::default(),
+            deadline: Deadline::default(),
         })
     }
+
+    /// The value of the first header field with the given (case-insensitive) name
+    pub(super) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Extracts typed data from the request (see [`extract`])
+    pub(super) fn extract<T: extract::FromRequest>(&self) -> Result<T> {
+        T::from_request(self)
+    }
+
+    /// The values of all header fields with the given (case-insensitive) name, in the order received
+    pub(super) fn header_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers
+            .iter()
+            .filter(move |(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Helper function that splits a header field line into its name and value (Cf. RFC-9112 5)
+fn parse_header(line: &str) -> Result<(String, String)> {
+    let (name, value) = line
+        .split_once(':')
+        .ok_or_else(|| Error::InvalidRequest(format!("header field without ':': {line}")))?;
+    if name.is_empty() || name.ends_with(|c: char| c.is_ascii_whitespace()) {
+        return Err(Error::InvalidRequest(format!(
+            "invalid header field name: '{name}'"
+        )));
+    }
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn parse() {
+        let request = Request::parse(&raw(&[
+            "GET /admin/log?level=debug HTTP/1.1",
+            "Host: localhost:6502",
+            "Authorization:  Bearer secret ",
+        ]))
+        .unwrap();
+        assert!(matches!(request.method, RequestMethod::Get));
+        assert_eq!("/admin/log", request.target.path());
+        assert_eq!(Some("Bearer secret"), request.header("authorization"));
+        assert_eq!(Some("localhost:6502"), request.header("HOST"));
+        assert_eq!(None, request.header("Accept"));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Request::parse(&[]).is_err());
+        assert_eq!(
+            Some(Status::HTTP_VERSION_NOT_SUPPORTED),
+            Request::parse(&raw(&["GET / HTTP/1.0"]))
+                .err()
+                .map(|e| e.status())
+        );
+        assert_eq!(
+            Some(Status::NOT_IMPLEMENTED),
+            Request::parse(&raw(&["FETCH / HTTP/1.1"]))
+                .err()
+                .map(|e| e.status())
+        );
+        assert!(Request::parse(&raw(&["GET / HTTP/1.1", "Host localhost"])).is_err());
+        assert!(Request::parse(&raw(&["GET / HTTP/1.1", "Host : localhost"])).is_err());
+    }
 }
@@ -0,0 +1,147 @@
+//! HTTP Response (v1.1)
+
+use super::*;
+
+/// Response status code (RFC-9110 15)
+///
+/// Cf. <https://datatracker.ietf.org/doc/html/rfc9110#name-status-codes>
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) struct Status(pub(super) u16);
+
+impl Status {
+    pub(super) const OK: Status = Status(200);
+    pub(super) const BAD_REQUEST: Status = Status(400);
+    pub(super) const UNAUTHORIZED: Status = Status(401);
+    pub(super) const FORBIDDEN: Status = Status(403);
+    pub(super) const NOT_FOUND: Status = Status(404);
+    pub(super) const METHOD_NOT_ALLOWED: Status = Status(405);
+    pub(super) const REQUEST_TIMEOUT: Status = Status(408);
+    pub(super) const CONTENT_TOO_LARGE: Status = Status(413);
+    pub(super) const URI_TOO_LONG: Status = Status(414);
+    pub(super) const TOO_MANY_REQUESTS: Status = Status(429);
+    pub(super) const REQUEST_HEADER_FIELDS_TOO_LARGE: Status = Status(431);
+    pub(super) const INTERNAL_SERVER_ERROR: Status = Status(500);
+    pub(super) const NOT_IMPLEMENTED: Status = Status(501);
+    pub(super) const SERVICE_UNAVAILABLE: Status = Status(503);
+    pub(super) const HTTP_VERSION_NOT_SUPPORTED: Status = Status(505);
+
+    /// The reason phrase recommended by RFC-9110 for this status code
+    pub(super) fn reason(&self) -> &'static str {
+        match self.0 {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            413 => "Content Too Large",
+            414 => "URI Too Long",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            503 => "Service Unavailable",
+            505 => "HTTP Version Not Supported",
+            _ => "",
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.0, self.reason())
+    }
+}
+
+/// An HTTP response ready to be written to a client
+#[derive(Debug, Clone)]
+pub(super) struct Response {
+    pub(super) status: Status,
+    pub(super) headers: Vec<(String, String)>,
+    pub(super) body: Vec<u8>,
+}
+
+impl Response {
+    /// Creates a response with no headers and an empty body
+    pub(super) fn new(status: Status) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Creates a response whose body is the contents of a file
+    pub(super) fn file(status: Status, filename: impl AsRef<Path>) -> Result<Response> {
+        Ok(Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(fs::read(filename)?))
+    }
+
+    /// Creates a plain text response
+    pub(super) fn text(status: Status, text: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(text.into().into_bytes())
+    }
+
+    /// Adds a header field
+    pub(super) fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Replaces the body
+    pub(super) fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Writes the status line, header fields and body (Cf. RFC-9112 2.1)
+    pub(super) fn write_to(&self, writer: &mut impl Write) -> Result<()> {
+        self.write_head_to(writer)?;
+        writer.write_all(&self.body)?;
+        Ok(())
+    }
+
+    /// Writes the status line and header fields alone, as the response to a HEAD request; the
+    /// `Content-Length` is still that of the body (Cf. RFC-9110 9.3.2)
+    pub(super) fn write_head_to(&self, writer: &mut impl Write) -> Result<()> {
+        // TODO: Send date in response header (Cf. RFC-9110 6.6.1)
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+        writer.write_all(head.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write() {
+        let mut out = Vec::new();
+        Response::text(Status::NOT_FOUND, "gone")
+            .with_header("X-Test", "1")
+            .write_to(&mut out)
+            .unwrap();
+        assert_eq!(
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain; charset=utf-8\r\nX-Test: 1\r\n\
+             Content-Length: 4\r\n\r\ngone",
+            String::from_utf8(out).unwrap()
+        );
+        let mut head = Vec::new();
+        Response::text(Status::OK, "hello")
+            .write_head_to(&mut head)
+            .unwrap();
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 5\r\n\r\n",
+            String::from_utf8(head).unwrap()
+        );
+    }
+}
@@ -0,0 +1,465 @@
+//! Request Routing
+//!
+//! A router holds a table of routes, each a method and a path pattern with the [`Handler`] for the
+//! requests they match. A pattern's segments are literal, or `{name}` to match any one non-empty
+//! segment and capture it as a path parameter (see [`extract::PathParams`]), e.g.
+//! `/admin/pool/{worker}`. The first route matching both the method and the path handles a request.
+//! A path matched only by routes for other methods gets a 405 (Method Not Allowed) listing the
+//! allowed ones in `Allow`; a path no route matches gets a 404 (Not Found). The router answers
+//! errors, including those its handlers return, with its error pages (see [`error_page`]).
+//!
+//! Unless a route handles them, the router answers some methods itself (Cf. RFC-9110 9.3):
+//!
+//! * HEAD is handled by the GET route, and answered without the body (see
+//!   [`Response::write_head_to`])
+//! * OPTIONS gets the methods allowed on the path in `Allow`, or, as `OPTIONS *`, those allowed on
+//!   any path
+//! * TRACE, when enabled, gets the request echoed back as `message/http`, less the header fields
+//!   carrying credentials
+
+use super::*;
+
+use self::{
+    admin::Admin, error_page::ErrorPages, extract::Query, handler::Handler, middleware::Chain,
+};
+
+/// How long `GET /sleep` sleeps unless given `seconds`
+const DEFAULT_SLEEP_SECONDS: u64 = 5;
+
+/// The request target standing for the server rather than a path (Cf. RFC-9112 3.2.4)
+const ASTERISK: &str = "*";
+
+/// The header fields left out of the requests TRACE echoes back (Cf. RFC-9110 9.3.8)
+const SENSITIVE_FIELDS: [&str; 3] = ["Authorization", "Cookie", "Proxy-Authorization"];
+
+/// Maps requests to the handlers that produce their responses
+#[derive(Debug)]
+pub(super) struct Router {
+    /// Renders the responses to failed requests
+    errors: ErrorPages,
+    /// The routes, in the order they are tried
+    routes: Vec<Route>,
+    /// The middleware run around every request
+    chain: Chain,
+    /// Whether TRACE requests are echoed back
+    trace: bool,
+}
+
+/// A handler for the requests with a method whose path matches a pattern
+struct Route {
+    method: RequestMethod,
+    pattern: Pattern,
+    handler: Box<dyn Handler>,
+}
+
+/// A path pattern's segments
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern(Vec<Segment>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// Matches the same segment
+    Literal(String),
+    /// Matches any non-empty segment, captured as the named parameter
+    Param(String),
+}
+
+impl Router {
+    /// Creates a router with no routes, rendering error pages from the templates in `root`
+    pub(super) fn new(root: PathBuf) -> Router {
+        Router {
+            errors: ErrorPages::new(root),
+            routes: Vec::new(),
+            chain: Chain::default(),
+            trace: false,
+        }
+    }
+
+    /// Creates a router for the site's pages, optionally also serving the admin routes
+    pub(super) fn site(root: PathBuf, admin: Option<Admin>) -> Router {
+        let hello = root.join("hello.html");
+        let sleep_page = hello.clone();
+        let router = Router::new(root)
+            .get("/", move |_: &Request| Response::file(Status::OK, &hello))
+            .get("/sleep", move |request: &Request| {
+                sleep(request, &sleep_page)
+            });
+        match admin {
+            Some(admin) => admin.routes(router),
+            None => router,
+        }
+    }
+
+    /// Creates a router serving only the admin routes
+    pub(super) fn admin(root: PathBuf, admin: Admin) -> Router {
+        admin.routes(Router::new(root))
+    }
+
+    /// Adds a route for the requests with the given method whose path matches `pattern`
+    pub(super) fn with_route(
+        mut self,
+        method: RequestMethod,
+        pattern: &str,
+        handler: impl Handler + 'static,
+    ) -> Router {
+        self.routes.push(Route {
+            method,
+            pattern: Pattern::parse(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Adds a route for the GET requests whose path matches `pattern`
+    pub(super) fn get(self, pattern: &str, handler: impl Handler + 'static) -> Router {
+        self.with_route(RequestMethod::Get, pattern, handler)
+    }
+
+    /// Renders error pages from the templates in `dir`
+    pub(super) fn with_error_pages(mut self, dir: PathBuf) -> Router {
+        self.errors = ErrorPages::new(dir);
+        self
+    }
+
+    /// Echoes TRACE requests back when `enabled`
+    pub(super) fn with_trace(mut self, enabled: bool) -> Router {
+        self.trace = enabled;
+        self
+    }
+
+    /// Runs the middleware chain around every request
+    pub(super) fn with_chain(mut self, chain: Chain) -> Router {
+        self.chain = chain;
+        self
+    }
+
+    /// Responds to a request, running the middleware chain around its handler. The parameters the
+    /// route's pattern captures are stored in the request.
+    pub(super) fn route(&self, request: &mut Request) -> Response {
+        let server_wide = request.target.path() == ASTERISK;
+        let matching: Vec<_> = self
+            .routes
+            .iter()
+            .filter_map(|route| match server_wide {
+                true => Some((route, Vec::new())),
+                false => Some((route, route.pattern.captures(request.target.path())?)),
+            })
+            .collect();
+        let route = match (server_wide, request.method) {
+            (true, _) => None,
+            (false, RequestMethod::Head) => {
+                find(&matching, RequestMethod::Head).or_else(|| find(&matching, RequestMethod::Get))
+            }
+            (false, method) => find(&matching, method),
+        };
+        if let Some((_, params)) = route {
+            request.params = params.clone();
+        }
+        let allowed = self.allowed(matching.iter().map(|(route, _)| route.method));
+        self.chain.run(request, |request| {
+            let result = match route {
+                Some((route, _)) => route.handler.call(request),
+                None if server_wide && request.method != RequestMethod::Options => Err(
+                    Error::InvalidRequest(format!("{} cannot target '*'", request.method)),
+                ),
+                None if request.method == RequestMethod::Trace && self.trace => Ok(trace(request)),
+                None if request.method == RequestMethod::Options
+                    && (server_wide || !matching.is_empty()) =>
+                {
+                    Ok(Response::new(Status::OK).with_header("Allow", allow(&allowed)))
+                }
+                None if !matching.is_empty() => {
+                    let error = Error::Rejected(
+                        Status::METHOD_NOT_ALLOWED.0,
+                        format!("{} is not allowed here", request.method),
+                    );
+                    return self
+                        .error_response(&error, Some(request))
+                        .with_header("Allow", allow(&allowed));
+                }
+                None => Err(Error::Rejected(
+                    Status::NOT_FOUND.0,
+                    format!("nothing found at {}", request.target.path()),
+                )),
+            };
+            result.unwrap_or_else(|error| self.error_response(&error, Some(request)))
+        })
+    }
+
+    // The methods allowed on a path matched by routes for `methods`: theirs, HEAD wherever GET is,
+    // and those the router answers itself
+    fn allowed(&self, methods: impl Iterator<Item = RequestMethod>) -> Vec<RequestMethod> {
+        let mut allowed = Vec::new();
+        let mut add = |method| {
+            if !allowed.contains(&method) {
+                allowed.push(method);
+            }
+        };
+        for method in methods {
+            add(method);
+            if method == RequestMethod::Get {
+                add(RequestMethod::Head);
+            }
+        }
+        add(RequestMethod::Options);
+        if self.trace {
+            add(RequestMethod::Trace);
+        }
+        allowed
+    }
+
+    /// The response to a request that failed with `error`; `request` is `None` when the request
+    /// could not be read
+    pub(super) fn error_response(&self, error: &Error, request: Option<&Request>) -> Response {
+        self.errors.render(error, request)
+    }
+}
+
+impl fmt::Debug for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Route")
+            .field("method", &self.method)
+            .field("pattern", &self.pattern)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Pattern {
+    // Parses a pattern such as `/admin/pool/{worker}`; patterns are written in the code, so an
+    // invalid one is a bug
+    fn parse(pattern: &str) -> Pattern {
+        let segments = pattern
+            .strip_prefix('/')
+            .unwrap_or_else(|| panic!("route pattern '{pattern}' does not start with '/'"))
+            .split('/')
+            .map(|segment| {
+                match segment
+                    .strip_prefix('{')
+                    .and_then(|name| name.strip_suffix('}'))
+                {
+                    Some(name) => Segment::Param(name.to_string()),
+                    None => Segment::Literal(segment.to_string()),
+                }
+            })
+            .collect();
+        Pattern(segments)
+    }
+
+    // The parameters captured from `path`, if it matches
+    fn captures(&self, path: &str) -> Option<Vec<(String, String)>> {
+        let segments: Vec<_> = path.strip_prefix('/')?.split('/').collect();
+        if segments.len() != self.0.len() {
+            return None;
+        }
+        let mut params = Vec::new();
+        for (expected, segment) in self.0.iter().zip(segments) {
+            match expected {
+                Segment::Literal(literal) if literal == segment => {}
+                Segment::Param(name) if !segment.is_empty() => {
+                    params.push((name.clone(), segment.to_string()))
+                }
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+}
+
+// Handles `GET /sleep[?seconds=<n>]`: sleeps within the request's deadline, then serves `page`
+fn sleep(request: &Request, page: &Path) -> Result<Response> {
+    let seconds = request
+        .extract::<Query>()?
+        .0
+        .optional("seconds")?
+        .unwrap_or(DEFAULT_SLEEP_SECONDS);
+    match request.deadline.sleep(Duration::from_secs(seconds)) {
+        true => Response::file(Status::OK, page),
+        false => Ok(
+            Response::text(Status::SERVICE_UNAVAILABLE, "request timed out\n")
+                .with_header("Connection", "close"),
+        ),
+    }
+}
+
+// Handles `TRACE`: echoes the request's head back, less its credentials
+fn trace(request: &Request) -> Response {
+    let mut message = format!("{} {} HTTP/1.1\r\n", request.method, request.target);
+    for (name, value) in &request.headers {
+        if !SENSITIVE_FIELDS
+            .iter()
+            .any(|field| field.eq_ignore_ascii_case(name))
+        {
+            message.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+    message.push_str("\r\n");
+    Response::new(Status::OK)
+        .with_header("Content-Type", "message/http")
+        .with_body(message)
+}
+
+// Helper function that returns the first of the matching routes for `method`
+fn find<'a>(
+    matching: &'a [(&'a Route, Vec<(String, String)>)],
+    method: RequestMethod,
+) -> Option<&'a (&'a Route, Vec<(String, String)>)> {
+    matching.iter().find(|(route, _)| route.method == method)
+}
+
+// Helper function that lists methods as the value of an `Allow` header field
+fn allow(methods: &[RequestMethod]) -> String {
+    let methods: Vec<_> = methods.iter().map(|method| method.to_string()).collect();
+    methods.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use extract::PathParams;
+
+    fn request(line: &str) -> Request {
+        Request::parse(&[line.to_string()]).unwrap()
+    }
+
+    fn body(response: Response) -> String {
+        String::from_utf8(response.body).unwrap()
+    }
+
+    #[test]
+    fn patterns() {
+        let pattern = Pattern::parse("/pool/{worker}/jobs");
+        assert_eq!(
+            Some(vec![("worker".to_string(), "3".to_string())]),
+            pattern.captures("/pool/3/jobs")
+        );
+        assert_eq!(None, pattern.captures("/pool//jobs"));
+        assert_eq!(None, pattern.captures("/pool/3"));
+        assert_eq!(None, pattern.captures("/pool/3/jobs/"));
+        assert_eq!(Some(Vec::new()), Pattern::parse("/").captures("/"));
+        assert_eq!(None, Pattern::parse("/").captures("/x"));
+    }
+
+    #[test]
+    fn routes() {
+        let router = Router::new(PathBuf::from("."))
+            .get("/items/{id}", |request: &Request| -> Result<String> {
+                let id: u32 = request.extract::<PathParams>()?.0.get("id")?;
+                Ok(format!("item {id}\n"))
+            })
+            .with_route(RequestMethod::Delete, "/items/{id}", |_: &Request| {
+                (Status(204), "")
+            });
+        let found = router.route(&mut request("GET /items/7 HTTP/1.1"));
+        assert_eq!(
+            (Status::OK, "item 7\n".to_string()),
+            (found.status, body(found))
+        );
+        let deleted = router.route(&mut request("DELETE /items/7 HTTP/1.1"));
+        assert_eq!(Status(204), deleted.status);
+        let mut invalid = Request::parse(&[
+            "GET /items/x HTTP/1.1".to_string(),
+            "Accept: application/json".to_string(),
+        ])
+        .unwrap();
+        let invalid = router.route(&mut invalid);
+        assert_eq!(Status::BAD_REQUEST, invalid.status);
+        assert!(body(invalid).contains(r#""detail":"invalid path parameter 'id': 'x'""#));
+        let other = router.route(&mut request("PUT /items/7 HTTP/1.1"));
+        assert_eq!(Status::METHOD_NOT_ALLOWED, other.status);
+        assert_eq!(
+            Some(&(
+                "Allow".to_string(),
+                "GET, HEAD, DELETE, OPTIONS".to_string()
+            )),
+            other.headers.last()
+        );
+        let missing = router.route(&mut request("GET /items HTTP/1.1"));
+        assert_eq!(Status::NOT_FOUND, missing.status);
+    }
+
+    #[test]
+    fn answered_methods() {
+        let router = Router::new(PathBuf::from("."))
+            .get("/items", |_: &Request| "items\n")
+            .with_route(RequestMethod::Post, "/items", |_: &Request| {
+                (Status(201), "")
+            })
+            .with_route(RequestMethod::Delete, "/items/{id}", |_: &Request| {
+                (Status(204), "")
+            });
+        let head = router.route(&mut request("HEAD /items HTTP/1.1"));
+        assert_eq!(
+            (Status::OK, "items\n".to_string()),
+            (head.status, body(head))
+        );
+        let allow = |response: Response| {
+            let allow = response.headers.iter().find(|(name, _)| name == "Allow");
+            (response.status, allow.map(|(_, value)| value.clone()))
+        };
+        assert_eq!(
+            (Status::OK, Some("GET, HEAD, POST, OPTIONS".to_string())),
+            allow(router.route(&mut request("OPTIONS /items HTTP/1.1")))
+        );
+        assert_eq!(
+            (Status::OK, Some("DELETE, OPTIONS".to_string())),
+            allow(router.route(&mut request("OPTIONS /items/7 HTTP/1.1")))
+        );
+        assert_eq!(
+            (
+                Status::OK,
+                Some("GET, HEAD, POST, DELETE, OPTIONS".to_string())
+            ),
+            allow(router.route(&mut request("OPTIONS * HTTP/1.1")))
+        );
+        assert_eq!(
+            Status::BAD_REQUEST,
+            router.route(&mut request("GET * HTTP/1.1")).status
+        );
+        assert_eq!(
+            Status::NOT_FOUND,
+            router.route(&mut request("OPTIONS /other HTTP/1.1")).status
+        );
+        // TRACE is disabled by default
+        assert_eq!(
+            (
+                Status::METHOD_NOT_ALLOWED,
+                Some("GET, HEAD, POST, OPTIONS".to_string())
+            ),
+            allow(router.route(&mut request("TRACE /items HTTP/1.1")))
+        );
+        let router = router.with_trace(true);
+        let mut traced = Request::parse(&[
+            "TRACE /items?x=1 HTTP/1.1".to_string(),
+            "Host: test".to_string(),
+            "Cookie: session=secret".to_string(),
+            "Max-Forwards: 0".to_string(),
+        ])
+        .unwrap();
+        let traced = router.route(&mut traced);
+        assert_eq!(Status::OK, traced.status);
+        assert_eq!(
+            Some(&("Content-Type".to_string(), "message/http".to_string())),
+            traced.headers.first()
+        );
+        assert_eq!(
+            "TRACE /items?x=1 HTTP/1.1\r\nHost: test\r\nMax-Forwards: 0\r\n\r\n",
+            body(traced)
+        );
+        assert_eq!(
+            (
+                Status::OK,
+                Some("GET, HEAD, POST, DELETE, OPTIONS, TRACE".to_string())
+            ),
+            allow(router.route(&mut request("OPTIONS * HTTP/1.1")))
+        );
+    }
+
+    #[test]
+    fn sleep_seconds() {
+        let router = Router::site(PathBuf::from("."), None);
+        let invalid = router.route(&mut request("GET /sleep?seconds=soon HTTP/1.1"));
+        assert_eq!(Status::BAD_REQUEST, invalid.status);
+        let slept = router.route(&mut request("GET /sleep?seconds=0 HTTP/1.1"));
+        assert_eq!(Status::OK, slept.status);
+    }
+}
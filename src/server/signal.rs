@@ -0,0 +1,91 @@
+//! POSIX Signal Handling
+//!
+//! `SIGINT` and `SIGTERM` request a graceful shutdown and `SIGUSR2` requests a zero-downtime restart
+//! (see [`super::handoff`]). The handlers only set flags, which the accept loop checks, and wake the
+//! loop through its self-pipe (see [`super::wakeup`]); a second shutdown signal while shutting down
+//! exits immediately.
+
+use std::{
+    os::raw::{c_int, c_void},
+    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+};
+
+use wakeup::Wakeup;
+
+use super::*;
+
+const SIGINT: c_int = 2;
+const SIGTERM: c_int = 15;
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+const SIGUSR2: c_int = 31;
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "freebsd")))]
+const SIGUSR2: c_int = 12;
+
+/// Value returned by `signal` on failure
+const SIG_ERR: usize = usize::MAX;
+
+/// Exit status used when a second signal forces an immediate exit (128 + SIGINT)
+const FORCED_EXIT_STATUS: c_int = 130;
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+static RESTART_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// The descriptor the handlers write to to wake the accept loop; -1 until handlers are installed
+static WAKEUP_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" {
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    fn _exit(status: c_int) -> !;
+    fn write(fd: c_int, buf: *const c_void, count: usize) -> isize;
+}
+
+// Helper function that wakes the accept loop from a signal handler
+fn wake_accept_loop() {
+    let fd = WAKEUP_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let byte = 1u8;
+        // SAFETY: `byte` outlives the call; a full or closed self-pipe is harmless to write to
+        unsafe { write(fd, (&byte as *const u8).cast(), 1) };
+    }
+}
+
+// Signal handler; only async-signal-safe operations are allowed here
+extern "C" fn on_shutdown_signal(_signum: c_int) {
+    if SHUTDOWN_REQUESTED.swap(true, Ordering::SeqCst) {
+        unsafe { _exit(FORCED_EXIT_STATUS) };
+    }
+    wake_accept_loop();
+}
+
+// Signal handler; only async-signal-safe operations are allowed here
+extern "C" fn on_restart_signal(_signum: c_int) {
+    RESTART_REQUESTED.store(true, Ordering::SeqCst);
+    wake_accept_loop();
+}
+
+/// Installs the `SIGINT`, `SIGTERM` and `SIGUSR2` handlers, which wake the accept loop through
+/// `wakeup`
+pub(super) fn install_handlers(wakeup: &Wakeup) -> Result<()> {
+    WAKEUP_FD.store(wakeup.sender_fd(), Ordering::SeqCst);
+    let handlers: [(c_int, extern "C" fn(c_int)); 3] = [
+        (SIGINT, on_shutdown_signal),
+        (SIGTERM, on_shutdown_signal),
+        (SIGUSR2, on_restart_signal),
+    ];
+    for (signum, handler) in handlers {
+        if unsafe { signal(signum, handler) } == SIG_ERR {
+            return Err(Error::Io(std::io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a shutdown signal has been received
+pub(super) fn shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Whether a restart signal has been received since the last call
+pub(super) fn take_restart_request() -> bool {
+    RESTART_REQUESTED.swap(false, Ordering::SeqCst)
+}
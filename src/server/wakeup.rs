@@ -0,0 +1,127 @@
+//! Waking the Accept Loop
+//!
+//! The accept loop blocks until a listener has a connection to accept, so something else must wake
+//! it when it should stop: a [`Wakeup`] is a connected pair of sockets (a self-pipe) whose receiving
+//! end the loop waits on alongside the listeners. Signal handlers wake it by writing a byte to the
+//! sending end (see [`super::signal`]), which is async-signal-safe.
+//!
+//! Cf. poll(2)
+
+use std::os::{
+    fd::{AsRawFd, RawFd},
+    raw::{c_int, c_short},
+    unix::net::UnixStream,
+};
+
+use super::*;
+
+mod sys {
+    use super::{c_int, c_short};
+    pub(super) const POLLIN: c_short = 0x001;
+    pub(super) const EINTR: i32 = 4;
+
+    /// `struct pollfd`
+    #[repr(C)]
+    #[derive(Debug, Copy, Clone)]
+    pub(super) struct PollFd {
+        pub(super) fd: c_int,
+        pub(super) events: c_short,
+        pub(super) revents: c_short,
+    }
+
+    /// `nfds_t`
+    #[cfg(target_os = "linux")]
+    pub(super) type NFds = std::os::raw::c_ulong;
+    #[cfg(not(target_os = "linux"))]
+    pub(super) type NFds = std::os::raw::c_uint;
+}
+
+mod ffi {
+    use super::{c_int, sys::NFds, sys::PollFd};
+
+    extern "C" {
+        pub(super) fn poll(fds: *mut PollFd, nfds: NFds, timeout: c_int) -> c_int;
+    }
+}
+
+/// A self-pipe that wakes a loop waiting on its receiving end
+#[derive(Debug)]
+pub(super) struct Wakeup {
+    receiver: UnixStream,
+    sender: UnixStream,
+}
+
+impl Wakeup {
+    pub(super) fn new() -> io::Result<Wakeup> {
+        let (receiver, sender) = UnixStream::pair()?;
+        receiver.set_nonblocking(true)?;
+        sender.set_nonblocking(true)?;
+        Ok(Wakeup { receiver, sender })
+    }
+
+    /// The descriptor written to wake the loop, for a signal handler
+    pub(super) fn sender_fd(&self) -> RawFd {
+        self.sender.as_raw_fd()
+    }
+
+    /// The descriptor that becomes readable when the loop is woken
+    pub(super) fn receiver_fd(&self) -> RawFd {
+        self.receiver.as_raw_fd()
+    }
+
+    /// A handle on the sending end, for another thread to wake the loop by writing to
+    #[cfg(test)]
+    pub(super) fn sender(&self) -> io::Result<UnixStream> {
+        self.sender.try_clone()
+    }
+
+    /// Discards the bytes that woke the loop, so that it can wait again
+    pub(super) fn clear(&self) {
+        let mut buffer = [0u8; 64];
+        while matches!((&self.receiver).read(&mut buffer), Ok(n) if n > 0) {}
+    }
+}
+
+/// Blocks until at least one of `fds` is readable, or a signal interrupts the wait, returning which
+/// are readable
+pub(super) fn wait_readable(fds: &[RawFd]) -> io::Result<Vec<bool>> {
+    let mut polled: Vec<_> = fds
+        .iter()
+        .map(|&fd| sys::PollFd {
+            fd,
+            events: sys::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    // SAFETY: `polled` is a valid array of `polled.len()` descriptors for the duration of the call
+    let ready = unsafe { ffi::poll(polled.as_mut_ptr(), polled.len() as sys::NFds, -1) };
+    if ready < 0 {
+        let e = io::Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(sys::EINTR) => Ok(vec![false; fds.len()]),
+            _ => Err(e),
+        };
+    }
+    Ok(polled.iter().map(|fd| fd.revents != 0).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wakes_waiter() {
+        let wakeup = Wakeup::new().unwrap();
+        let receiver = wakeup.receiver_fd();
+        let waiter = thread::spawn(move || wait_readable(&[receiver]).unwrap());
+        thread::sleep(Duration::from_millis(20));
+        assert!(!waiter.is_finished());
+        wakeup.sender().unwrap().write_all(&[1]).unwrap();
+        assert_eq!(vec![true], waiter.join().unwrap());
+        wakeup.clear();
+        assert_eq!(
+            io::ErrorKind::WouldBlock,
+            (&wakeup.receiver).read(&mut [0]).unwrap_err().kind()
+        );
+    }
+}
@@ -1,37 +1,123 @@
 //! Worker Management
 
+use std::panic::{self, AssertUnwindSafe};
+
 use super::*;
 
+use self::{
+    deadline::Deadline,
+    pool::{lock, panic_message, Shared},
+};
+
 #[derive(Debug)]
 pub(super) struct Worker {
     pub(super) id: usize,
     pub(super) thread: Option<thread::JoinHandle<()>>,
+    pub(super) activity: Arc<Mutex<Activity>>,
+}
+
+/// What a worker has been doing
+#[derive(Debug, Default)]
+pub(super) struct Activity {
+    /// When the job the worker is running started, if it is running one
+    pub(super) job_started: Option<Instant>,
+    /// The running job's deadline
+    pub(super) deadline: Option<Deadline>,
+    /// Whether the running job has been reported as overdue
+    pub(super) overdue: bool,
+    /// Jobs finished, including those that panicked
+    pub(super) completed: usize,
+    /// Time spent running jobs
+    pub(super) job_time: Duration,
+    /// The longest time a job has taken
+    pub(super) longest_job: Duration,
+}
+
+impl Activity {
+    // Records that a job with the given deadline has started
+    fn start(&mut self, started: Instant, deadline: &Deadline) {
+        self.job_started = Some(started);
+        self.deadline = Some(deadline.clone());
+        self.overdue = false;
+    }
+
+    // Records that a job started at `started` has finished
+    fn finish(&mut self, started: Instant) -> Duration {
+        let elapsed = started.elapsed();
+        self.job_started = None;
+        self.deadline = None;
+        self.completed += 1;
+        self.job_time += elapsed;
+        self.longest_job = self.longest_job.max(elapsed);
+        elapsed
+    }
+}
+
+// Replaces its worker should the worker's thread die from a panic outside of a job
+struct Sentinel {
+    id: usize,
+    shared: Arc<Shared>,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.shared.replace_worker(self.id);
+        }
+    }
 }
 
 impl Worker {
-    pub(super) fn new(
-        id: usize,
-        receiver: Arc<Mutex<mpsc::Receiver<pool::Job>>>,
-    ) -> Result<Worker> {
-        let thread = thread::spawn(move || loop {
-            let message = receiver
-                .lock()
-                .expect("unable to lock spawned thread")
-                .recv();
-            match message {
-                Ok(job) => {
-                    debug!("Worker {id} got a job; executing.");
-                    job();
-                }
-                Err(_) => {
-                    debug!("Worker {id} shutting down.");
-                    break;
+    /// Starts a worker thread in a place already counted in `shared.workers`. The worker gives up
+    /// its place when it retires after being idle, or when the pool shuts down.
+    pub(super) fn new(id: usize, shared: Arc<Shared>) -> Result<Worker> {
+        let activity = Arc::new(Mutex::new(Activity::default()));
+        let recorder = Arc::clone(&activity);
+        let thread = thread::Builder::new()
+            .name(format!("worker-{id}"))
+            .spawn(move || {
+                let sentinel = Sentinel { id, shared };
+                let shared = &sentinel.shared;
+                loop {
+                    let message = shared.queue.pop_timeout(shared.size.idle_timeout);
+                    match message {
+                        Ok(job) => {
+                            shared.queued.fetch_sub(1, Ordering::SeqCst);
+                            debug!("Worker {id} got a job; executing.");
+                            shared.busy.fetch_add(1, Ordering::SeqCst);
+                            let started = Instant::now();
+                            let deadline = Deadline::after(shared.size.job_timeout);
+                            lock(&recorder).start(started, &deadline);
+                            let outcome = panic::catch_unwind(AssertUnwindSafe(|| job(&deadline)));
+                            if let Err(payload) = outcome {
+                                shared.panics.fetch_add(1, Ordering::SeqCst);
+                                error!(
+                                    "Worker {id}: job panicked: {}",
+                                    panic_message(payload.as_ref())
+                                );
+                            }
+                            let elapsed = lock(&recorder).finish(started);
+                            shared.busy.fetch_sub(1, Ordering::SeqCst);
+                            shared.record_job(elapsed);
+                        }
+                        Err(mpsc::RecvTimeoutError::Timeout) => {
+                            if shared.retire_worker() {
+                                debug!("Worker {id} idle; exiting.");
+                                break;
+                            }
+                        }
+                        Err(mpsc::RecvTimeoutError::Disconnected) => {
+                            debug!("Worker {id} shutting down.");
+                            shared.workers.fetch_sub(1, Ordering::SeqCst);
+                            break;
+                        }
+                    }
                 }
-            }
-        });
+            })?;
         Ok(Worker {
             id,
             thread: Some(thread),
+            activity,
         })
     }
 }
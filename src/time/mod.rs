@@ -163,7 +163,7 @@ where
     }
 }
 
-// Determine the year given the number of days since the Unix epoch
+// Determine the year and (one-based) day of the year given the number of days since the Unix epoch
 //
 // This calculation is done via brute force by iterating through the years
 fn year<T>(epoch_days: T) -> (u16, u16)
@@ -175,18 +175,18 @@ where
     let mut remaining_days = epoch_days;
     loop {
         match is_leap_year(year) {
-            false if remaining_days <= 365 => break,
+            false if remaining_days < 365 => break,
             false => {
                 remaining_days -= 365;
             }
-            true if remaining_days <= 366 => break,
+            true if remaining_days < 366 => break,
             true => {
                 remaining_days -= 366;
             }
         };
         year += 1;
     }
-    (year, remaining_days as u16)
+    (year, remaining_days as u16 + 1)
 }
 
 // Determine the month given the day of the year
@@ -214,14 +214,17 @@ impl DateTime {
     pub fn now() -> DateTime {
         let now = SystemTime::now();
         let duration = unsafe { now.duration_since(UNIX_EPOCH).unwrap_unchecked() };
-        let epoch_seconds = duration.as_secs();
+        Self::from_epoch(duration.as_secs(), duration.subsec_nanos())
+    }
+    /// Builds a date and time from seconds (and sub-second nanoseconds) since the Unix epoch
+    pub fn from_epoch(epoch_seconds: u64, epoch_sub_nanoseconds: u32) -> DateTime {
         let epoch_days = epoch_seconds / 86_400;
         let (year, day_of_year) = year(epoch_days);
         let (month, day) = month(year, day_of_year);
 
         DateTime {
             epoch_seconds,
-            epoch_sub_nanoseconds: duration.subsec_nanos(),
+            epoch_sub_nanoseconds,
             epoch_days,
             year,
             day_of_year,
@@ -249,6 +252,34 @@ impl DateTime {
     pub fn is_leap_year(&self) -> bool {
         is_leap_year(self.year)
     }
+    /// The hour of the day (UTC)
+    pub fn hour(&self) -> u8 {
+        ((self.epoch_seconds % 86_400) / 3_600) as u8
+    }
+    /// The minute of the hour
+    pub fn minute(&self) -> u8 {
+        ((self.epoch_seconds % 3_600) / 60) as u8
+    }
+    /// The second of the minute
+    pub fn second(&self) -> u8 {
+        (self.epoch_seconds % 60) as u8
+    }
+    /// Formats the date and time as an RFC-3339 UTC timestamp with millisecond precision
+    ///
+    /// Cf. <https://www.rfc-editor.org/rfc/rfc3339#section-5.6>
+    pub fn to_rfc3339(self) -> String {
+        let month_num: u8 = self.month.into();
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year,
+            month_num,
+            self.day,
+            self.hour(),
+            self.minute(),
+            self.second(),
+            self.epoch_sub_nanoseconds / 1_000_000
+        )
+    }
 }
 
 #[cfg(test)]
@@ -267,4 +298,36 @@ mod tests {
         // leap year - div 4 true, div 100 false
         assert!(is_leap_year(2020u16));
     }
+
+    // Make sure days since the epoch land on the right calendar day
+    #[test]
+    fn calendar_day() {
+        let epoch = DateTime::from_epoch(0, 0);
+        assert_eq!(
+            (1970, 1, 1),
+            (epoch.year(), epoch.month() as u8, epoch.day())
+        );
+        let new_years_eve = DateTime::from_epoch(364 * 86_400, 0);
+        assert_eq!(
+            (1970, 12, 31),
+            (
+                new_years_eve.year(),
+                new_years_eve.month() as u8,
+                new_years_eve.day()
+            )
+        );
+        let leap_day = DateTime::from_epoch(951_782_400, 0);
+        assert_eq!(
+            (2000, 2, 29),
+            (leap_day.year(), leap_day.month() as u8, leap_day.day())
+        );
+    }
+
+    #[test]
+    fn rfc3339() {
+        assert_eq!(
+            "2001-09-09T01:46:40.123Z",
+            DateTime::from_epoch(1_000_000_000, 123_456_789).to_rfc3339()
+        );
+    }
 }
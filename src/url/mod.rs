@@ -12,6 +12,31 @@ pub struct Url {
     raw_path: String,
 }
 
+impl Url {
+    /// The path component (everything before any '?' or '#')
+    pub fn path(&self) -> &str {
+        let end = self
+            .raw_path
+            .find(['?', '#'])
+            .unwrap_or(self.raw_path.len());
+        &self.raw_path[..end]
+    }
+
+    /// The raw query component (everything between '?' and any '#'), if present
+    pub fn query(&self) -> Option<&str> {
+        let start = self.raw_path.find('?')? + 1;
+        let end = self.raw_path[start..]
+            .find('#')
+            .map_or(self.raw_path.len(), |end| start + end);
+        Some(&self.raw_path[start..end])
+    }
+
+    /// The decoded `name=value` pairs of an `application/x-www-form-urlencoded` query
+    pub fn query_pairs(&self) -> Result<Vec<(String, String)>> {
+        self.query().map_or(Ok(Vec::new()), form_decode)
+    }
+}
+
 impl fmt::Display for Url {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", &self.raw_path)
@@ -77,6 +102,45 @@ fn pct_decode<S: AsRef<str>>(pct_encoded: S) -> Result<char> {
         .ok_or("pct-decode internal error")?)
 }
 
+// Helper function that decodes a string containing RFC-3986 Percent-Encodings. When `plus_as_space`
+// is set, '+' is decoded as a space as in `application/x-www-form-urlencoded` content.
+fn pct_decode_str(encoded: &str, plus_as_space: bool) -> Result<String> {
+    let mut bytes = Vec::<u8>::with_capacity(encoded.len());
+    let mut chars = encoded.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => {
+                let high = hex_char_to_byte(chars.next().ok_or("invalid percentage encoding")?)?;
+                let low = hex_char_to_byte(chars.next().ok_or("invalid percentage encoding")?)?;
+                bytes.push((high << 4) + low);
+            }
+            '+' if plus_as_space => bytes.push(b' '),
+            c => {
+                let mut buffer = [0u8; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            }
+        }
+    }
+    Ok(String::from_utf8(bytes).map_err(|_| "invalid UTF+8 unicode".to_string())?)
+}
+
+/// Decodes the RFC-3986 Percent-Encodings in a path segment
+pub fn percent_decode(encoded: &str) -> Result<String> {
+    pct_decode_str(encoded, false)
+}
+
+/// Decodes `application/x-www-form-urlencoded` content into `name=value` pairs
+pub fn form_decode(encoded: &str) -> Result<Vec<(String, String)>> {
+    encoded
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((pct_decode_str(name, true)?, pct_decode_str(value, true)?))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -126,5 +190,39 @@ mod tests {
         assert_eq!('\u{00E9}', pct_decode(pct_encode('\u{00E9}')).unwrap()); // 2-byte UTF-8 glyph 'é'
         assert_eq!('\u{20AC}', pct_decode(pct_encode('\u{20AC}')).unwrap()); // 3-byte UTF-8 glyph '€'
         assert_eq!('\u{10348}', pct_decode(pct_encode('\u{10348}')).unwrap()); // 4-byte UTF-8 glyph '𐍈'
+        assert_eq!("a+b c", percent_decode("a+b%20c").unwrap());
+        assert!(percent_decode("%C3").is_err());
+    }
+
+    #[test]
+    fn path_and_query() {
+        let url = Url::from("/admin/log?level=debug&target=ptodd%3A%3Aserver#top");
+        assert_eq!("/admin/log", url.path());
+        assert_eq!(Some("level=debug&target=ptodd%3A%3Aserver"), url.query());
+        assert_eq!(
+            vec![
+                ("level".to_string(), "debug".to_string()),
+                ("target".to_string(), "ptodd::server".to_string())
+            ],
+            url.query_pairs().unwrap()
+        );
+        let url = Url::from("/");
+        assert_eq!("/", url.path());
+        assert_eq!(None, url.query());
+        assert!(url.query_pairs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn form() {
+        assert_eq!(
+            vec![
+                ("a b".to_string(), "c+d".to_string()),
+                ("flag".to_string(), String::new()),
+                ("é".to_string(), "€".to_string())
+            ],
+            form_decode("a+b=c%2Bd&&flag&%C3%A9=%E2%82%AC").unwrap()
+        );
+        assert!(form_decode("bad=%zz").is_err());
+        assert!(form_decode("bad=%C3").is_err());
     }
 }